@@ -529,6 +529,24 @@ components: {
 						}
 					}
 
+					alpn_protocols: {
+						common:      false
+						description: "The protocols advertised through ALPN (application-layer protocol negotiation), in order of preference. Clients that offer none of them are connected without a protocol, unless `alpn_strict` is set."
+						required:    false
+						type: array: {
+							default: null
+							items: type: string: {
+								examples: ["fluent"]
+								syntax: "literal"
+							}
+						}
+					}
+					alpn_strict: {
+						common:      false
+						description: "Whether connections from clients that don't negotiate one of the `alpn_protocols`, including clients that don't use ALPN at all, are rejected."
+						required:    false
+						type: bool: default: false
+					}
 					ca_file: {
 						common:      false
 						description: "Absolute path to an additional CA certificate file, in DER or PEM format (X.509), or an in-line CA certificate in PEM format."
@@ -603,6 +621,18 @@ components: {
 						}
 					}
 
+					alpn_protocols: {
+						common:      false
+						description: "The protocols offered to the remote through ALPN (application-layer protocol negotiation), in order of preference."
+						required:    false
+						type: array: {
+							default: null
+							items: type: string: {
+								examples: ["h2", "http/1.1"]
+								syntax: "literal"
+							}
+						}
+					}
 					ca_file: {
 						common:      false
 						description: "Absolute path to an additional CA certificate file, in DER or PEM format (X.509), or an inline CA certificate in PEM format."
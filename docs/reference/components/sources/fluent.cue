@@ -61,14 +61,768 @@ components: sources: fluent: {
 
 	configuration: {
 		address: {
-			description: "The address to listen for TCP connections on."
+			description: "The address to listen for TCP connections on. A list of addresses can be given instead, such as an IPv4 and an IPv6 address, to listen on all of them with the same settings. Sockets passed by systemd socket activation can be used with `systemd#N` for the Nth socket, or `systemd:NAME` for the socket named NAME with `FileDescriptorName=`."
 			required:    true
 			warnings: []
 			type: string: {
-				examples: ["0.0.0.0:\(_port)"]
+				examples: ["0.0.0.0:\(_port)", "systemd:fluent"]
 				syntax: "literal"
 			}
 		}
+		allowed_peers: {
+			common:      false
+			description: "A list of IPv4 and IPv6 CIDR ranges clients may connect from. Connections from any other address are closed right away. When `proxy_protocol` is enabled, the client address from the PROXY header is checked. By default all clients are accepted."
+			required:    false
+			warnings: []
+			type: array: items: type: string: {
+				examples: ["10.0.0.0/8", "2001:db8::/32"]
+				syntax: "literal"
+			}
+		}
+		array_record_key: {
+			common:      false
+			description: "The field that array records are stored under when `lenient_array_records` is enabled. Defaults to the global `log_schema.message_key`."
+			required:    false
+			warnings: []
+			type: string: {
+				default: "message"
+				examples: ["payload"]
+				syntax: "literal"
+			}
+		}
+		batch_mode: {
+			common:      false
+			description: "Whether the entries of a forward mode message, which fluentd and fluent-bit use to send records in batches, become one event each or a single event per message."
+			required:    false
+			warnings: []
+			type: string: {
+				default: "per_entry"
+				enum: {
+					per_entry:   "Emit one event per entry, with the message's tag and the entry's timestamp and record fields."
+					per_message: "Emit one event per message, with the message's tag, the first entry's timestamp and a `records` array holding each entry's timestamp and record fields. `max_pending_frames` doesn't apply in this mode, as all entries of a message are decoded at once."
+				}
+				syntax: "literal"
+			}
+		}
+		bind_to_device: {
+			common:      false
+			description: "The name of the network interface to bind the listening socket to, such as `eth1`, with `SO_BINDTODEVICE`, so that only connections arriving through that interface are accepted, which on multi-homed hosts may not follow from the address alone. This is only supported on Linux, where it may require the `CAP_NET_RAW` capability; elsewhere the source fails to build when set. It has no effect when listening on a systemd socket."
+			required:    false
+			warnings: []
+			type: string: {
+				examples: ["eth1"]
+				syntax: "literal"
+			}
+		}
+		byte_size_key: {
+			common:      false
+			description: "The key name added to each event for the number of msgpack bytes it was decoded from. For messages in forward or packed forward mode this is the size of the entry, after decompression, rather than that of the whole message. By default the size isn't added."
+			required:    false
+			warnings: []
+			type: string: {
+				examples: ["byte_size"]
+				syntax: "literal"
+			}
+		}
+		chunk_key: {
+			category:    "Context"
+			common:      false
+			description: "The key name added to each event for the `chunk` option of the fluent message, the unique id fluentd assigns to each message it forwards. All events decoded from one message get the same id, so it can be used to deduplicate messages that were sent again. By default the chunk id isn't added."
+			required:    false
+			warnings: []
+			type: string: {
+				examples: ["chunk"]
+				syntax: "literal"
+			}
+		}
+		connection_metadata_key: {
+			common:      false
+			description: "The key name added to each event for metadata about the connection it was received on: the `local_addr` it was accepted on and, for TLS connections, the negotiated `tls_version` and `cipher`. By default no connection metadata is added."
+			required:    false
+			warnings: []
+			type: string: {
+				examples: ["connection"]
+				syntax: "literal"
+			}
+		}
+		decode_error_sample_bytes: {
+			common:      false
+			description: "How many bytes of the data that failed to decode are included, base64 encoded, in the error logged for it. The data is whatever clients sent, so it may contain sensitive records that would otherwise never reach the logs of Vector itself; set this to `0` to leave it out entirely."
+			required:    false
+			warnings: []
+			type: uint: {
+				default: 256
+				unit:    "bytes"
+			}
+		}
+		emit_decode_errors_as_events: {
+			common:      false
+			description: "Whether messages that fail to decode, but don't close the connection, are sent on as events with the tag `_fluent_decode_error` rather than dropped, so that they can be routed elsewhere for analysis. Such events have the `error` and `error_type` of the failure, and the whole `message`, base64 encoded."
+			required:    false
+			warnings: []
+			type: bool: default: false
+		}
+		ext_decoders: {
+			common:      false
+			description: "Decodings for the bytes of msgpack extension values with the given codes, such as a custom UUID extension. Values with any other extension code are inserted as an object with `msgpack_extension_code` and `bytes` fields, as are values the decoding doesn't apply to."
+			required:    false
+			warnings: []
+			type: array: {
+				default: []
+				items: type: object: {
+					examples: []
+					options: {
+						code: {
+							description: "The msgpack extension code."
+							required:    true
+							warnings: []
+							type: uint: {
+								examples: [2]
+								unit: null
+							}
+						}
+						decoding: {
+							description: "How the extension bytes are decoded."
+							required:    true
+							warnings: []
+							type: string: {
+								enum: {
+									hex:    "A lowercase hex string."
+									base64: "A base64 string."
+									string: "A UTF-8 string, replacing invalid sequences."
+									uuid:   "A hyphenated UUID string, for 16 byte values."
+								}
+								syntax: "literal"
+							}
+						}
+					}
+				}
+			}
+		}
+		fallback_uncompressed: {
+			common:      false
+			description: "Whether the entries of messages with a `compressed` option other than `gzip` or `text`, such as a compression scheme added to the protocol later, are decoded as if they were uncompressed, with a warning, rather than failing the message. Messages that are actually compressed still fail to decode."
+			required:    false
+			warnings: []
+			type: bool: default: false
+		}
+		first_frame_timeout_secs: {
+			common:      false
+			description: "The amount of time a connection may take to send its first complete fluent message, counted from when the TLS handshake completes, or from when it's accepted without TLS. Until then it replaces `idle_timeout_secs` and `frame_read_timeout_secs`, which apply once the first message is in, so that clients that are slow to start, such as TLS clients, can be given more time than the steady-state idle timeout. By default the first message is subject to those timeouts like any other."
+			required:    false
+			warnings: []
+			type: uint: {
+				examples: [60]
+				unit: "seconds"
+			}
+		}
+		flatten_max_depth: {
+			common:      false
+			description: "The most levels of nested maps that are flattened when `flatten_records` is enabled. Maps nested deeper than that are kept as map values of the flattened fields. By default maps are flattened however deeply they're nested."
+			required:    false
+			warnings: []
+			type: uint: {
+				examples: [3]
+				unit: null
+			}
+		}
+		flatten_records: {
+			common:      false
+			description: "Whether nested maps in records are flattened into top-level fields, with their keys joined by `flatten_separator`, e.g. `{\"a\": {\"b\": {\"c\": 1}}}` becomes `a.b.c`, for downstream schemas that require flat records. Flattened keys are never parsed as paths, even with `parse_key_paths`. Empty maps are kept as they are."
+			required:    false
+			warnings: []
+			type: bool: default: false
+		}
+		flatten_separator: {
+			common:      false
+			description: "The string that joins the keys of nested maps when `flatten_records` is enabled."
+			required:    false
+			warnings: []
+			type: string: {
+				default: "."
+				examples: ["_"]
+				syntax: "literal"
+			}
+		}
+		force_close_on_shutdown: {
+			common:      false
+			description: "Whether connections still open once the shutdown timeout passes are closed in both directions immediately, dropping any data buffered for them, rather than being left to end once their buffered messages are decoded. This can help with sockets lingering on some kernels."
+			required:    false
+			warnings: []
+			type: bool: default: false
+		}
+		frame_read_timeout_secs: {
+			common:      false
+			description: "The amount of time a connection may take to send the rest of a fluent message once it started sending it. Connections exceeding this are closed, which protects against clients holding connections open by sending data very slowly. Unlike `idle_timeout_secs`, connections that aren't in the middle of a message are left open. By default there is no limit."
+			required:    false
+			warnings: []
+			type: uint: {
+				examples: [30]
+				unit: "seconds"
+			}
+		}
+		framing: {
+			common:      false
+			description: "How messages are delimited on connections. Some lightweight forwarders send fluent messages each preceded by its length rather than relying on msgpack encoding being self-delimiting."
+			required:    false
+			warnings: []
+			type: string: {
+				default: "msgpack"
+				enum: {
+					msgpack:          "Each message ends where its msgpack encoding does, as the fluent forward protocol specifies."
+					length_delimited: "Each message is preceded by its length in bytes as a 4-byte big-endian integer, and has to take up exactly that many bytes. `max_frame_bytes` applies to the length, so larger messages are rejected before they are buffered."
+				}
+				syntax: "literal"
+			}
+		}
+		global_buffer_bytes: {
+			common:      false
+			description: "The most bytes that may be buffered for decoding across all connections at once. Connections stop reading once this is used up, leaving the data in the socket so that clients are slowed down, until other connections have decoded what they buffered. So that connections holding partial frames when it's used up don't wait on each other, one of them at a time may read past it until it has decoded a frame, so up to one more frame may be buffered. It must be at least `max_frame_bytes`. By default buffering is only limited per connection."
+			required:    false
+			warnings: []
+			type: uint: {
+				examples: [104857600]
+				unit: "bytes"
+			}
+		}
+		heartbeat_udp: {
+			common:      false
+			description: "The address to listen for the forward protocol's UDP heartbeats on. Each heartbeat is echoed back to its sender so clients can health check the source. Like `address`, a UDP socket passed by systemd socket activation can be used with `systemd#N` or `systemd:NAME`, and the source fails to build if that socket isn't a UDP one. By default UDP heartbeats aren't answered."
+			required:    false
+			warnings: []
+			type: string: {
+				examples: ["0.0.0.0:\(_port)", "systemd:fluent-heartbeat"]
+				syntax: "literal"
+			}
+		}
+		host_key: {
+			category:    "Context"
+			common:      false
+			description: "The key name added to each event representing the IP address of the client that sent it. This can also be globally set via the [global `host_key` option][docs.reference.configuration.global-options#host_key]. Link-local IPv6 addresses include their scope id, such as `fe80::1%2`. Set to an empty string to not add the client address at all."
+			required:    false
+			warnings: []
+			type: string: {
+				default: "host"
+				syntax:  "literal"
+			}
+		}
+		idle_timeout_secs: {
+			common:      false
+			description: "The amount of time a connection may go without sending a complete fluent message before it is closed. By default idle connections are kept open."
+			required:    false
+			warnings: []
+			type: uint: {
+				examples: [300]
+				unit: "seconds"
+			}
+		}
+		key_remapping: {
+			common:      false
+			description: "Record keys to rename, mapped to their new names, for clients that send keys with characters that clash with downstream systems, such as dots, `@` or spaces. Keys are renamed before any other handling, such as conflicts with the timestamp and tag keys, and a renamed field wins over one that already has its new name. Keys that aren't listed are left as they are."
+			required:    false
+			warnings: []
+			type: object: {
+				examples: [{"@timestamp": "fluent_timestamp", "kubernetes.pod name": "pod_name"}]
+				options: {}
+			}
+		}
+		large_int_as_float: {
+			common:      false
+			description: "Whether integers in records that are too large to be stored as a 64-bit signed integer, such as unsigned 64-bit counters, are decoded as the nearest float instead of as a string. Floats lose precision for such large numbers but can still be compared and used in arithmetic."
+			required:    false
+			warnings: []
+			type: bool: default: false
+		}
+		lenient_array_records: {
+			common:      false
+			description: "Whether messages whose record is an array instead of a map, as sent by some clients, are decoded with the array stored under the `array_record_key` field. Such messages are counted in the `fluent_array_records_total` metric. By default they are ignored, like heartbeats."
+			required:    false
+			warnings: []
+			type: bool: default: false
+		}
+		lenient_decompression: {
+			common:      false
+			description: "Whether data after the last complete member of a gzip compressed message that isn't a gzip member itself, such as padding added by some clients, is ignored with a warning rather than failing the whole message. Data that is corrupt within a member still fails it."
+			required:    false
+			warnings: []
+			type: bool: default: false
+		}
+		lenient_message_order: {
+			common:      false
+			description: "Whether messages whose elements aren't in the order of the specification, such as with the options before the record, are put in order on a best-effort basis, for clients that send them that way. Such messages are counted in the `fluent_reordered_messages_total` metric. By default they are ignored, like heartbeats."
+			required:    false
+			warnings: []
+			type: bool: default: false
+		}
+		lenient_timestamps: {
+			common:      false
+			description: "Whether messages with a malformed EventTime or msgpack timestamp, such as one with a truncated payload, get the current time instead of failing to decode. By default such messages are dropped."
+			required:    false
+			warnings: []
+			type: bool: default: false
+		}
+		listener_label: {
+			category:    "Context"
+			common:      false
+			description: "A label added to each event under `listener_label_key`, to tell apart events received by different fluent sources feeding the same pipeline. Unlike the fluent tag, it doesn't depend on the client. By default no label is added."
+			required:    false
+			warnings: []
+			type: string: {
+				examples: ["fluent-eu-west"]
+				syntax: "literal"
+			}
+		}
+		listener_label_key: {
+			category:    "Context"
+			common:      false
+			description: "The key name the `listener_label` is added under. It has no effect without `listener_label`."
+			required:    false
+			warnings: []
+			type: string: {
+				default: "listener"
+				syntax:  "literal"
+			}
+		}
+		max_bytes_per_second: {
+			common:      false
+			description: "The maximum number of bytes read from each connection per second, with bursts of up to one second's worth. A connection exceeding this is read from more slowly, which pushes back on the client instead of dropping data. By default reads aren't limited."
+			required:    false
+			warnings: []
+			type: uint: {
+				examples: [1048576]
+				unit: "bytes"
+			}
+		}
+		max_connection_lifetime_secs: {
+			common:      false
+			description: "The amount of time a connection may stay open, counted from when it's accepted, or from when the TLS handshake completes. Connections open for longer stop reading, hand out the messages that were already received and are closed, prompting clients to reconnect, which spreads connections evenly across instances behind a load balancer. By default connections may stay open indefinitely."
+			required:    false
+			warnings: []
+			type: uint: {
+				examples: [3600]
+				unit: "seconds"
+			}
+		}
+		max_connection_rate: {
+			common:      false
+			description: "The maximum number of new connections accepted per second on each address listened on, with bursts of up to one second's worth, to blunt connection storms. Connections beyond that wait in the listen backlog until they can be accepted. By default connections aren't rate limited."
+			required:    false
+			warnings: []
+			type: uint: {
+				examples: [100]
+				unit: null
+			}
+		}
+		max_connections: {
+			common:      false
+			description: "The most connections open at a time on each address listened on. What happens to connections over the limit is set by `on_connection_limit`. By default there is no limit."
+			required:    false
+			warnings: []
+			type: uint: {
+				examples: [1000]
+				unit: null
+			}
+		}
+		max_decode_errors_per_connection: {
+			common:      false
+			description: "The number of fluent messages in a row that may fail to decode before the connection is closed. Otherwise a client that keeps sending invalid messages stays connected, as most decode errors only cause the message to be dropped. By default connections aren't closed for decode errors."
+			required:    false
+			warnings: []
+			type: uint: {
+				examples: [100]
+				unit: null
+			}
+		}
+		max_decompressed_bytes: {
+			common:      false
+			description: "The maximum size a gzip compressed fluent message may expand to. Connections sending a message that decompresses to more than this are closed. By default there is no limit."
+			required:    false
+			warnings: []
+			type: uint: {
+				examples: [10485760]
+				unit: "bytes"
+			}
+		}
+		max_entry_bytes: {
+			common:      false
+			description: "The maximum size of a single entry of a packed forward message, after decompression. Connections sending a message with a larger entry are closed, as decoding stops reading an entry once it exceeds this. This complements `max_frame_bytes` and `max_decompressed_bytes`, which apply to messages as a whole. By default entries of any size are accepted."
+			required:    false
+			warnings: []
+			type: uint: {
+				examples: [65536]
+				unit: "bytes"
+			}
+		}
+		max_frame_bytes: {
+			common:      false
+			description: "The maximum size of a single fluent message. Connections sending a larger message, or announcing one with a length prefix, are closed. By default messages of any size are accepted, except with `length_delimited` framing, where they are limited to 64 MiB."
+			required:    false
+			warnings: []
+			type: uint: {
+				examples: [1048576]
+				unit: "bytes"
+			}
+		}
+		max_pending_frames: {
+			common:      false
+			description: "The most events decoded from a single fluent message to buffer at a time. Forward messages can carry any number of entries, so rather than decoding all of them at once, only this many are decoded, and more once they've been sent on."
+			required:    false
+			warnings: []
+			type: uint: {
+				default: 128
+				unit:    null
+			}
+		}
+		max_record_fields: {
+			common:      false
+			description: "The most top-level fields a record may have. Records with more fields, which are likely from a misbehaving client, are handled according to `on_oversized`. By default records may have any number of fields."
+			required:    false
+			warnings: []
+			type: uint: {
+				examples: [1000]
+				unit: null
+			}
+		}
+		max_tag_metric_labels: {
+			common:      false
+			description: "The most distinct tags the `fluent_events_received_total` metric is labeled with. Events with tags seen after that many are counted under an `other` tag instead, so that clients sending many different tags can't create an unbounded number of metrics."
+			required:    false
+			warnings: []
+			type: uint: {
+				default: 100
+				unit:    null
+			}
+		}
+		max_value_depth: {
+			common:      false
+			description: "The most levels of arrays and maps a record value may be nested in. Records with values nested deeper, which are likely from a misbehaving client, are handled according to `on_too_deep`. It can be at most 256. Regardless of this option, messages with values nested more than 256 levels deep are rejected before they're decoded and the connection is closed, as decoding them could exhaust the stack."
+			required:    false
+			warnings: []
+			type: uint: {
+				examples: [32]
+				unit: null
+			}
+		}
+		nodelay: {
+			common:      false
+			description: "Whether to set `TCP_NODELAY` on accepted connections, disabling Nagle's algorithm. By default the operating system setting is left untouched."
+			required:    false
+			warnings: []
+			type: bool: {}
+		}
+		non_utf8_key_encoding: {
+			common:      false
+			description: "How record keys that aren't valid UTF-8 are turned into field names. Other keys that aren't strings, such as integers, are formatted as text."
+			required:    false
+			warnings: []
+			type: string: {
+				default: "hex"
+				enum: {
+					hex:    "A lowercase hex string of the key bytes."
+					base64: "A base64 string of the key bytes."
+				}
+				syntax: "literal"
+			}
+		}
+		on_connection_limit: {
+			common:      false
+			description: "What happens to new connections once `max_connections` are open."
+			required:    false
+			warnings: []
+			type: string: {
+				default: "queue"
+				enum: {
+					queue:  "Stop accepting connections until one closes, so that new ones wait in the listen backlog."
+					reject: "Accept new connections and close them right away, so that clients back off rather than wait. Such connections are counted in the `connection_limit_rejected_total` metric."
+				}
+				syntax: "literal"
+			}
+		}
+		on_key_conflict: {
+			common:      false
+			description: "Which field is kept when a record contains a field with the same name as the host, timestamp or tag added to events."
+			required:    false
+			warnings: []
+			type: string: {
+				default: "record_wins"
+				enum: {
+					record_wins:   "Keep the field of the record."
+					reserved_wins: "Keep the host, timestamp or tag, dropping the field of the record."
+					prefix_record: "Keep both, with the field of the record renamed to `fluent_<key>`, such as `fluent_host`."
+				}
+				syntax: "literal"
+			}
+		}
+		on_oversized: {
+			common:      false
+			description: "What to do with records that have more than `max_record_fields` fields."
+			required:    false
+			warnings: []
+			type: string: {
+				default: "truncate"
+				enum: {
+					truncate: "Keep the first `max_record_fields` fields, in the order the client sent them."
+					drop:     "Drop the event."
+				}
+				syntax: "literal"
+			}
+		}
+		on_send_error: {
+			common:      false
+			description: "What a connection does when sending its events downstream fails. A pipeline that stops taking events altogether, such as when it's shutting down, closes the connection either way."
+			required:    false
+			warnings: []
+			type: string: {
+				default: "close_connection"
+				enum: {
+					close_connection: "Stop reading from the connection and close it, so that the client has to reconnect and send again what wasn't received."
+					drop:             "Drop events the pipeline rejects and carry on reading from the connection."
+				}
+				syntax: "literal"
+			}
+		}
+		on_too_deep: {
+			common:      false
+			description: "What to do with records that have values nested more than `max_value_depth` levels deep."
+			required:    false
+			warnings: []
+			type: string: {
+				default: "truncate"
+				enum: {
+					truncate: "Replace the arrays and maps nested too deep with the string `[too deep]`."
+					drop:     "Drop the event."
+				}
+				syntax: "literal"
+			}
+		}
+		parse_key_paths: {
+			common:      false
+			description: "Whether record keys are parsed as paths, so that a key such as `kubernetes.pod_name` creates a nested `pod_name` field in a `kubernetes` object. Keys are inserted in sorted order, and when two keys collide, such as `kubernetes` and `kubernetes.pod_name`, the later one overwrites the earlier. By default keys are inserted as is."
+			required:    false
+			warnings: []
+			type: bool: default: false
+		}
+		pause_accept_on_backpressure: {
+			common:      false
+			description: "Whether to stop accepting new connections while sending events downstream has been blocked for more than a second, resuming once downstream catches up. Pending connections wait in the listen backlog in the meantime, rather than adding to the load. Existing connections are unaffected."
+			required:    false
+			warnings: []
+			type: bool: default: false
+		}
+		peer_hostname_key: {
+			common:      false
+			description: "The key name added to each event for the hostname of the client, with `resolve_peer_hostname` enabled."
+			required:    false
+			warnings: []
+			type: string: {
+				default: "peer_hostname"
+				syntax:  "literal"
+			}
+		}
+		port_key: {
+			common:      false
+			description: "The key name added to each event representing the port of the client that sent it. By default the port isn't added."
+			required:    false
+			warnings: []
+			type: string: {
+				examples: ["port"]
+				syntax: "literal"
+			}
+		}
+		proxy_protocol: {
+			common:      false
+			description: "Whether connections are expected to start with a [PROXY protocol](\(urls.haproxy_proxy_protocol)) v1 or v2 header, as sent by load balancers such as HAProxy or AWS NLB. When enabled, the client address from the header is used for the `host` field, and connections without a valid header are dropped. The header is read before the TLS handshake."
+			required:    false
+			warnings: []
+			type: bool: default: false
+		}
+		raw_message_key: {
+			common:      false
+			description: "The key name added to each event for the msgpack bytes of the message it was decoded from, base64 encoded, to help diagnose decoding issues. All events decoded from a forward mode message get the whole message. By default the message isn't added."
+			required:    false
+			warnings: []
+			type: string: {
+				examples: ["raw_message"]
+				syntax: "literal"
+			}
+		}
+		raw_timestamp_key: {
+			common:      false
+			description: "The key name added to each event for the timestamp of the fluent message as it was received, before it's turned into the event timestamp: the number of seconds for unix timestamps, or a map of the extension `type` and base64 encoded `data` for EventTime and msgpack timestamps. By default the raw timestamp isn't added."
+			required:    false
+			warnings: []
+			type: string: {
+				default: null
+				examples: ["raw_timestamp"]
+				syntax: "literal"
+			}
+		}
+		receive_lowat_bytes: {
+			common:      false
+			description: "The least amount of data, in bytes, that has to be buffered on a connection before the operating system wakes the source to read it, set as `SO_RCVLOWAT`. For very high-throughput clients this coalesces many small reads into fewer larger ones, but it adds latency, as small messages wait until more data arrives or until the operating system gives up waiting, and a message smaller than it that is the last one for a while may be delayed considerably. Only supported on Unix, a warning is logged for each connection it can't be set on."
+			required:    false
+			warnings: []
+			type: uint: {
+				examples: [65536]
+				unit: "bytes"
+			}
+		}
+		received_timestamp_key: {
+			common:      false
+			description: "The key name added to each event for the time Vector received it, as opposed to the `timestamp` field, which is the time the client sent with the event and may lag behind for clients that buffer events. By default the time of receipt isn't added."
+			required:    false
+			warnings: []
+			type: string: {
+				examples: ["received_at"]
+				syntax: "literal"
+			}
+		}
+		resolve_peer_hostname: {
+			common:      false
+			description: "Whether the hostname of each client is looked up with reverse DNS and added to its events under `peer_hostname_key`. Lookups are cached for five minutes, and reading from a new connection waits for up to two seconds for one. Clients without a hostname get their address instead."
+			required:    false
+			warnings: []
+			type: bool: default: false
+		}
+		reuse_port: {
+			common:      false
+			description: "Whether to set `SO_REUSEPORT` on the listening socket, so that several Vector instances can listen on the same address and have connections spread between them. This is only supported on Unix platforms other than Solaris and illumos; elsewhere the source fails to start when enabled. It has no effect when listening on a systemd socket."
+			required:    false
+			warnings: []
+			type: bool: default: false
+		}
+		sample_key: {
+			common:      false
+			description: "The field that events are sampled by when `sample_rate` is set, so that events with the same value for it are all kept or all dropped. Events without the field, or all of them if it isn't set, are sampled at random."
+			required:    false
+			warnings: []
+			type: string: {
+				default: null
+				examples: ["user_id"]
+				syntax: "literal"
+			}
+		}
+		sample_rate: {
+			common:      false
+			description: "The fraction of events that are kept, between `0.0` and `1.0`, to reduce the volume of events before they reach transforms. The other events are dropped and counted in the `events_sampled_out_total` metric. By default all events are kept."
+			required:    false
+			warnings: []
+			type: float: {
+				default: null
+				examples: [0.1]
+			}
+		}
+		send_buffer_bytes: {
+			common:      false
+			description: "Configures the send buffer size using the `SO_SNDBUF` option on accepted connections."
+			required:    false
+			warnings: []
+			type: uint: {
+				examples: [65536]
+				unit: "bytes"
+			}
+		}
+		sni_key: {
+			common:      false
+			description: "The key name added to each event for the server name the client requested through SNI during the TLS handshake, which tells apart tenants sharing a listener. Nothing is added for connections without TLS or clients that don't send a server name. By default the server name isn't added."
+			required:    false
+			warnings: []
+			type: string: {
+				examples: ["server_name"]
+				syntax: "literal"
+			}
+		}
+		source_type_key: {
+			category:    "Context"
+			common:      false
+			description: "The key name added to each event for the name of the source type, `fluent`. This can also be globally set via the [global `source_type_key` option][docs.reference.configuration.global-options#source_type_key]. Set to an empty string to not add the source type at all."
+			required:    false
+			warnings: []
+			type: string: {
+				default: "source_type"
+				syntax:  "literal"
+			}
+		}
+		strict_entry_count: {
+			common:      false
+			description: "Whether forward messages whose number of entries doesn't match the `size` option sent by the client are dropped, as they are likely truncated or corrupt. Mismatches are counted in the `fluent_entry_count_mismatches_total` metric either way. By default the entries are still processed."
+			required:    false
+			warnings: []
+			type: bool: default: false
+		}
+		stringify_values: {
+			common:      false
+			description: "Whether record values are all turned into strings, for sinks that can't handle fields of varying types. Numbers and booleans become their text representation, and arrays and maps their JSON encoding, while nulls are kept. The host, timestamp and tag added by the source keep their types."
+			required:    false
+			warnings: []
+			type: bool: default: false
+		}
+		suppress_empty_connections: {
+			common:      false
+			description: "Whether connections are only logged and counted in the connection metrics once the client has sent something, so that load balancer health checks that connect and close right away don't add to them."
+			required:    false
+			warnings: []
+			type: bool: default: false
+		}
+		tag_key: {
+			common:      false
+			description: "The key name added to each event for the tag of the fluent message. If the record itself contains a field with this name, which one is kept depends on `on_key_conflict`."
+			required:    false
+			warnings: []
+			type: string: {
+				default: "tag"
+				syntax:  "literal"
+			}
+		}
+		timestamp_from_field: {
+			common:      false
+			description: "A record field holding the timestamp of the event, such as an application's `@timestamp`, which then replaces the timestamp of the fluent message. The field can be an RFC 3339 timestamp, or the seconds since the Unix epoch as a number or string. The message timestamp is kept when the field is missing or isn't a timestamp. The field itself is kept either way. This requires `timestamp_key` to not be empty."
+			required:    false
+			warnings: []
+			type: string: {
+				default: null
+				examples: ["@timestamp"]
+				syntax: "literal"
+			}
+		}
+		timestamp_key: {
+			category:    "Context"
+			common:      false
+			description: "The key name added to each event for the timestamp of the fluent message. This can also be globally set via the [global `timestamp_key` option][docs.reference.configuration.global-options#timestamp_key]. Set to an empty string to not add the message timestamp at all, for example to keep a timestamp field of the record itself."
+			required:    false
+			warnings: []
+			type: string: {
+				default: "timestamp"
+				syntax:  "literal"
+			}
+		}
+		tls_client_metadata_key: {
+			common:      false
+			description: "The key name added to each event for the subject of the certificate the client presented, as an object with `common_name` and `subject_alt_names` fields. Only certificates verified against `tls.ca_file` are used, so this requires `tls.verify_certificate` to be enabled. By default the client certificate isn't added."
+			required:    false
+			warnings: []
+			type: string: {
+				examples: ["tls_client"]
+				syntax: "literal"
+			}
+		}
+		tls_handshake_timeout_secs: {
+			common:      false
+			description: "How long clients get to complete the TLS handshake, and to send the PROXY protocol header with `proxy_protocol` enabled, before their connection is closed, so that clients that connect and stall don't hold on to connections indefinitely."
+			required:    false
+			warnings: []
+			type: uint: {
+				default: 10
+				unit:    "seconds"
+			}
+		}
+		v6only: {
+			common:      false
+			description: "Whether listeners on IPv6 addresses only accept IPv6 connections, set as `IPV6_V6ONLY`. When false, a listener on `[::]` also accepts IPv4 connections, whose peers appear as IPv4-mapped IPv6 addresses such as `::ffff:10.1.2.3`. By default the operating system's setting applies, which differs between platforms. It doesn't apply to IPv4 addresses, or to sockets passed by systemd."
+			required:    false
+			warnings: []
+			type: bool: {}
+		}
 	}
 
 	output: logs: line: {
@@ -82,8 +836,16 @@ components: sources: fluent: {
 					syntax: "literal"
 				}
 			}
+			source_type: {
+				description: "The name of the source type."
+				required:    true
+				type: string: {
+					examples: ["fluent"]
+					syntax: "literal"
+				}
+			}
 			timestamp: {
-				description: "The timestamp extracted from the fluent message."
+				description: "The timestamp extracted from the fluent message, which may be a Unix timestamp, a fluent EventTime or a msgpack timestamp extension."
 				required:    true
 				type: timestamp: {}
 			}
@@ -118,10 +880,11 @@ components: sources: fluent: {
 				(this is the fluentd stdout encoding of the dummy message)
 				"""
 			output: log: {
-				host:      _values.remote_host
-				timestamp: "2021-05-20T20:23:03.021497Z"
-				tag:       "dummy"
-				message:   "dummy"
+				host:        _values.remote_host
+				source_type: "fluent"
+				timestamp:   "2021-05-20T20:23:03.021497Z"
+				tag:         "dummy"
+				message:     "dummy"
 			}
 		},
 		{
@@ -135,10 +898,11 @@ components: sources: fluent: {
 				(this is the fluent-bit stdout encoding of the dummy message)
 				"""
 			output: log: {
-				host:      _values.remote_host
-				timestamp: "2020-05-20T20:17:28.161827Z"
-				tag:       "dummy.0"
-				message:   "dummy"
+				host:        _values.remote_host
+				source_type: "fluent"
+				timestamp:   "2020-05-20T20:17:28.161827Z"
+				tag:         "dummy.0"
+				message:     "dummy"
 			}
 		},
 	]
@@ -208,16 +972,56 @@ components: sources: fluent: {
 			body:  """
 				The `fluent` source currently does not support the acknowledgement parts of the Fluent protocol and so
 				the `require_ack_response` option forward output plugins for Fluent and Fluent Bit cannot be used.
+				As no acks are written back to clients, there is also no timeout for writing them yet; a slow client can
+				only stall a connection by not sending, which `frame_read_timeout_secs` already bounds.
 
 				If you would find this useful, [please let us know](\(urls.vector_repo)/issues/7533).
 				"""
 		}
+
+		keepalive: {
+			title: "TCP keepalive"
+			body:  """
+				Unlike other TCP sources, this source enables TCP keepalive by default, sending probes after a
+				connection has been idle for 75 seconds, so that connections from forwarders that went away without
+				closing them are eventually dropped. `keepalive.time_secs` must be greater than zero. Setting
+				`keepalive.enabled` to `false` disables keepalive, and setting `keepalive` to an empty table leaves the
+				operating system setting untouched. The effective setting is logged when the source starts.
+				"""
+		}
 	}
 
 	telemetry: metrics: {
-		events_in_total:        components.sources.internal_metrics.output.metrics.events_in_total
-		decode_errors_total:    components.sources.internal_metrics.output.metrics.decode_errors_total
-		processed_bytes_total:  components.sources.internal_metrics.output.metrics.processed_bytes_total
-		processed_events_total: components.sources.internal_metrics.output.metrics.processed_events_total
+		accept_paused:                         components.sources.internal_metrics.output.metrics.accept_paused
+		component_received_bytes_total:        components.sources.internal_metrics.output.metrics.component_received_bytes_total
+		component_received_events_total:       components.sources.internal_metrics.output.metrics.component_received_events_total
+		connection_drain_dropped_frames_total: components.sources.internal_metrics.output.metrics.connection_drain_dropped_frames_total
+		connection_duration_seconds:           components.sources.internal_metrics.output.metrics.connection_duration_seconds
+		connection_errored_total:              components.sources.internal_metrics.output.metrics.connection_errored_total
+		connection_established_total:          components.sources.internal_metrics.output.metrics.connection_established_total
+		connection_handshake_timeouts_total:   components.sources.internal_metrics.output.metrics.connection_handshake_timeouts_total
+		connection_lifetime_exceeded_total:    components.sources.internal_metrics.output.metrics.connection_lifetime_exceeded_total
+		connection_limit_rejected_total:       components.sources.internal_metrics.output.metrics.connection_limit_rejected_total
+		connection_rate_limited_total:         components.sources.internal_metrics.output.metrics.connection_rate_limited_total
+		connection_receive_buffer_bytes:       components.sources.internal_metrics.output.metrics.connection_receive_buffer_bytes
+		connection_rejected_total:             components.sources.internal_metrics.output.metrics.connection_rejected_total
+		connection_reset_total:                components.sources.internal_metrics.output.metrics.connection_reset_total
+		downstream_send_errors_total:          components.sources.internal_metrics.output.metrics.downstream_send_errors_total
+		events_in_total:                       components.sources.internal_metrics.output.metrics.events_in_total
+		events_sampled_out_total:              components.sources.internal_metrics.output.metrics.events_sampled_out_total
+		decode_errors_total:                   components.sources.internal_metrics.output.metrics.decode_errors_total
+		fluent_array_records_total:            components.sources.internal_metrics.output.metrics.fluent_array_records_total
+		fluent_buffered_bytes:                 components.sources.internal_metrics.output.metrics.fluent_buffered_bytes
+		fluent_decode_errors_total:            components.sources.internal_metrics.output.metrics.fluent_decode_errors_total
+		fluent_entry_count_mismatches_total:   components.sources.internal_metrics.output.metrics.fluent_entry_count_mismatches_total
+		fluent_events_received_total:          components.sources.internal_metrics.output.metrics.fluent_events_received_total
+		fluent_forward_entries:                components.sources.internal_metrics.output.metrics.fluent_forward_entries
+		fluent_messages_total:                 components.sources.internal_metrics.output.metrics.fluent_messages_total
+		fluent_oversized_records_total:        components.sources.internal_metrics.output.metrics.fluent_oversized_records_total
+		fluent_reordered_messages_total:       components.sources.internal_metrics.output.metrics.fluent_reordered_messages_total
+		fluent_too_deep_values_total:          components.sources.internal_metrics.output.metrics.fluent_too_deep_values_total
+		fluent_unread_frames:                  components.sources.internal_metrics.output.metrics.fluent_unread_frames
+		processed_bytes_total:                 components.sources.internal_metrics.output.metrics.processed_bytes_total
+		processed_events_total:                components.sources.internal_metrics.output.metrics.processed_events_total
 	}
 }
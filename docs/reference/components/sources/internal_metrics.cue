@@ -85,6 +85,18 @@ components: sources: internal_metrics: {
 			default_namespace: "vector"
 			tags:              _internal_metrics_tags
 		}
+		connection_drain_dropped_frames_total: {
+			description:       "The total number of frames that connections had received but dropped, because they couldn't hand them out in time after the source was shut down."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _internal_metrics_tags
+		}
+		connection_errored_total: {
+			description:       "The total number of connections that failed to be accepted."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _internal_metrics_tags
+		}
 		connection_errors_total: {
 			description:       "The total number of connection errors for this Vector instance."
 			type:              "counter"
@@ -103,6 +115,48 @@ components: sources: internal_metrics: {
 			default_namespace: "vector"
 			tags:              _internal_metrics_tags
 		}
+		connection_handshake_timeouts_total: {
+			description:       "The total number of connections closed because the client didn't complete the TLS handshake, or send the PROXY protocol header, in time."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _internal_metrics_tags
+		}
+		connection_limit_rejected_total: {
+			description:       "The total number of connections closed right after they were accepted because `max_connections` were already open, with `on_connection_limit` set to `reject`."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _internal_metrics_tags
+		}
+		connection_rate_limited_total: {
+			description:       "The total number of times accepting new connections was delayed because they came in faster than `max_connection_rate` allows."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _internal_metrics_tags
+		}
+		connection_rejected_total: {
+			description:       "The total number of connections closed because the peer isn't allowed to connect."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _internal_metrics_tags
+		}
+		connection_lifetime_exceeded_total: {
+			description:       "The total number of connections closed because they were open for longer than their maximum lifetime."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _internal_metrics_tags
+		}
+		connection_receive_buffer_bytes: {
+			description:       "The receive buffer size of the most recently accepted connection, as reported by the operating system. This may be smaller than the configured `receive_buffer_bytes` when the operating system limits it."
+			type:              "gauge"
+			default_namespace: "vector"
+			tags:              _internal_metrics_tags
+		}
+		connection_reset_total: {
+			description:       "The total number of connections the peer closed abruptly, by resetting or aborting them."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _internal_metrics_tags
+		}
 		connection_send_errors_total: {
 			description:       "The total number of errors sending data via the connection."
 			type:              "counter"
@@ -154,6 +208,12 @@ components: sources: internal_metrics: {
 
 		// Metrics emitted by one or more components
 		// Reusable metric definitions
+		accept_paused: {
+			description:       "Whether accepting new connections is currently paused because sending events downstream is blocked, 1 when paused and 0 otherwise."
+			type:              "gauge"
+			default_namespace: "vector"
+			tags:              _component_tags
+		}
 		adaptive_concurrency_averaged_rtt: {
 			description:       "The average round-trip time (RTT) from the HTTP sink across the current window."
 			type:              "histogram"
@@ -228,6 +288,34 @@ components: sources: internal_metrics: {
 			default_namespace: "vector"
 			tags:              _component_tags
 		}
+		component_received_bytes_total: {
+			description:       "The number of raw bytes accepted by this component from source origins."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _component_tags & {
+				protocol: {
+					description: "The protocol used to send the bytes."
+					required:    true
+				}
+			}
+		}
+		component_received_events_total: {
+			description:       "The number of events accepted by this component from source origins."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _component_tags & {
+				protocol: {
+					description: "The protocol used to send the events."
+					required:    true
+				}
+			}
+		}
+		connection_duration_seconds: {
+			description:       "How long connections stayed open, from being accepted until they were closed."
+			type:              "histogram"
+			default_namespace: "vector"
+			tags:              _component_tags
+		}
 		connection_read_errors_total: {
 			description:       "The total number of errors reading datagram."
 			type:              "counter"
@@ -278,6 +366,21 @@ components: sources: internal_metrics: {
 			default_namespace: "vector"
 			tags:              _component_tags
 		}
+		downstream_send_errors_total: {
+			description:       "The total number of times sending events downstream from a connection failed."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _internal_metrics_tags & {
+				action: {
+					description: "What the connection did about it, depending on `on_send_error`."
+					required:    true
+					enum: {
+						"close": "The connection was closed."
+						"drop":  "An event the pipeline rejected was dropped."
+					}
+				}
+			}
+		}
 		k8s_format_picker_edge_cases_total: {
 			description:       "The total number of edge cases encountered while picking format of the Kubernetes log message."
 			type:              "counter"
@@ -414,6 +517,12 @@ components: sources: internal_metrics: {
 			default_namespace: "vector"
 			tags:              _component_tags
 		}
+		events_sampled_out_total: {
+			description:       "The total number of events dropped by sampling with `sample_rate`."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _component_tags
+		}
 		processed_events_total: {
 			description: """
 				The total number of events processed by this component.
@@ -540,6 +649,121 @@ components: sources: internal_metrics: {
 				file: _file
 			}
 		}
+		fluent_array_records_total: {
+			description:       "The total number of fluent messages with an array as their record that were wrapped in a map, with `lenient_array_records` enabled."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _component_tags
+		}
+		fluent_buffered_bytes: {
+			description:       "The number of bytes received by the fluent source that are buffered but not decoded yet, across all connections."
+			type:              "gauge"
+			default_namespace: "vector"
+			tags:              _component_tags
+		}
+		fluent_decode_errors_total: {
+			description:       "The total number of errors decoding fluent messages, by kind of error."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _component_tags & {
+				error_type: {
+					description: "The kind of decode error."
+					required:    true
+					enum: {
+						"decode":                 "The message isn't valid msgpack or doesn't match the forward protocol."
+						"decompressed_too_large": "The message decompressed to more than `max_decompressed_bytes`."
+						"entry_count_mismatch":   "The number of entries didn't match the message's `size` option, with `strict_entry_count` enabled."
+						"entry_too_large":        "An entry of a packed forward message exceeded `max_entry_bytes`."
+						"frame_too_large":        "The message exceeded `max_frame_bytes`."
+						"io":                     "Reading or decompressing the message failed."
+						"too_deep":               "The message had record values nested more than 256 levels deep, so the connection was closed."
+						"too_many_decode_errors": "More messages than `max_decode_errors_per_connection` in a row failed to decode, so the connection was closed."
+						"unknown_compression":    "The message used an unsupported compression."
+					}
+				}
+			}
+		}
+		fluent_entry_count_mismatches_total: {
+			description:       "The total number of fluent messages whose number of entries didn't match their `size` option."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _component_tags
+		}
+		fluent_events_received_total: {
+			description:       "The total number of events the fluent source received, by the tag of their message."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _component_tags & {
+				tag: {
+					description: "The tag of the message, or `other` once the source has counted `max_tag_metric_labels` distinct tags."
+					required:    true
+				}
+			}
+		}
+		fluent_forward_entries: {
+			description:       "The number of entries in each forward or packed forward mode message the fluent source decoded."
+			type:              "histogram"
+			default_namespace: "vector"
+			tags:              _component_tags
+		}
+		fluent_messages_total: {
+			description:       "The total number of fluent messages decoded, by compression of their entries."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _component_tags & {
+				compression: {
+					description: "The compression of the message's entries."
+					required:    true
+					enum: {
+						"gzip": "The entries of a packed forward message were compressed with gzip."
+						"none": "The entries weren't compressed."
+						"text": "The entries of a packed forward message were explicitly sent uncompressed."
+					}
+				}
+			}
+		}
+		fluent_oversized_records_total: {
+			description:       "The total number of fluent records with more fields than `max_record_fields`."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _component_tags & {
+				action: {
+					description: "What was done with the record."
+					required:    true
+					enum: {
+						"drop":     "The event was dropped."
+						"truncate": "The record was truncated to `max_record_fields` fields."
+					}
+				}
+			}
+		}
+		fluent_reordered_messages_total: {
+			description:       "The total number of fluent messages with their elements out of order that were put in order, with `lenient_message_order` enabled."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _component_tags
+		}
+		fluent_too_deep_values_total: {
+			description:       "The total number of fluent records with values nested more than `max_value_depth` levels deep."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _component_tags & {
+				action: {
+					description: "What was done with the record."
+					required:    true
+					enum: {
+						"drop":     "The event was dropped."
+						"truncate": "The values were truncated to `max_value_depth` levels."
+					}
+				}
+			}
+		}
+		fluent_unread_frames: {
+			description:       "The number of events decoded by the fluent source from multi-event messages that are not sent on yet, across all connections."
+			type:              "gauge"
+			default_namespace: "vector"
+			tags:              _component_tags
+		}
 		glob_errors_total: {
 			description:       "The total number of errors encountered when globbing paths."
 			type:              "counter"
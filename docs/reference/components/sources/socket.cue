@@ -62,12 +62,12 @@ components: sources: socket: {
 
 	configuration: {
 		address: {
-			description:   "The address to listen for connections on, or `systemd#N` to use the Nth socket passed by systemd socket activation. If an address is used it _must_ include a port."
+			description:   "The address to listen for connections on, or `systemd#N` to use the Nth socket passed by systemd socket activation, or `systemd:NAME` to use the socket named NAME with `FileDescriptorName=`. If an address is used it _must_ include a port."
 			relevant_when: "mode = `tcp` or `udp`"
 			required:      true
 			warnings: []
 			type: string: {
-				examples: ["0.0.0.0:\(_port)", "systemd", "systemd#3"]
+				examples: ["0.0.0.0:\(_port)", "systemd", "systemd#3", "systemd:vector"]
 				syntax: "literal"
 			}
 		}
@@ -118,7 +118,7 @@ components: sources: socket: {
 		}
 		shutdown_timeout_secs: {
 			common:        false
-			description:   "The timeout before a connection is forcefully closed during shutdown."
+			description:   "The timeout before a connection is forcefully closed during shutdown. Frames it had already received are still handed out for up to as long again, and dropped if they can't be by then."
 			relevant_when: "mode = `tcp``"
 			required:      false
 			warnings: []
@@ -159,12 +159,15 @@ components: sources: socket: {
 	]
 
 	telemetry: metrics: {
-		events_in_total:              components.sources.internal_metrics.output.metrics.events_in_total
-		connection_errors_total:      components.sources.internal_metrics.output.metrics.connection_errors_total
-		connection_failed_total:      components.sources.internal_metrics.output.metrics.connection_failed_total
-		connection_established_total: components.sources.internal_metrics.output.metrics.connection_established_total
-		connection_failed_total:      components.sources.internal_metrics.output.metrics.connection_failed_total
-		connection_send_errors_total: components.sources.internal_metrics.output.metrics.connection_send_errors_total
-		connection_shutdown_total:    components.sources.internal_metrics.output.metrics.connection_shutdown_total
+		events_in_total:                     components.sources.internal_metrics.output.metrics.events_in_total
+		connection_errored_total:            components.sources.internal_metrics.output.metrics.connection_errored_total
+		connection_errors_total:             components.sources.internal_metrics.output.metrics.connection_errors_total
+		connection_failed_total:             components.sources.internal_metrics.output.metrics.connection_failed_total
+		connection_established_total:        components.sources.internal_metrics.output.metrics.connection_established_total
+		connection_failed_total:             components.sources.internal_metrics.output.metrics.connection_failed_total
+		connection_handshake_timeouts_total: components.sources.internal_metrics.output.metrics.connection_handshake_timeouts_total
+		connection_reset_total:              components.sources.internal_metrics.output.metrics.connection_reset_total
+		connection_send_errors_total:        components.sources.internal_metrics.output.metrics.connection_send_errors_total
+		connection_shutdown_total:           components.sources.internal_metrics.output.metrics.connection_shutdown_total
 	}
 }
@@ -61,12 +61,12 @@ components: sources: statsd: {
 
 	configuration: {
 		address: {
-			description:   "The address to listen for connections on, or `systemd#N` to use the Nth socket passed by systemd socket activation. If an address is used it _must_ include a port."
+			description:   "The address to listen for connections on, or `systemd#N` to use the Nth socket passed by systemd socket activation, or `systemd:NAME` to use the socket named NAME with `FileDescriptorName=`. If an address is used it _must_ include a port."
 			relevant_when: "mode = `tcp` or `udp`"
 			required:      true
 			warnings: []
 			type: string: {
-				examples: ["0.0.0.0:\(_port)", "systemd", "systemd#3"]
+				examples: ["0.0.0.0:\(_port)", "systemd", "systemd#3", "systemd:vector"]
 				syntax: "literal"
 			}
 		}
@@ -95,7 +95,7 @@ components: sources: statsd: {
 		}
 		shutdown_timeout_secs: {
 			common:        false
-			description:   "The timeout before a connection is forcefully closed during shutdown."
+			description:   "The timeout before a connection is forcefully closed during shutdown. Frames it had already received are still handed out for up to as long again, and dropped if they can't be by then."
 			relevant_when: "mode = `tcp`"
 			required:      false
 			warnings: []
@@ -65,7 +65,7 @@ components: sources: vector: {
 	configuration: {
 		acknowledgements: configuration._acknowledgements
 		address: {
-			description: "The TCP address to listen for connections on, or `systemd#N to use the Nth socket passed by systemd socket activation. If an address is used it _must_ include a port."
+			description: "The TCP address to listen for connections on, or `systemd#N` to use the Nth socket passed by systemd socket activation, or `systemd:NAME` to use the socket named NAME with `FileDescriptorName=`. If an address is used it _must_ include a port."
 			required:    true
 			warnings: []
 			type: string: {
@@ -75,7 +75,7 @@ components: sources: vector: {
 		}
 		shutdown_timeout_secs: {
 			common:      false
-			description: "The timeout before a connection is forcefully closed during shutdown."
+			description: "The timeout before a connection is forcefully closed during shutdown. Frames it had already received are still handed out for up to as long again, and dropped if they can't be by then."
 			required:    false
 			warnings: []
 			type: uint: {
@@ -203,6 +203,7 @@ urls: {
 	grok_patterns:                                            "\(github)/daschl/grok/tree/master/patterns"
 	gzip:                                                     "https://www.gzip.org/"
 	haproxy:                                                  "https://www.haproxy.org/"
+	haproxy_proxy_protocol:                                   "https://www.haproxy.org/download/2.4/doc/proxy-protocol.txt"
 	helm:                                                     "https://helm.sh/"
 	heroku:                                                   "https://www.heroku.com"
 	heroku_http_log_drain:                                    "https://devcenter.heroku.com/articles/log-drains#https-drains"
@@ -1,6 +1,7 @@
 use super::InternalEvent;
 use crate::sources::fluent::DecodeError;
-use metrics::counter;
+use metrics::{counter, decrement_gauge, histogram, increment_gauge};
+use std::net::{IpAddr, SocketAddr};
 
 #[derive(Debug)]
 pub struct FluentMessageReceived {
@@ -18,18 +19,296 @@ impl InternalEvent for FluentMessageReceived {
     }
 }
 
+/// An event was built from a message with `tag`, which is `other` once there are too many
+/// distinct tags to label the metric with each.
+#[derive(Debug)]
+pub struct FluentTagEventReceived<'a> {
+    pub tag: &'a str,
+}
+
+impl<'a> InternalEvent for FluentTagEventReceived<'a> {
+    fn emit_metrics(&self) {
+        counter!("fluent_events_received_total", 1, "tag" => self.tag.to_owned());
+    }
+}
+
+/// All entries of a forward, or packed forward, mode message were decoded.
+#[derive(Debug)]
+pub struct FluentForwardEntriesDecoded {
+    pub count: usize,
+}
+
+impl InternalEvent for FluentForwardEntriesDecoded {
+    fn emit_logs(&self) {
+        trace!(message = "Decoded entries of fluent forward message.", count = %self.count);
+    }
+
+    fn emit_metrics(&self) {
+        histogram!("fluent_forward_entries", self.count as f64);
+    }
+}
+
+#[derive(Debug)]
+pub struct FluentMessageDecoded {
+    pub compression: &'static str,
+}
+
+impl InternalEvent for FluentMessageDecoded {
+    fn emit_logs(&self) {
+        trace!(message = "Decoded fluent message.", compression = %self.compression);
+    }
+
+    fn emit_metrics(&self) {
+        counter!("fluent_messages_total", 1, "compression" => self.compression);
+    }
+}
+
+#[derive(Debug)]
+pub struct FluentMessageReordered;
+
+impl InternalEvent for FluentMessageReordered {
+    fn emit_logs(&self) {
+        debug!(
+            message = "Decoded fluent message with its elements out of order.",
+            internal_log_rate_secs = 10
+        );
+    }
+
+    fn emit_metrics(&self) {
+        counter!("fluent_reordered_messages_total", 1);
+    }
+}
+
+#[derive(Debug)]
+pub struct FluentArrayRecordWrapped;
+
+impl InternalEvent for FluentArrayRecordWrapped {
+    fn emit_logs(&self) {
+        debug!(
+            message = "Decoded fluent message with an array as its record.",
+            internal_log_rate_secs = 10
+        );
+    }
+
+    fn emit_metrics(&self) {
+        counter!("fluent_array_records_total", 1);
+    }
+}
+
 #[derive(Debug)]
 pub struct FluentMessageDecodeError<'a> {
     pub error: &'a DecodeError,
     pub base64_encoded_message: String,
+    pub peer_addr: SocketAddr,
+    // of the message within the connection
+    pub offset: usize,
 }
 
 impl<'a> InternalEvent for FluentMessageDecodeError<'a> {
     fn emit_logs(&self) {
-        error!(message = "Error decoding fluent message.", error = ?self.error, base64_encoded_message = %self.base64_encoded_message, internal_log_rate_secs = 10);
+        error!(message = "Error decoding fluent message.", error = ?self.error, peer_addr = %self.peer_addr, offset = %self.offset, base64_encoded_message = %self.base64_encoded_message, internal_log_rate_secs = 10);
     }
 
     fn emit_metrics(&self) {
         counter!("decode_errors_total", 1);
+        counter!("fluent_decode_errors_total", 1, "error_type" => self.error.error_type());
+    }
+}
+
+#[derive(Debug)]
+pub struct FluentEntryCountMismatch {
+    pub expected: u64,
+    pub actual: usize,
+}
+
+impl InternalEvent for FluentEntryCountMismatch {
+    fn emit_logs(&self) {
+        warn!(message = "Number of entries in fluent message doesn't match its size option.", expected = %self.expected, actual = %self.actual, internal_log_rate_secs = 10);
+    }
+
+    fn emit_metrics(&self) {
+        counter!("fluent_entry_count_mismatches_total", 1);
+    }
+}
+
+#[derive(Debug)]
+pub struct FluentOversizedRecordReceived {
+    pub fields: usize,
+    pub max_fields: usize,
+    pub dropped: bool,
+}
+
+impl InternalEvent for FluentOversizedRecordReceived {
+    fn emit_logs(&self) {
+        if self.dropped {
+            warn!(message = "Dropping fluent record with too many fields.", fields = %self.fields, max_fields = %self.max_fields, internal_log_rate_secs = 10);
+        } else {
+            warn!(message = "Truncating fluent record with too many fields.", fields = %self.fields, max_fields = %self.max_fields, internal_log_rate_secs = 10);
+        }
+    }
+
+    fn emit_metrics(&self) {
+        let action = if self.dropped { "drop" } else { "truncate" };
+        counter!("fluent_oversized_records_total", 1, "action" => action);
+    }
+}
+
+#[derive(Debug)]
+pub struct FluentTooDeepValueReceived {
+    pub max_depth: usize,
+    pub dropped: bool,
+}
+
+impl InternalEvent for FluentTooDeepValueReceived {
+    fn emit_logs(&self) {
+        if self.dropped {
+            warn!(message = "Dropping fluent record with values nested too deep.", max_depth = %self.max_depth, internal_log_rate_secs = 10);
+        } else {
+            warn!(message = "Truncating fluent record values nested too deep.", max_depth = %self.max_depth, internal_log_rate_secs = 10);
+        }
+    }
+
+    fn emit_metrics(&self) {
+        let action = if self.dropped { "drop" } else { "truncate" };
+        counter!("fluent_too_deep_values_total", 1, "action" => action);
+    }
+}
+
+/// A connection's bytes that are buffered but not decoded yet went from `previous` to `current`.
+#[derive(Debug)]
+pub struct FluentBufferedBytes {
+    pub previous: usize,
+    pub current: usize,
+}
+
+impl InternalEvent for FluentBufferedBytes {
+    fn emit_metrics(&self) {
+        if self.current > self.previous {
+            increment_gauge!(
+                "fluent_buffered_bytes",
+                (self.current - self.previous) as f64
+            );
+        } else {
+            decrement_gauge!(
+                "fluent_buffered_bytes",
+                (self.previous - self.current) as f64
+            );
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FluentUnreadFrames {
+    pub peer_addr: SocketAddr,
+    pub previous: usize,
+    pub current: usize,
+}
+
+impl InternalEvent for FluentUnreadFrames {
+    fn emit_logs(&self) {
+        debug!(
+            message = "Decoded frames waiting to be sent.",
+            peer_addr = %self.peer_addr,
+            count = %self.current,
+            internal_log_rate_secs = 10
+        );
+    }
+
+    fn emit_metrics(&self) {
+        if self.current > self.previous {
+            increment_gauge!(
+                "fluent_unread_frames",
+                (self.current - self.previous) as f64
+            );
+        } else {
+            decrement_gauge!(
+                "fluent_unread_frames",
+                (self.previous - self.current) as f64
+            );
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FluentTrailingGzipDataIgnored<'a> {
+    pub byte_size: usize,
+    pub error: &'a std::io::Error,
+}
+
+impl<'a> InternalEvent for FluentTrailingGzipDataIgnored<'a> {
+    fn emit_logs(&self) {
+        warn!(message = "Ignoring data after the last gzip member of fluent message.", byte_size = %self.byte_size, error = %self.error, internal_log_rate_secs = 10);
+    }
+}
+
+#[derive(Debug)]
+pub struct FluentUnknownCompressionIgnored<'a> {
+    pub compression: &'a str,
+}
+
+impl<'a> InternalEvent for FluentUnknownCompressionIgnored<'a> {
+    fn emit_logs(&self) {
+        warn!(message = "Decoding fluent message with unknown compression as uncompressed.", compression = %self.compression, internal_log_rate_secs = 10);
+    }
+}
+
+#[derive(Debug)]
+pub struct FluentMalformedTimestamp<'a> {
+    pub reason: &'a str,
+}
+
+impl<'a> InternalEvent for FluentMalformedTimestamp<'a> {
+    fn emit_logs(&self) {
+        warn!(message = "Using the current time for malformed fluent timestamp.", reason = %self.reason, internal_log_rate_secs = 10);
+    }
+}
+
+#[derive(Debug)]
+pub struct FluentPeerHostnameUnresolved {
+    pub ip: IpAddr,
+}
+
+impl InternalEvent for FluentPeerHostnameUnresolved {
+    fn emit_logs(&self) {
+        debug!(message = "No hostname found for peer, using its address instead.", ip = %self.ip, internal_log_rate_secs = 10);
+    }
+}
+
+#[derive(Debug)]
+pub struct FluentUnexpectedHeartbeat<'a> {
+    pub value: &'a rmpv::Value,
+}
+
+impl<'a> InternalEvent for FluentUnexpectedHeartbeat<'a> {
+    fn emit_logs(&self) {
+        warn!(message = "Ignoring fluent heartbeat with unexpected value.", value = %self.value, internal_log_rate_secs = 10);
+    }
+}
+
+#[derive(Debug)]
+pub struct FluentEventSampledOut;
+
+impl InternalEvent for FluentEventSampledOut {
+    fn emit_logs(&self) {
+        trace!(message = "Dropping fluent event not selected by sampling.");
+    }
+
+    fn emit_metrics(&self) {
+        counter!("events_sampled_out_total", 1);
+    }
+}
+
+#[derive(Debug)]
+pub struct FluentTimestampFieldInvalid<'a> {
+    pub field: &'a str,
+}
+
+impl<'a> InternalEvent for FluentTimestampFieldInvalid<'a> {
+    fn emit_logs(&self) {
+        debug!(
+            message = "Record field isn't a timestamp, keeping the message timestamp.",
+            field = %self.field,
+            internal_log_rate_secs = 10
+        );
     }
 }
@@ -1,6 +1,6 @@
 use super::InternalEvent;
 use crate::tls::TlsError;
-use metrics::counter;
+use metrics::{counter, gauge, histogram};
 
 #[derive(Debug)]
 pub struct TcpSocketConnectionEstablished {
@@ -80,6 +80,21 @@ impl InternalEvent for TcpSocketConnectionError {
     }
 }
 
+#[derive(Debug)]
+pub struct TcpSocketAcceptError {
+    pub error: TlsError,
+}
+
+impl InternalEvent for TcpSocketAcceptError {
+    fn emit_logs(&self) {
+        error!(message = "Failed to accept socket.", error = %self.error);
+    }
+
+    fn emit_metrics(&self) {
+        counter!("connection_errored_total", 1, "mode" => "tcp");
+    }
+}
+
 #[derive(Debug)]
 pub struct TcpSocketError {
     pub error: std::io::Error,
@@ -94,3 +109,259 @@ impl InternalEvent for TcpSocketError {
         counter!("connection_errors_total", 1, "mode" => "tcp");
     }
 }
+
+#[derive(Debug)]
+pub struct TcpBytesReceived {
+    pub byte_size: usize,
+    pub peer_addr: std::net::SocketAddr,
+}
+
+impl InternalEvent for TcpBytesReceived {
+    fn emit_logs(&self) {
+        trace!(message = "Bytes received.", byte_size = %self.byte_size, peer_addr = %self.peer_addr);
+    }
+
+    fn emit_metrics(&self) {
+        counter!("component_received_bytes_total", self.byte_size as u64, "protocol" => "tcp");
+    }
+}
+
+#[derive(Debug)]
+pub struct TcpEventReceived {
+    pub peer_addr: std::net::SocketAddr,
+}
+
+impl InternalEvent for TcpEventReceived {
+    fn emit_logs(&self) {
+        trace!(message = "Event received.", peer_addr = %self.peer_addr);
+    }
+
+    fn emit_metrics(&self) {
+        counter!("component_received_events_total", 1, "protocol" => "tcp");
+    }
+}
+
+#[derive(Debug)]
+pub struct TcpConnectionRejected {
+    pub peer_addr: std::net::SocketAddr,
+}
+
+impl InternalEvent for TcpConnectionRejected {
+    fn emit_logs(&self) {
+        debug!(message = "Rejected connection from peer that isn't allowed.", peer_addr = %self.peer_addr, internal_log_rate_secs = 10);
+    }
+
+    fn emit_metrics(&self) {
+        counter!("connection_rejected_total", 1, "mode" => "tcp");
+    }
+}
+
+#[derive(Debug)]
+pub struct TcpHandshakeTimedOut {
+    pub peer_addr: std::net::SocketAddr,
+    pub timeout: std::time::Duration,
+}
+
+impl InternalEvent for TcpHandshakeTimedOut {
+    fn emit_logs(&self) {
+        debug!(message = "Closing connection that didn't complete the handshake in time.", peer_addr = %self.peer_addr, timeout_secs = ?self.timeout.as_secs(), internal_log_rate_secs = 10);
+    }
+
+    fn emit_metrics(&self) {
+        counter!("connection_handshake_timeouts_total", 1, "mode" => "tcp");
+    }
+}
+
+#[derive(Debug)]
+pub struct TcpConnectionReset<'a, E> {
+    pub error: &'a E,
+    pub peer_addr: std::net::SocketAddr,
+}
+
+impl<'a, E> InternalEvent for TcpConnectionReset<'a, E>
+where
+    E: std::fmt::Display,
+{
+    fn emit_logs(&self) {
+        debug!(message = "Connection reset by peer.", error = %self.error, peer_addr = %self.peer_addr);
+    }
+
+    fn emit_metrics(&self) {
+        counter!("connection_reset_total", 1, "mode" => "tcp");
+    }
+}
+
+#[derive(Debug)]
+pub struct TcpAcceptPaused {
+    pub paused: bool,
+}
+
+impl InternalEvent for TcpAcceptPaused {
+    fn emit_logs(&self) {
+        if self.paused {
+            warn!(message = "Downstream is not keeping up, pausing accepting new connections.");
+        } else {
+            info!(message = "Downstream caught up, resuming accepting new connections.");
+        }
+    }
+
+    fn emit_metrics(&self) {
+        let value = if self.paused { 1.0 } else { 0.0 };
+        gauge!("accept_paused", value, "mode" => "tcp");
+    }
+}
+
+/// The receive buffer size a connection ended up with after requesting `requested` bytes. The
+/// operating system may adjust the request, e.g. Linux doubles it and then clamps it to
+/// `net.core.rmem_max`.
+#[derive(Debug)]
+pub struct TcpReceiveBufferSize {
+    pub requested: usize,
+    pub effective: usize,
+}
+
+impl InternalEvent for TcpReceiveBufferSize {
+    fn emit_logs(&self) {
+        if self.effective < self.requested {
+            warn!(
+                message = "Receive buffer size is smaller than requested, it was likely limited by the operating system.",
+                requested = %self.requested,
+                effective = %self.effective,
+                internal_log_rate_secs = 10
+            );
+        } else {
+            debug!(
+                message = "Configured receive buffer size.",
+                requested = %self.requested,
+                effective = %self.effective,
+            );
+        }
+    }
+
+    fn emit_metrics(&self) {
+        gauge!("connection_receive_buffer_bytes", self.effective as f64, "mode" => "tcp");
+    }
+}
+
+/// Sending events downstream failed, and the connection is either retrying, dropping what failed
+/// to send or closing.
+#[derive(Debug)]
+pub struct TcpSendFailed {
+    pub action: &'static str,
+}
+
+impl InternalEvent for TcpSendFailed {
+    fn emit_logs(&self) {
+        match self.action {
+            "drop" => warn!(
+                message = "Failed sending events downstream, dropping them.",
+                internal_log_rate_secs = 10
+            ),
+            _ => warn!(message = "Failed sending events downstream, closing connection."),
+        }
+    }
+
+    fn emit_metrics(&self) {
+        counter!("downstream_send_errors_total", 1, "mode" => "tcp", "action" => self.action);
+    }
+}
+
+#[derive(Debug)]
+pub struct TcpConnectionLifetimeExceeded {
+    pub peer_addr: std::net::SocketAddr,
+    pub lifetime: std::time::Duration,
+}
+
+impl InternalEvent for TcpConnectionLifetimeExceeded {
+    fn emit_logs(&self) {
+        debug!(
+            message = "Closing connection that reached its maximum lifetime.",
+            peer_addr = %self.peer_addr,
+            lifetime_secs = %self.lifetime.as_secs(),
+            internal_log_rate_secs = 10
+        );
+    }
+
+    fn emit_metrics(&self) {
+        counter!("connection_lifetime_exceeded_total", 1, "mode" => "tcp");
+    }
+}
+
+#[derive(Debug)]
+pub struct TcpDrainTimedOut {
+    pub peer_addr: std::net::SocketAddr,
+    pub dropped_frames: usize,
+}
+
+impl InternalEvent for TcpDrainTimedOut {
+    fn emit_logs(&self) {
+        warn!(
+            message = "Closing connection that didn't hand out its received frames in time after shutdown.",
+            peer_addr = %self.peer_addr,
+            dropped_frames = %self.dropped_frames,
+            internal_log_rate_secs = 10
+        );
+    }
+
+    fn emit_metrics(&self) {
+        counter!(
+            "connection_drain_dropped_frames_total",
+            self.dropped_frames as u64,
+            "mode" => "tcp"
+        );
+    }
+}
+
+#[derive(Debug)]
+pub struct TcpConnectionRateLimited;
+
+impl InternalEvent for TcpConnectionRateLimited {
+    fn emit_logs(&self) {
+        debug!(
+            message =
+                "New connections exceed the maximum connection rate, delaying accepting them.",
+            internal_log_rate_secs = 10
+        );
+    }
+
+    fn emit_metrics(&self) {
+        counter!("connection_rate_limited_total", 1, "mode" => "tcp");
+    }
+}
+
+#[derive(Debug)]
+pub struct TcpConnectionLimitReached {
+    pub peer_addr: std::net::SocketAddr,
+    pub max_connections: u32,
+}
+
+impl InternalEvent for TcpConnectionLimitReached {
+    fn emit_logs(&self) {
+        warn!(
+            message = "Rejecting connection, as the maximum number of connections are open.",
+            peer_addr = %self.peer_addr,
+            max_connections = %self.max_connections,
+            internal_log_rate_secs = 10
+        );
+    }
+
+    fn emit_metrics(&self) {
+        counter!("connection_limit_rejected_total", 1, "mode" => "tcp");
+    }
+}
+
+#[derive(Debug)]
+pub struct TcpConnectionDuration {
+    pub peer_addr: std::net::SocketAddr,
+    pub duration: std::time::Duration,
+}
+
+impl InternalEvent for TcpConnectionDuration {
+    fn emit_logs(&self) {
+        trace!(message = "Connection ended.", peer_addr = %self.peer_addr, duration = ?self.duration);
+    }
+
+    fn emit_metrics(&self) {
+        histogram!("connection_duration_seconds", self.duration, "mode" => "tcp");
+    }
+}
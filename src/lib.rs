@@ -47,6 +47,7 @@ pub mod list;
 pub(crate) mod pipeline;
 pub(crate) mod proto;
 pub mod providers;
+pub mod proxy_protocol;
 #[cfg(feature = "rusoto_core")]
 pub mod rusoto;
 pub mod serde;
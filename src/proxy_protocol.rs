@@ -0,0 +1,220 @@
+//! Parsing of the PROXY protocol header sent by L4 load balancers ahead of the proxied stream.
+//!
+//! https://www.haproxy.org/download/2.4/doc/proxy-protocol.txt
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str,
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// The longest possible v1 header, `PROXY TCP6` with two full IPv6 addresses, including CRLF.
+const V1_MAX_LENGTH: usize = 107;
+
+/// Reads a PROXY protocol v1 or v2 header from the start of `stream`.
+///
+/// Exactly the bytes of the header are consumed, so the proxied stream can be read from
+/// `stream` afterwards. Returns the original client address, or `None` if the header doesn't
+/// carry one (`UNKNOWN` in v1, `LOCAL` commands or unspecified families in v2), in which case
+/// the address of the connection itself should be used.
+pub async fn read_proxy_header<S>(stream: &mut S) -> io::Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    // Shortest valid v1 header is `PROXY UNKNOWN\r\n`, so this never reads past the header.
+    let mut start = [0u8; 12];
+    stream.read_exact(&mut start).await?;
+
+    if start == V2_SIGNATURE {
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header).await?;
+        let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let mut addresses = vec![0u8; length];
+        stream.read_exact(&mut addresses).await?;
+
+        parse_v2(header[0], header[1], &addresses)
+    } else if start.starts_with(V1_PREFIX) {
+        let mut line = start.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LENGTH {
+                return Err(invalid("PROXY protocol v1 header is too long"));
+            }
+            line.push(stream.read_u8().await?);
+        }
+
+        parse_v1(&line[..line.len() - 2])
+    } else {
+        Err(invalid("missing PROXY protocol header"))
+    }
+}
+
+fn parse_v1(line: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let line =
+        str::from_utf8(line).map_err(|_| invalid("PROXY protocol v1 header is not ASCII"))?;
+    let fields = line.split(' ').collect::<Vec<_>>();
+
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", protocol, source, _destination, source_port, _destination_port] => {
+            let ip = source
+                .parse::<IpAddr>()
+                .map_err(|_| invalid("invalid source address in PROXY protocol v1 header"))?;
+            let port = source_port
+                .parse::<u16>()
+                .map_err(|_| invalid("invalid source port in PROXY protocol v1 header"))?;
+
+            match (*protocol, ip) {
+                ("TCP4", IpAddr::V4(_)) | ("TCP6", IpAddr::V6(_)) => {
+                    Ok(Some(SocketAddr::new(ip, port)))
+                }
+                _ => Err(invalid(
+                    "protocol doesn't match address in PROXY protocol v1 header",
+                )),
+            }
+        }
+        _ => Err(invalid("malformed PROXY protocol v1 header")),
+    }
+}
+
+fn parse_v2(version_command: u8, family: u8, addresses: &[u8]) -> io::Result<Option<SocketAddr>> {
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+
+    match version_command & 0x0F {
+        // LOCAL, the connection was opened by the proxy itself, e.g. for health checks.
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => {}
+        _ => return Err(invalid("unsupported PROXY protocol v2 command")),
+    }
+
+    match family >> 4 {
+        // AF_INET
+        0x1 if addresses.len() >= 12 => {
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // AF_INET6
+        0x2 if addresses.len() >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&addresses[..16]);
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port)))
+        }
+        // AF_UNSPEC and AF_UNIX don't carry an address we could use.
+        0x0 | 0x3 => Ok(None),
+        _ => Err(invalid("malformed PROXY protocol v2 address block")),
+    }
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn read(mut input: &[u8]) -> (io::Result<Option<SocketAddr>>, &[u8]) {
+        let result = read_proxy_header(&mut input).await;
+        (result, input)
+    }
+
+    #[tokio::test]
+    async fn v1_tcp4() {
+        let (addr, rest) = read(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nhello").await;
+        assert_eq!(addr.unwrap(), Some("192.168.0.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"hello");
+    }
+
+    #[tokio::test]
+    async fn v1_tcp6() {
+        let (addr, rest) = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 24224\r\n").await;
+        assert_eq!(addr.unwrap(), Some("[2001:db8::1]:4000".parse().unwrap()));
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn v1_unknown() {
+        let (addr, rest) = read(b"PROXY UNKNOWN\r\nhello").await;
+        assert_eq!(addr.unwrap(), None);
+        assert_eq!(rest, b"hello");
+    }
+
+    #[tokio::test]
+    async fn v1_malformed() {
+        let (addr, _) = read(b"PROXY TCP4 192.168.0.1 56324\r\n").await;
+        assert_eq!(addr.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let (addr, _) = read(b"PROXY TCP6 192.168.0.1 192.168.0.11 56324 443\r\n").await;
+        assert_eq!(addr.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut too_long = b"PROXY TCP4 ".to_vec();
+        too_long.resize(200, b'1');
+        let (addr, _) = read(&too_long).await;
+        assert_eq!(addr.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn v2_tcp4() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x11, 0, 12]);
+        header.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2, 0x1f, 0x90, 0x5e, 0xe0]);
+        header.extend_from_slice(b"hello");
+
+        let (addr, rest) = read(&header).await;
+        assert_eq!(addr.unwrap(), Some("10.0.0.1:8080".parse().unwrap()));
+        assert_eq!(rest, b"hello");
+    }
+
+    #[tokio::test]
+    async fn v2_tcp6_with_tlvs() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x21, 0, 40]);
+        header.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        header.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        header.extend_from_slice(&[0x1f, 0x90, 0x5e, 0xe0]);
+        // NOOP TLV
+        header.extend_from_slice(&[0x04, 0, 1, 0]);
+
+        let (addr, rest) = read(&header).await;
+        assert_eq!(addr.unwrap(), Some("[2001:db8::1]:8080".parse().unwrap()));
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn v2_local() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x20, 0x00, 0, 0]);
+
+        let (addr, _) = read(&header).await;
+        assert_eq!(addr.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn v2_malformed() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x11, 0, 4]);
+        header.extend_from_slice(&[10, 0, 0, 1]);
+
+        let (addr, _) = read(&header).await;
+        assert_eq!(addr.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x11, 0x11, 0, 0]);
+
+        let (addr, _) = read(&header).await;
+        assert_eq!(addr.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn missing_header() {
+        let (addr, _) = read(b"\x93\xa8tag.name\xce\x55\xec\xe6\xf8").await;
+        assert_eq!(addr.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,32 +1,464 @@
-use super::util::{SocketListenAddr, TcpIsErrorFatal, TcpSource};
+use super::util::{
+    take_systemd_udp_socket, SocketListenAddr, TcpBufferBudget, TcpIsErrorFatal,
+    TcpOnConnectionLimit, TcpOnSendError, TcpSource, TcpSourceOptions,
+};
 use crate::{
     config::{
         log_schema, DataType, GenerateConfig, Resource, SourceConfig, SourceContext,
         SourceDescription,
     },
     event::{Event, LogEvent, Value},
-    internal_events::{FluentMessageDecodeError, FluentMessageReceived},
+    internal_events::{
+        FluentArrayRecordWrapped, FluentBufferedBytes, FluentEntryCountMismatch,
+        FluentEventSampledOut, FluentForwardEntriesDecoded, FluentMalformedTimestamp,
+        FluentMessageDecodeError, FluentMessageDecoded, FluentMessageReceived,
+        FluentMessageReordered, FluentOversizedRecordReceived, FluentPeerHostnameUnresolved,
+        FluentTagEventReceived, FluentTimestampFieldInvalid, FluentTooDeepValueReceived,
+        FluentTrailingGzipDataIgnored, FluentUnexpectedHeartbeat, FluentUnknownCompressionIgnored,
+        FluentUnreadFrames,
+    },
+    shutdown::ShutdownSignal,
     tcp::TcpKeepaliveConfig,
     tls::{MaybeTlsSettings, TlsConfig},
 };
-use bytes::{Buf, Bytes, BytesMut};
-use chrono::{serde::ts_seconds, DateTime, TimeZone, Utc};
-use flate2::read::MultiGzDecoder;
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, TimeZone, Utc};
+use cidr_utils::cidr::IpCidr;
+use flate2::{bufread::GzDecoder, read::MultiGzDecoder};
+use futures::{future::BoxFuture, FutureExt, TryFutureExt};
 use rmp_serde::{decode, Deserializer};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{BTreeMap, VecDeque},
-    convert::TryInto,
-    io::{self, Read},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    convert::{TryFrom, TryInto},
+    io::{self, BufRead, Read},
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
+use tokio::{net::UdpSocket, time::timeout};
 use tokio_util::codec::Decoder;
+use trust_dns_resolver::TokioAsyncResolver;
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct FluentConfig {
-    address: SocketListenAddr,
+    address: FluentAddresses,
     tls: Option<TlsConfig>,
+    #[serde(
+        default = "default_keepalive",
+        deserialize_with = "deserialize_keepalive",
+        serialize_with = "serialize_keepalive"
+    )]
     keepalive: Option<TcpKeepaliveConfig>,
     receive_buffer_bytes: Option<usize>,
+    receive_lowat_bytes: Option<usize>,
+    send_buffer_bytes: Option<usize>,
+    nodelay: Option<bool>,
+    #[serde(default)]
+    proxy_protocol: bool,
+    idle_timeout_secs: Option<u64>,
+    allowed_peers: Option<Vec<String>>,
+    host_key: Option<String>,
+    port_key: Option<String>,
+    source_type_key: Option<String>,
+    tag_key: Option<String>,
+    timestamp_key: Option<String>,
+    chunk_key: Option<String>,
+    listener_label: Option<String>,
+    listener_label_key: Option<String>,
+    #[serde(default)]
+    parse_key_paths: bool,
+    key_remapping: Option<BTreeMap<String, String>>,
+    max_frame_bytes: Option<usize>,
+    max_decompressed_bytes: Option<usize>,
+    max_entry_bytes: Option<usize>,
+    heartbeat_udp: Option<SocketListenAddr>,
+    tls_client_metadata_key: Option<String>,
+    sni_key: Option<String>,
+    connection_metadata_key: Option<String>,
+    max_bytes_per_second: Option<u64>,
+    #[serde(default)]
+    ext_decoders: Vec<FluentExtDecoderConfig>,
+    #[serde(default)]
+    reuse_port: bool,
+    bind_to_device: Option<String>,
+    v6only: Option<bool>,
+    #[serde(default)]
+    strict_entry_count: bool,
+    frame_read_timeout_secs: Option<u64>,
+    first_frame_timeout_secs: Option<u64>,
+    max_connection_lifetime_secs: Option<u64>,
+    byte_size_key: Option<String>,
+    received_timestamp_key: Option<String>,
+    max_decode_errors_per_connection: Option<usize>,
+    #[serde(default)]
+    pause_accept_on_backpressure: bool,
+    #[serde(default)]
+    non_utf8_key_encoding: FluentKeyEncoding,
+    max_pending_frames: Option<usize>,
+    max_record_fields: Option<usize>,
+    #[serde(default)]
+    on_oversized: FluentOversizedRecord,
+    max_value_depth: Option<usize>,
+    #[serde(default)]
+    on_too_deep: FluentTooDeepValue,
+    #[serde(default)]
+    on_key_conflict: FluentKeyConflict,
+    #[serde(default)]
+    lenient_timestamps: bool,
+    global_buffer_bytes: Option<usize>,
+    #[serde(default)]
+    batch_mode: FluentBatchMode,
+    #[serde(default)]
+    force_close_on_shutdown: bool,
+    #[serde(default)]
+    suppress_empty_connections: bool,
+    tls_handshake_timeout_secs: Option<u64>,
+    max_connection_rate: Option<u32>,
+    max_connections: Option<u32>,
+    #[serde(default)]
+    on_connection_limit: TcpOnConnectionLimit,
+    #[serde(default)]
+    on_send_error: TcpOnSendError,
+    max_tag_metric_labels: Option<usize>,
+    #[serde(default)]
+    lenient_decompression: bool,
+    #[serde(default)]
+    lenient_message_order: bool,
+    #[serde(default)]
+    lenient_array_records: bool,
+    array_record_key: Option<String>,
+    #[serde(default)]
+    fallback_uncompressed: bool,
+    #[serde(default)]
+    resolve_peer_hostname: bool,
+    peer_hostname_key: Option<String>,
+    #[serde(default)]
+    framing: FluentFraming,
+    decode_error_sample_bytes: Option<usize>,
+    #[serde(default)]
+    stringify_values: bool,
+    #[serde(default)]
+    emit_decode_errors_as_events: bool,
+    raw_message_key: Option<String>,
+    #[serde(default)]
+    flatten_records: bool,
+    flatten_separator: Option<String>,
+    flatten_max_depth: Option<usize>,
+    sample_rate: Option<f64>,
+    sample_key: Option<String>,
+    timestamp_from_field: Option<String>,
+    raw_timestamp_key: Option<String>,
+    #[serde(default)]
+    large_int_as_float: bool,
+}
+
+/// One or more addresses to listen on.
+#[derive(Clone, Debug, Serialize)]
+#[serde(untagged)]
+enum FluentAddresses {
+    Single(SocketListenAddr),
+    Multiple(Vec<SocketListenAddr>),
+}
+
+// Not derived for an untagged enum, so that the error for an invalid address isn't replaced with
+// a generic one.
+impl<'de> Deserialize<'de> for FluentAddresses {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct FluentAddressesVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for FluentAddressesVisitor {
+            type Value = FluentAddresses;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                formatter.write_str("an address or a list of addresses")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                use serde::de::IntoDeserializer;
+
+                SocketListenAddr::deserialize(value.into_deserializer())
+                    .map(FluentAddresses::Single)
+            }
+
+            fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                Vec::deserialize(serde::de::value::SeqAccessDeserializer::new(seq))
+                    .map(FluentAddresses::Multiple)
+            }
+        }
+
+        deserializer.deserialize_any(FluentAddressesVisitor)
+    }
+}
+
+impl FluentAddresses {
+    fn addresses(&self) -> &[SocketListenAddr] {
+        match self {
+            Self::Single(address) => std::slice::from_ref(address),
+            Self::Multiple(addresses) => addresses,
+        }
+    }
+}
+
+/// Decoding applied to the bytes of msgpack extension values with the given code.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct FluentExtDecoderConfig {
+    code: i8,
+    decoding: FluentExtDecoding,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FluentExtDecoding {
+    Hex,
+    Base64,
+    String,
+    Uuid,
+}
+
+impl FluentExtDecoding {
+    /// None if the bytes can't be decoded this way.
+    fn decode(self, bytes: &[u8]) -> Option<Value> {
+        match self {
+            Self::Hex => Some(hex(bytes).into()),
+            Self::Base64 => Some(base64::encode(bytes).into()),
+            Self::String => Some(String::from_utf8_lossy(bytes).into_owned().into()),
+            Self::Uuid if bytes.len() == 16 => {
+                let hex = hex(bytes);
+                Some(
+                    format!(
+                        "{}-{}-{}-{}-{}",
+                        &hex[..8],
+                        &hex[8..12],
+                        &hex[12..16],
+                        &hex[16..20],
+                        &hex[20..]
+                    )
+                    .into(),
+                )
+            }
+            Self::Uuid => None,
+        }
+    }
+}
+
+/// Encoding of record keys that aren't valid UTF-8.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FluentKeyEncoding {
+    Hex,
+    Base64,
+}
+
+impl Default for FluentKeyEncoding {
+    fn default() -> Self {
+        Self::Hex
+    }
+}
+
+impl FluentKeyEncoding {
+    /// Keys other than strings and binary, such as integers, are formatted as they are in
+    /// nested maps.
+    fn key(self, key: rmpv::Value) -> String {
+        let bytes = match key {
+            rmpv::Value::String(key) => key.into_bytes(),
+            rmpv::Value::Binary(bytes) => bytes,
+            key => return format!("{}", key),
+        };
+        match String::from_utf8(bytes) {
+            Ok(key) => key,
+            Err(error) => match self {
+                Self::Hex => hex(error.as_bytes()),
+                Self::Base64 => base64::encode(error.as_bytes()),
+            },
+        }
+    }
+}
+
+/// What to do with records that have more than `max_record_fields` fields.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FluentOversizedRecord {
+    /// Keep the first `max_record_fields` fields, in the order the client sent them.
+    Truncate,
+    Drop,
+}
+
+impl Default for FluentOversizedRecord {
+    fn default() -> Self {
+        Self::Truncate
+    }
+}
+
+/// What to do with records that have arrays or maps nested more than `max_value_depth` levels deep.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FluentTooDeepValue {
+    /// Replace the arrays and maps below `max_value_depth` with `TOO_DEEP_PLACEHOLDER`.
+    Truncate,
+    Drop,
+}
+
+impl Default for FluentTooDeepValue {
+    fn default() -> Self {
+        Self::Truncate
+    }
+}
+
+/// What arrays and maps nested deeper than `max_value_depth` are replaced with.
+const TOO_DEEP_PLACEHOLDER: &str = "[too deep]";
+
+/// The most levels of arrays and maps record values can be nested in, no matter
+/// `max_value_depth`. Deserializing recurses once per level, so messages with values nested
+/// deeper are rejected before they're deserialized, see `scan_nesting`.
+const MAX_VALUE_DEPTH: usize = 256;
+
+/// Levels of arrays and maps record values are nested in within a message, the most of any mode
+/// being forward mode's `[tag, [[time, {key: value}]]]`.
+const MESSAGE_NESTING: usize = 4;
+
+/// Levels of arrays and maps record values are nested in within a packed entry,
+/// `[time, {key: value}]`.
+const ENTRY_NESTING: usize = 2;
+
+/// Which field is kept when a record key is the same as the key of the host, timestamp or tag.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FluentKeyConflict {
+    ReservedWins,
+    RecordWins,
+    /// Keep both, with the record key renamed to `fluent_<key>`.
+    PrefixRecord,
+}
+
+impl Default for FluentKeyConflict {
+    fn default() -> Self {
+        Self::RecordWins
+    }
+}
+
+/// Whether the entries of forward mode messages become separate events.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FluentBatchMode {
+    PerEntry,
+    /// One event per message, with the entries in a `records` array.
+    PerMessage,
+}
+
+impl Default for FluentBatchMode {
+    fn default() -> Self {
+        Self::PerEntry
+    }
+}
+
+/// How messages are delimited within a connection.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FluentFraming {
+    /// Each message is read up to where its msgpack encoding ends.
+    Msgpack,
+    /// Each message is preceded by its length in bytes, as a 32-bit big-endian integer.
+    LengthDelimited,
+}
+
+impl Default for FluentFraming {
+    fn default() -> Self {
+        Self::Msgpack
+    }
+}
+
+/// The tag of the events for messages that failed to decode, with `emit_decode_errors_as_events`.
+const DEAD_LETTER_TAG: &str = "_fluent_decode_error";
+
+/// How many bytes of the data that failed to decode are logged when `decode_error_sample_bytes`
+/// isn't configured.
+const DEFAULT_DECODE_ERROR_SAMPLE_BYTES: usize = 256;
+
+/// How many entries of a message are decoded at a time when `max_pending_frames` isn't
+/// configured, so that a message with a huge number of entries is decoded a little at a time,
+/// as its events are sent on, rather than all in one go.
+const DEFAULT_MAX_PENDING_FRAMES: usize = 128;
+
+/// The size of the length prefix of messages with `length_delimited` framing.
+const LENGTH_PREFIX_BYTES: usize = 4;
+
+/// The most bytes a length prefix may announce when `max_frame_bytes` isn't configured, so that a
+/// corrupt or hostile prefix can't have up to 4 GiB buffered for a single message.
+const DEFAULT_LENGTH_DELIMITED_MAX_FRAME_BYTES: usize = 64 * 1024 * 1024;
+
+/// Idle time before keepalive probes are sent when `keepalive` isn't configured, so that
+/// connections from forwarders that went away without closing them are eventually dropped.
+const DEFAULT_KEEPALIVE_SECS: u64 = 75;
+
+fn default_keepalive() -> Option<TcpKeepaliveConfig> {
+    Some(TcpKeepaliveConfig {
+        time_secs: Some(DEFAULT_KEEPALIVE_SECS),
+    })
+}
+
+/// `keepalive` as configured for this source, which unlike `TcpKeepaliveConfig` can opt out of
+/// the keepalive it enables by default.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct FluentKeepaliveConfig {
+    #[serde(default = "crate::serde::default_true")]
+    enabled: bool,
+    time_secs: Option<u64>,
+}
+
+fn deserialize_keepalive<'de, D>(deserializer: D) -> Result<Option<TcpKeepaliveConfig>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    let keepalive = FluentKeepaliveConfig::deserialize(deserializer)?;
+    if keepalive.time_secs == Some(0) {
+        return Err(D::Error::custom(
+            "keepalive.time_secs must be greater than zero",
+        ));
+    }
+    if !keepalive.enabled {
+        if keepalive.time_secs.is_some() {
+            return Err(D::Error::custom(
+                "keepalive.time_secs can't be set with keepalive.enabled = false",
+            ));
+        }
+        return Ok(None);
+    }
+    Ok(Some(TcpKeepaliveConfig {
+        time_secs: keepalive.time_secs,
+    }))
+}
+
+fn serialize_keepalive<S>(
+    keepalive: &Option<TcpKeepaliveConfig>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    // Omitting the opt out would enable the default again.
+    FluentKeepaliveConfig {
+        enabled: keepalive.is_some(),
+        time_secs: keepalive.and_then(|keepalive| keepalive.time_secs),
+    }
+    .serialize(serializer)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
 }
 
 inventory::submit! {
@@ -36,10 +468,86 @@ inventory::submit! {
 impl GenerateConfig for FluentConfig {
     fn generate_config() -> toml::Value {
         toml::Value::try_from(Self {
-            address: SocketListenAddr::SocketAddr("0.0.0.0:24224".parse().unwrap()),
-            keepalive: None,
+            address: FluentAddresses::Single(SocketListenAddr::SocketAddr(
+                "0.0.0.0:24224".parse().unwrap(),
+            )),
+            keepalive: default_keepalive(),
             tls: None,
             receive_buffer_bytes: None,
+            receive_lowat_bytes: None,
+            send_buffer_bytes: None,
+            nodelay: None,
+            proxy_protocol: false,
+            idle_timeout_secs: None,
+            allowed_peers: None,
+            host_key: None,
+            port_key: None,
+            source_type_key: None,
+            tag_key: None,
+            timestamp_key: None,
+            chunk_key: None,
+            listener_label: None,
+            listener_label_key: None,
+            parse_key_paths: false,
+            key_remapping: None,
+            max_frame_bytes: None,
+            max_decompressed_bytes: None,
+            max_entry_bytes: None,
+            heartbeat_udp: None,
+            tls_client_metadata_key: None,
+            sni_key: None,
+            connection_metadata_key: None,
+            max_bytes_per_second: None,
+            ext_decoders: Vec::new(),
+            reuse_port: false,
+            bind_to_device: None,
+            v6only: None,
+            strict_entry_count: false,
+            frame_read_timeout_secs: None,
+            first_frame_timeout_secs: None,
+            max_connection_lifetime_secs: None,
+            byte_size_key: None,
+            received_timestamp_key: None,
+            max_decode_errors_per_connection: None,
+            pause_accept_on_backpressure: false,
+            non_utf8_key_encoding: FluentKeyEncoding::Hex,
+            max_pending_frames: None,
+            max_record_fields: None,
+            on_oversized: FluentOversizedRecord::Truncate,
+            max_value_depth: None,
+            on_too_deep: FluentTooDeepValue::Truncate,
+            on_key_conflict: FluentKeyConflict::RecordWins,
+            lenient_timestamps: false,
+            global_buffer_bytes: None,
+            batch_mode: FluentBatchMode::PerEntry,
+            force_close_on_shutdown: false,
+            suppress_empty_connections: false,
+            tls_handshake_timeout_secs: None,
+            max_connection_rate: None,
+            max_connections: None,
+            on_connection_limit: TcpOnConnectionLimit::Queue,
+            on_send_error: TcpOnSendError::CloseConnection,
+            max_tag_metric_labels: None,
+            lenient_decompression: false,
+            lenient_message_order: false,
+            lenient_array_records: false,
+            array_record_key: None,
+            fallback_uncompressed: false,
+            resolve_peer_hostname: false,
+            peer_hostname_key: None,
+            framing: FluentFraming::Msgpack,
+            decode_error_sample_bytes: None,
+            stringify_values: false,
+            emit_decode_errors_as_events: false,
+            raw_message_key: None,
+            flatten_records: false,
+            flatten_separator: None,
+            flatten_max_depth: None,
+            sample_rate: None,
+            sample_key: None,
+            timestamp_from_field: None,
+            raw_timestamp_key: None,
+            large_int_as_float: false,
         })
         .unwrap()
     }
@@ -49,18 +557,154 @@ impl GenerateConfig for FluentConfig {
 #[typetag::serde(name = "fluent")]
 impl SourceConfig for FluentConfig {
     async fn build(&self, cx: SourceContext) -> crate::Result<super::Source> {
-        let source = FluentSource {};
+        let mut source = FluentSource::new(self);
+        if self.resolve_peer_hostname {
+            let resolver = TokioAsyncResolver::tokio_from_system_conf()?;
+            source.peer_hostnames = Some(Arc::new(PeerHostnames::new(
+                self.peer_hostname_key
+                    .clone()
+                    .unwrap_or_else(|| "peer_hostname".to_owned()),
+                move |ip| {
+                    let resolver = resolver.clone();
+                    async move {
+                        let names = resolver.reverse_lookup(ip).await.ok()?;
+                        let name = names.iter().next()?.to_utf8();
+                        Some(name.trim_end_matches('.').to_owned())
+                    }
+                    .boxed()
+                },
+            )));
+        }
         let shutdown_secs = 30;
         let tls = MaybeTlsSettings::from_config(&self.tls, true)?;
-        source.run(
-            self.address,
-            self.keepalive,
-            shutdown_secs,
-            tls,
-            self.receive_buffer_bytes,
-            cx.shutdown,
-            cx.out,
-        )
+        let allowed_peers = self
+            .allowed_peers
+            .as_ref()
+            .map(|peers| {
+                peers
+                    .iter()
+                    .map(|peer| {
+                        IpCidr::from_str(peer).map_err(|error| {
+                            format!("Invalid CIDR {:?} in allowed_peers: {}", peer, error)
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?;
+        let heartbeat = match self.heartbeat_udp {
+            Some(SocketListenAddr::SocketAddr(address)) => Some(heartbeat_udp(
+                UdpSocket::bind(address).await?,
+                cx.shutdown.clone(),
+            )),
+            Some(SocketListenAddr::SystemdFd(offset)) => Some(heartbeat_udp(
+                take_systemd_udp_socket(offset)?,
+                cx.shutdown.clone(),
+            )),
+            None => None,
+        };
+        if self.address.addresses().is_empty() {
+            return Err("At least one address is required.".into());
+        }
+        if self.bind_to_device.is_some()
+            && !cfg!(any(
+                target_os = "android",
+                target_os = "fuchsia",
+                target_os = "linux"
+            ))
+        {
+            return Err("`bind_to_device` is only supported on Linux.".into());
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            if !(0.0..=1.0).contains(&sample_rate) {
+                return Err("`sample_rate` must be between 0.0 and 1.0.".into());
+            }
+        }
+        // The field would replace a timestamp that isn't inserted.
+        if self.timestamp_from_field.is_some() && self.timestamp_key.as_deref() == Some("") {
+            return Err("`timestamp_from_field` requires `timestamp_key` to be set.".into());
+        }
+        if let Some(max_value_depth) = self.max_value_depth {
+            if max_value_depth > MAX_VALUE_DEPTH {
+                return Err(
+                    format!("`max_value_depth` can be at most {}.", MAX_VALUE_DEPTH).into(),
+                );
+            }
+        }
+        if let (Some(global_buffer_bytes), Some(max_frame_bytes)) =
+            (self.global_buffer_bytes, self.max_frame_bytes)
+        {
+            // A frame that doesn't fit in the budget could never be decoded.
+            if global_buffer_bytes < max_frame_bytes {
+                return Err("`global_buffer_bytes` must be at least `max_frame_bytes`.".into());
+            }
+        }
+        match self.keepalive {
+            Some(TcpKeepaliveConfig {
+                time_secs: Some(time_secs),
+            }) => info!(message = "TCP keepalive enabled.", time_secs),
+            Some(TcpKeepaliveConfig { time_secs: None }) => {
+                info!(message = "TCP keepalive left to the operating system setting.")
+            }
+            None => info!(message = "TCP keepalive disabled."),
+        }
+        // Shared by the connections on all addresses.
+        let buffer_budget = self
+            .global_buffer_bytes
+            .map(|max_bytes| Arc::new(TcpBufferBudget::new(max_bytes)));
+        let listeners = self
+            .address
+            .addresses()
+            .iter()
+            .map(|address| {
+                source.clone().run(
+                    *address,
+                    self.keepalive,
+                    shutdown_secs,
+                    tls.clone(),
+                    self.receive_buffer_bytes,
+                    TcpSourceOptions {
+                        receive_lowat_bytes: self.receive_lowat_bytes,
+                        send_buffer_bytes: self.send_buffer_bytes,
+                        nodelay: self.nodelay,
+                        proxy_protocol: self.proxy_protocol,
+                        idle_timeout_secs: self.idle_timeout_secs,
+                        allowed_peers: allowed_peers.clone(),
+                        tls_client_metadata_key: self.tls_client_metadata_key.clone(),
+                        sni_key: self.sni_key.clone(),
+                        connection_metadata_key: self.connection_metadata_key.clone(),
+                        max_bytes_per_second: self.max_bytes_per_second,
+                        reuse_port: self.reuse_port,
+                        bind_to_device: self.bind_to_device.clone(),
+                        v6only: self.v6only,
+                        frame_read_timeout_secs: self.frame_read_timeout_secs,
+                        first_frame_timeout_secs: self.first_frame_timeout_secs,
+                        max_connection_lifetime_secs: self.max_connection_lifetime_secs,
+                        pause_accept_on_backpressure: self.pause_accept_on_backpressure,
+                        buffer_budget: buffer_budget.clone(),
+                        force_close_on_shutdown: self.force_close_on_shutdown,
+                        suppress_empty_connections: self.suppress_empty_connections,
+                        tls_handshake_timeout_secs: self.tls_handshake_timeout_secs,
+                        max_connection_rate: self.max_connection_rate,
+                        max_connections: self.max_connections,
+                        on_connection_limit: self.on_connection_limit,
+                        on_send_error: self.on_send_error,
+                    },
+                    cx.shutdown.clone(),
+                    cx.out.clone(),
+                )
+            })
+            .collect::<crate::Result<Vec<_>>>()?;
+        // The source fails as a whole as soon as any of the listeners does.
+        let source: super::Source =
+            Box::pin(futures::future::try_join_all(listeners).map_ok(|_| ()));
+
+        Ok(match heartbeat {
+            Some(heartbeat) => Box::pin(async move {
+                let (result, _) = futures::join!(source, heartbeat);
+                result
+            }),
+            None => source,
+        })
     }
 
     fn output_type(&self) -> DataType {
@@ -72,480 +716,5851 @@ impl SourceConfig for FluentConfig {
     }
 
     fn resources(&self) -> Vec<Resource> {
-        vec![self.address.into()]
+        let mut resources = self
+            .address
+            .addresses()
+            .iter()
+            .copied()
+            .map(Resource::from)
+            .collect::<Vec<_>>();
+        resources.extend(self.heartbeat_udp.map(|address| match address {
+            SocketListenAddr::SocketAddr(address) => Resource::udp(address),
+            SocketListenAddr::SystemdFd(offset) => Resource::SystemFdOffset(offset),
+        }));
+        resources
     }
 }
 
-#[derive(Debug, Clone)]
-struct FluentSource;
+/// Answers the UDP heartbeats of the forward protocol, echoing each single `0x00` byte back to its
+/// sender. Anything else is ignored.
+async fn heartbeat_udp(socket: UdpSocket, mut shutdown: ShutdownSignal) {
+    // Longer datagrams are truncated to the buffer, so they can't be mistaken for a heartbeat.
+    let mut buf = [0u8; 2];
+    loop {
+        tokio::select! {
+            result = socket.recv_from(&mut buf) => match result {
+                Ok((1, peer_addr)) if buf[0] == 0 => {
+                    if let Err(error) = socket.send_to(&buf[..1], peer_addr).await {
+                        debug!(message = "Failed to answer UDP heartbeat.", %peer_addr, %error);
+                    }
+                }
+                Ok(_) => {}
+                Err(error) => {
+                    debug!(message = "Failed to receive UDP heartbeat.", %error);
+                }
+            },
+            _ = &mut shutdown => break,
+        }
+    }
+}
 
-impl TcpSource for FluentSource {
-    type Error = DecodeError;
-    type Decoder = FluentDecoder;
+#[derive(Debug, Clone)]
+struct FluentSource {
+    host_key: Option<String>,
+    port_key: Option<String>,
+    source_type_key: Option<String>,
+    tag_key: String,
+    timestamp_key: Option<String>,
+    chunk_key: Option<String>,
+    // key and label
+    listener_label: Option<(String, String)>,
+    parse_key_paths: bool,
+    // record keys to rename, and what to
+    key_remapping: BTreeMap<String, String>,
+    max_frame_bytes: Option<usize>,
+    max_decompressed_bytes: Option<usize>,
+    max_entry_bytes: Option<usize>,
+    ext_decoders: BTreeMap<i8, FluentExtDecoding>,
+    strict_entry_count: bool,
+    byte_size_key: Option<String>,
+    received_timestamp_key: Option<String>,
+    max_decode_errors_per_connection: Option<usize>,
+    non_utf8_key_encoding: FluentKeyEncoding,
+    max_pending_frames: usize,
+    max_record_fields: Option<usize>,
+    on_oversized: FluentOversizedRecord,
+    max_value_depth: Option<usize>,
+    on_too_deep: FluentTooDeepValue,
+    on_key_conflict: FluentKeyConflict,
+    lenient_timestamps: bool,
+    batch_mode: FluentBatchMode,
+    tag_labels: Arc<TagLabels>,
+    lenient_decompression: bool,
+    lenient_message_order: bool,
+    // key to wrap records that are arrays under, with `lenient_array_records`
+    array_record_key: Option<String>,
+    fallback_uncompressed: bool,
+    framing: FluentFraming,
+    decode_error_sample_bytes: usize,
+    stringify_values: bool,
+    emit_decode_errors_as_events: bool,
+    raw_message_key: Option<String>,
+    // separator and the most levels of nested maps that are flattened
+    flatten_records: Option<(String, Option<usize>)>,
+    // fraction of events kept, and the field to sample them consistently by
+    sample: Option<(f64, Option<String>)>,
+    timestamp_from_field: Option<String>,
+    raw_timestamp_key: Option<String>,
+    large_int_as_float: bool,
+    // only set up when the source is built, as looking up hostnames requires a listener
+    peer_hostnames: Option<Arc<PeerHostnames>>,
+}
 
-    fn decoder(&self) -> Self::Decoder {
-        FluentDecoder::new()
-    }
+/// How many distinct tags `fluent_events_received_total` is labeled with when
+/// `max_tag_metric_labels` isn't configured.
+const DEFAULT_MAX_TAG_METRIC_LABELS: usize = 100;
 
-    fn build_event(&self, frame: FluentFrame, host: Bytes) -> Option<Event> {
-        let mut log = LogEvent::from(frame);
+/// The tags `fluent_events_received_total` has been labeled with, shared by all connections so
+/// that the number of distinct labels stays bounded however many tags clients send.
+#[derive(Debug)]
+struct TagLabels {
+    max_labels: usize,
+    seen: Mutex<HashSet<String>>,
+}
 
-        if !log.contains(log_schema().host_key()) {
-            log.insert(log_schema().host_key(), host);
+impl TagLabels {
+    fn new(max_labels: usize) -> Self {
+        Self {
+            max_labels,
+            seen: Mutex::new(HashSet::new()),
         }
+    }
 
-        Some(Event::from(log))
+    /// The tag itself if it's already a label or there's room for another, and `other` otherwise.
+    fn label<'a>(&self, tag: &'a str) -> &'a str {
+        let mut seen = self.seen.lock().unwrap();
+        if seen.contains(tag) || (seen.len() < self.max_labels && seen.insert(tag.to_owned())) {
+            tag
+        } else {
+            "other"
+        }
     }
 }
 
-#[derive(Debug)]
-pub enum DecodeError {
-    IO(io::Error),
-    Decode(decode::Error),
-    UnknownCompression(String),
-    UnexpectedValue(rmpv::Value),
+/// How long looking up the hostname of a peer may hold up reading from its connection.
+const PEER_HOSTNAME_LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long the hostname of a peer is cached, including when looking it up failed.
+const PEER_HOSTNAME_TTL: Duration = Duration::from_secs(300);
+
+type PeerHostnameLookup = dyn Fn(IpAddr) -> BoxFuture<'static, Option<String>> + Send + Sync;
+
+/// Hostnames of peers looked up with reverse DNS, cached so that clients that reconnect often
+/// don't cause a lookup for every connection.
+struct PeerHostnames {
+    key: String,
+    lookup: Box<PeerHostnameLookup>,
+    // `None` for addresses that have no hostname, or whose lookup failed or timed out
+    cache: Mutex<HashMap<IpAddr, (Option<String>, Instant)>>,
 }
 
-impl std::fmt::Display for DecodeError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            DecodeError::IO(err) => write!(f, "{}", err),
-            DecodeError::Decode(err) => write!(f, "{}", err),
-            DecodeError::UnknownCompression(compression) => {
-                write!(f, "unknown compression: {}", compression)
-            }
-            DecodeError::UnexpectedValue(value) => {
-                write!(f, "unexpected msgpack value, ignoring: {}", value)
-            }
+impl PeerHostnames {
+    fn new(
+        key: String,
+        lookup: impl Fn(IpAddr) -> BoxFuture<'static, Option<String>> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            key,
+            lookup: Box::new(lookup),
+            cache: Mutex::new(HashMap::new()),
         }
     }
-}
 
-impl TcpIsErrorFatal for DecodeError {
-    fn is_error_fatal(&self) -> bool {
-        match self {
-            DecodeError::IO(_) => true,
-            DecodeError::Decode(_) => false,
-            DecodeError::UnknownCompression(_) => false,
-            DecodeError::UnexpectedValue(_) => false,
+    fn cached(&self, ip: IpAddr) -> Option<Option<String>> {
+        self.cache
+            .lock()
+            .unwrap()
+            .get(&ip)
+            .filter(|(_, resolved_at)| resolved_at.elapsed() < PEER_HOSTNAME_TTL)
+            .map(|(hostname, _)| hostname.clone())
+    }
+
+    /// Looks up the hostname of `ip`, unless it's cached already.
+    async fn resolve(self: Arc<Self>, ip: IpAddr) {
+        if self.cached(ip).is_some() {
+            return;
+        }
+        let hostname = timeout(PEER_HOSTNAME_LOOKUP_TIMEOUT, (self.lookup)(ip))
+            .await
+            .unwrap_or(None);
+        if hostname.is_none() {
+            emit!(FluentPeerHostnameUnresolved { ip });
         }
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (_, resolved_at)| resolved_at.elapsed() < PEER_HOSTNAME_TTL);
+        cache.insert(ip, (hostname, Instant::now()));
     }
-}
 
-impl From<io::Error> for DecodeError {
-    fn from(e: io::Error) -> Self {
-        DecodeError::IO(e)
+    /// The hostname of the peer, or its address if it doesn't have one.
+    fn hostname(&self, peer_addr: SocketAddr) -> String {
+        self.cached(peer_addr.ip())
+            .flatten()
+            .unwrap_or_else(|| host(peer_addr))
     }
 }
 
-impl From<decode::Error> for DecodeError {
-    fn from(e: decode::Error) -> Self {
-        DecodeError::Decode(e)
+impl std::fmt::Debug for PeerHostnames {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PeerHostnames")
+            .field("key", &self.key)
+            .field("cache", &self.cache)
+            .finish()
     }
 }
 
-#[derive(Debug)]
-struct FluentDecoder {
-    // unread frames from previous fluent message
-    unread_frames: VecDeque<FluentFrame>,
-}
+impl FluentSource {
+    fn new(config: &FluentConfig) -> Self {
+        // An empty key disables adding the host.
+        let host_key = match config.host_key.as_deref() {
+            None => Some(log_schema().host_key().to_owned()),
+            Some("") => None,
+            Some(host_key) => Some(host_key.to_owned()),
+        };
+        // Likewise for the source type.
+        let source_type_key = match config.source_type_key.as_deref() {
+            None => Some(log_schema().source_type_key().to_owned()),
+            Some("") => None,
+            Some(source_type_key) => Some(source_type_key.to_owned()),
+        };
+        // Likewise for the message timestamp.
+        let timestamp_key = match config.timestamp_key.as_deref() {
+            None => Some(log_schema().timestamp_key().to_owned()),
+            Some("") => None,
+            Some(timestamp_key) => Some(timestamp_key.to_owned()),
+        };
 
-impl FluentDecoder {
-    fn new() -> Self {
-        FluentDecoder {
-            unread_frames: VecDeque::new(),
+        Self {
+            host_key,
+            port_key: config.port_key.clone(),
+            source_type_key,
+            tag_key: config.tag_key.clone().unwrap_or_else(|| "tag".to_owned()),
+            timestamp_key,
+            chunk_key: config.chunk_key.clone(),
+            listener_label: config.listener_label.clone().map(|label| {
+                let key = config
+                    .listener_label_key
+                    .clone()
+                    .unwrap_or_else(|| "listener".to_owned());
+                (key, label)
+            }),
+            parse_key_paths: config.parse_key_paths,
+            key_remapping: config.key_remapping.clone().unwrap_or_default(),
+            max_frame_bytes: match config.framing {
+                FluentFraming::LengthDelimited => Some(
+                    config
+                        .max_frame_bytes
+                        .unwrap_or(DEFAULT_LENGTH_DELIMITED_MAX_FRAME_BYTES),
+                ),
+                FluentFraming::Msgpack => config.max_frame_bytes,
+            },
+            max_decompressed_bytes: config.max_decompressed_bytes,
+            max_entry_bytes: config.max_entry_bytes,
+            strict_entry_count: config.strict_entry_count,
+            byte_size_key: config.byte_size_key.clone(),
+            received_timestamp_key: config.received_timestamp_key.clone(),
+            max_decode_errors_per_connection: config.max_decode_errors_per_connection,
+            non_utf8_key_encoding: config.non_utf8_key_encoding,
+            max_pending_frames: config
+                .max_pending_frames
+                .unwrap_or(DEFAULT_MAX_PENDING_FRAMES),
+            max_record_fields: config.max_record_fields,
+            on_oversized: config.on_oversized,
+            max_value_depth: config.max_value_depth,
+            on_too_deep: config.on_too_deep,
+            on_key_conflict: config.on_key_conflict,
+            lenient_timestamps: config.lenient_timestamps,
+            batch_mode: config.batch_mode,
+            lenient_decompression: config.lenient_decompression,
+            lenient_message_order: config.lenient_message_order,
+            array_record_key: if config.lenient_array_records {
+                Some(
+                    config
+                        .array_record_key
+                        .clone()
+                        .unwrap_or_else(|| log_schema().message_key().to_owned()),
+                )
+            } else {
+                None
+            },
+            fallback_uncompressed: config.fallback_uncompressed,
+            framing: config.framing,
+            decode_error_sample_bytes: config
+                .decode_error_sample_bytes
+                .unwrap_or(DEFAULT_DECODE_ERROR_SAMPLE_BYTES),
+            stringify_values: config.stringify_values,
+            emit_decode_errors_as_events: config.emit_decode_errors_as_events,
+            raw_message_key: config.raw_message_key.clone(),
+            flatten_records: if config.flatten_records {
+                let separator = config
+                    .flatten_separator
+                    .clone()
+                    .unwrap_or_else(|| ".".to_owned());
+                Some((separator, config.flatten_max_depth))
+            } else {
+                None
+            },
+            sample: config
+                .sample_rate
+                .map(|rate| (rate, config.sample_key.clone())),
+            timestamp_from_field: config.timestamp_from_field.clone(),
+            raw_timestamp_key: config.raw_timestamp_key.clone(),
+            large_int_as_float: config.large_int_as_float,
+            peer_hostnames: None,
+            tag_labels: Arc::new(TagLabels::new(
+                config
+                    .max_tag_metric_labels
+                    .unwrap_or(DEFAULT_MAX_TAG_METRIC_LABELS),
+            )),
+            // Later entries for the same code take precedence.
+            ext_decoders: config
+                .ext_decoders
+                .iter()
+                .map(|decoder| (decoder.code, decoder.decoding))
+                .collect(),
         }
     }
 
-    fn handle_message(&mut self, message: FluentMessage) -> Result<(), DecodeError> {
-        match message {
-            FluentMessage::Message(tag, timestamp, record)
-            | FluentMessage::MessageWithOptions(tag, timestamp, record, ..) => {
-                self.unread_frames.push_back(FluentFrame {
-                    tag,
-                    timestamp,
-                    record,
-                });
-                Ok(())
-            }
-            FluentMessage::Forward(tag, entries)
-            | FluentMessage::ForwardWithOptions(tag, entries, ..) => {
-                self.unread_frames.extend(entries.into_iter().map(
-                    |FluentEntry(timestamp, record)| FluentFrame {
-                        tag: tag.clone(),
-                        timestamp,
-                        record,
-                    },
-                ));
-                Ok(())
-            }
-            FluentMessage::PackedForward(tag, bin) => {
-                let mut buf = BytesMut::from(&bin[..]);
+    /// Whether `key` is that of the host, timestamp or tag of events.
+    fn is_reserved_key(&self, key: &str) -> bool {
+        key == self.tag_key
+            || self.timestamp_key.as_deref() == Some(key)
+            || self.host_key.as_deref() == Some(key)
+    }
 
-                let mut decoder = FluentEntryStreamDecoder;
+    /// Record fields are inserted last, so which of them and the timestamp and tag is kept when
+    /// their keys collide is up to `on_key_conflict`. With `parse_key_paths`, record keys are
+    /// inserted as paths in key order, so of two colliding keys such as `a` and `a.b`, the later
+    /// one overwrites the other.
+    fn log_from_frame(&self, frame: FluentFrame) -> LogEvent {
+        let FluentFrame {
+            tag,
+            timestamp,
+            record,
+            entries,
+            ..
+        } = frame;
 
-                while let Some(FluentEntry(timestamp, record)) = decoder.decode(&mut buf)? {
-                    self.unread_frames.push_back(FluentFrame {
-                        tag: tag.clone(),
-                        timestamp,
-                        record,
-                    });
-                }
-                Ok(())
-            }
-            FluentMessage::PackedForwardWithOptions(tag, bin, options) => {
-                let buf = match options.compressed.as_deref() {
-                    Some("gzip") => {
-                        let mut buf = Vec::new();
-                        MultiGzDecoder::new(io::Cursor::new(bin.into_vec()))
-                            .read_to_end(&mut buf)
-                            .map(|_| buf)
-                            .map_err(Into::into)
+        let mut log = LogEvent::default();
+        self.insert_raw_timestamp(&mut log, &timestamp);
+        if let Some(timestamp_key) = &self.timestamp_key {
+            log.insert(timestamp_key.as_str(), timestamp);
+        }
+        log.insert(self.tag_key.as_str(), &*tag);
+        self.insert_record(&mut log, record);
+        self.timestamp_from_field(&mut log);
+
+        if !entries.is_empty() {
+            let records = entries
+                .into_iter()
+                .map(|FluentEntry(timestamp, record)| {
+                    let mut entry = LogEvent::default();
+                    self.insert_raw_timestamp(&mut entry, &timestamp);
+                    if let Some(timestamp_key) = &self.timestamp_key {
+                        entry.insert(timestamp_key.as_str(), timestamp);
                     }
-                    Some("text") | None => Ok(bin.into_vec()),
-                    Some(s) => Err(DecodeError::UnknownCompression(s.to_owned())),
-                }?;
-
-                let mut buf = BytesMut::from(&buf[..]);
+                    self.insert_record(&mut entry, record);
+                    self.timestamp_from_field(&mut entry);
+                    Value::Map(entry.into())
+                })
+                .collect::<Vec<_>>();
+            log.insert("records", records);
+        }
+        log
+    }
 
-                let mut decoder = FluentEntryStreamDecoder;
+    fn insert_raw_timestamp(&self, log: &mut LogEvent, timestamp: &FluentTimestamp) {
+        if let (Some(raw_timestamp_key), Some(raw)) = (&self.raw_timestamp_key, timestamp.raw()) {
+            log.insert(raw_timestamp_key.as_str(), raw);
+        }
+    }
 
-                while let Some(FluentEntry(timestamp, record)) = decoder.decode(&mut buf)? {
-                    self.unread_frames.push_back(FluentFrame {
-                        tag: tag.clone(),
-                        timestamp,
-                        record,
-                    });
+    /// Replaces the timestamp of `log` with its `timestamp_from_field` field, when that parses as
+    /// a timestamp, leaving the message timestamp otherwise.
+    fn timestamp_from_field(&self, log: &mut LogEvent) {
+        if let (Some(field), Some(timestamp_key)) =
+            (&self.timestamp_from_field, &self.timestamp_key)
+        {
+            let timestamp = match log.get(field.as_str()) {
+                Some(value) => parse_timestamp_field(value),
+                None => return,
+            };
+            match timestamp {
+                Some(timestamp) => {
+                    log.insert(timestamp_key.as_str(), timestamp);
                 }
-                Ok(())
+                None => emit!(FluentTimestampFieldInvalid { field }),
             }
-            FluentMessage::Heartbeat(rmpv::Value::Nil) => Ok(()),
-            FluentMessage::Heartbeat(value) => Err(DecodeError::UnexpectedValue(value)),
         }
     }
-}
-
-impl Decoder for FluentDecoder {
-    type Item = FluentFrame;
-    type Error = DecodeError;
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        if let Some(frame) = self.unread_frames.pop_front() {
-            return Ok(Some(frame));
+    /// Keys are renamed according to `key_remapping` before anything else, so a renamed field
+    /// can avoid a conflict with the timestamp or tag, and wins over a field that already has
+    /// its new name.
+    fn insert_record(&self, log: &mut LogEvent, record: FluentRecord) {
+        let mut renamed = HashSet::new();
+        let mut fields = BTreeMap::new();
+        let keys_and_values = record.0.into_iter().filter_map(|(key, value)| {
+            let key = self.non_utf8_key_encoding.key(key);
+            let (key, is_renamed) = match self.key_remapping.get(&key) {
+                Some(new_key) => (new_key.clone(), true),
+                None => (key, false),
+            };
+            let key = if !self.is_reserved_key(&key) {
+                key
+            } else {
+                match self.on_key_conflict {
+                    FluentKeyConflict::ReservedWins => return None,
+                    FluentKeyConflict::RecordWins => key,
+                    FluentKeyConflict::PrefixRecord => format!("fluent_{}", key),
+                }
+            };
+            Some((key, value, is_renamed))
+        });
+        for (key, value, is_renamed) in keys_and_values {
+            if is_renamed {
+                renamed.insert(key.clone());
+            } else if renamed.contains(&key) {
+                continue;
+            }
+            fields.insert(key, value);
+        }
+        let mut flattened = Vec::new();
+        for (key, value) in fields.into_iter() {
+            let value = value.into_value(&self.ext_decoders, self.large_int_as_float);
+            match &self.flatten_records {
+                Some((separator, max_depth)) => {
+                    flatten_value(&mut flattened, key, value, separator, *max_depth)
+                }
+                None => flattened.push((key, value)),
+            }
         }
+        for (key, value) in flattened {
+            let value = match value {
+                // Nulls are left as they are, being just as easy for sinks to handle as strings.
+                value @ Value::Null => value,
+                // Maps and arrays are rendered as JSON.
+                value if self.stringify_values => Value::Bytes(value.into_bytes()),
+                value => value,
+            };
+            // Flattened keys are meant to stay flat, whatever separator they were joined with.
+            if self.parse_key_paths && self.flatten_records.is_none() {
+                log.insert(key, value);
+            } else {
+                log.insert_flat(key, value)
+            }
+        }
+    }
+}
 
-        if src.is_empty() {
-            return Ok(None);
+/// Adds `value` to `fields` under `key`, or if it's a map, each of its fields under `key` and
+/// their own key joined by `separator`, recursing into at most `max_depth` levels of nested maps.
+/// Empty maps, and maps nested deeper than that, are kept as they are.
+fn flatten_value(
+    fields: &mut Vec<(String, Value)>,
+    key: String,
+    value: Value,
+    separator: &str,
+    max_depth: Option<usize>,
+) {
+    match value {
+        Value::Map(map) if !map.is_empty() && max_depth != Some(0) => {
+            for (nested_key, value) in map {
+                flatten_value(
+                    fields,
+                    format!("{}{}{}", key, separator, nested_key),
+                    value,
+                    separator,
+                    max_depth.map(|depth| depth - 1),
+                );
+            }
         }
+        value => fields.push((key, value)),
+    }
+}
 
-        let (pos, res) = {
-            let mut des = Deserializer::new(io::Cursor::new(&src[..]));
+impl TcpSource for FluentSource {
+    type Error = DecodeError;
+    type Decoder = FluentDecoder;
 
-            let res = Deserialize::deserialize(&mut des).map_err(DecodeError::Decode);
+    fn decoder(&self, peer_addr: SocketAddr) -> Self::Decoder {
+        FluentDecoder::new(
+            peer_addr,
+            FluentDecoderOptions {
+                max_frame_bytes: self.max_frame_bytes,
+                max_decompressed_bytes: self.max_decompressed_bytes,
+                max_entry_bytes: self.max_entry_bytes,
+                strict_entry_count: self.strict_entry_count,
+                entry_byte_sizes: self.byte_size_key.is_some(),
+                max_decode_errors: self.max_decode_errors_per_connection,
+                max_pending_frames: self.max_pending_frames,
+                lenient_timestamps: self.lenient_timestamps,
+                batch_entries: self.batch_mode == FluentBatchMode::PerMessage,
+                lenient_decompression: self.lenient_decompression,
+                lenient_message_order: self.lenient_message_order,
+                fallback_uncompressed: self.fallback_uncompressed,
+                framing: self.framing,
+                decode_error_sample_bytes: self.decode_error_sample_bytes,
+                emit_decode_errors: self.emit_decode_errors_as_events,
+                raw_messages: self.raw_message_key.is_some(),
+                array_record_key: self.array_record_key.clone(),
+            },
+        )
+    }
 
-            // check for unexpected EOF to indicate that we need more data
-            match res {
-                // can use or-patterns in 1.53
-                // https://github.com/rust-lang/rust/pull/79278
-                Err(DecodeError::Decode(decode::Error::InvalidDataRead(ref custom))) => {
-                    if custom.kind() == io::ErrorKind::UnexpectedEof {
-                        return Ok(None);
+    fn build_event(&self, mut frame: FluentFrame, peer_addr: SocketAddr) -> Option<Event> {
+        if let Some(max_record_fields) = self.max_record_fields {
+            let records = std::iter::once(&mut frame.record).chain(
+                frame
+                    .entries
+                    .iter_mut()
+                    .map(|FluentEntry(_, record)| record),
+            );
+            for record in records {
+                let fields = record.0.len();
+                if fields > max_record_fields {
+                    emit!(FluentOversizedRecordReceived {
+                        fields,
+                        max_fields: max_record_fields,
+                        dropped: self.on_oversized == FluentOversizedRecord::Drop,
+                    });
+                    match self.on_oversized {
+                        FluentOversizedRecord::Truncate => record.0.truncate(max_record_fields),
+                        FluentOversizedRecord::Drop => return None,
                     }
                 }
-                Err(DecodeError::Decode(decode::Error::InvalidMarkerRead(ref custom))) => {
-                    if custom.kind() == io::ErrorKind::UnexpectedEof {
-                        return Ok(None);
-                    }
+            }
+        }
+
+        if let Some(max_value_depth) = self.max_value_depth {
+            let records = std::iter::once(&mut frame.record).chain(
+                frame
+                    .entries
+                    .iter_mut()
+                    .map(|FluentEntry(_, record)| record),
+            );
+            for record in records {
+                let mut truncated = false;
+                for (key, value) in record.0.iter_mut() {
+                    truncated |= truncate_depth(key, max_value_depth);
+                    truncated |= truncate_depth(&mut value.0, max_value_depth);
+                }
+                if truncated {
+                    emit!(FluentTooDeepValueReceived {
+                        max_depth: max_value_depth,
+                        dropped: self.on_too_deep == FluentTooDeepValue::Drop,
+                    });
+                    if self.on_too_deep == FluentTooDeepValue::Drop {
+                        return None;
+                    }
                 }
-                _ => {}
             }
+        }
 
-            (des.position() as usize, res)
-        };
+        emit!(FluentTagEventReceived {
+            tag: self.tag_labels.label(&frame.tag),
+        });
 
-        src.advance(pos);
+        let byte_size = frame.byte_size;
+        let chunk = frame.chunk.take();
+        let raw_message = frame.raw_message.take();
+        let mut log = self.log_from_frame(frame);
 
-        res.and_then(|message| {
-            self.handle_message(message)
-                .map(|_| self.unread_frames.pop_front())
-        })
-        .map_err(|error| {
-            let base64_encoded_message = base64::encode(&src);
-            emit!(FluentMessageDecodeError {
-                error: &error,
-                base64_encoded_message
-            });
-            error
-        })
-    }
-}
+        if let Some((rate, key)) = &self.sample {
+            if !sampled(&log, *rate, key.as_deref()) {
+                emit!(FluentEventSampledOut);
+                return None;
+            }
+        }
 
-/// Decoder for decoding MessagePackEventStream which are just a stream of Entries
-#[derive(Clone, Debug)]
-struct FluentEntryStreamDecoder;
+        if let Some(host_key) = &self.host_key {
+            if !log.contains(host_key.as_str()) {
+                log.insert(host_key.as_str(), host(peer_addr));
+            }
+        }
 
-impl Decoder for FluentEntryStreamDecoder {
-    type Item = FluentEntry;
-    type Error = DecodeError;
+        if let Some(port_key) = &self.port_key {
+            log.insert(port_key.as_str(), i64::from(peer_addr.port()));
+        }
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        if src.is_empty() {
-            return Ok(None);
+        if let Some(source_type_key) = &self.source_type_key {
+            log.insert(source_type_key.as_str(), Bytes::from("fluent"));
         }
-        let (pos, res) = {
-            let mut des = Deserializer::new(io::Cursor::new(&src[..]));
 
-            // attempt to parse, if we get unexpected EOF, we need more data
-            let res = Deserialize::deserialize(&mut des).map_err(DecodeError::Decode);
+        if let Some(peer_hostnames) = &self.peer_hostnames {
+            log.insert(
+                peer_hostnames.key.as_str(),
+                peer_hostnames.hostname(peer_addr),
+            );
+        }
 
-            if let Err(DecodeError::Decode(decode::Error::InvalidDataRead(ref custom))) = res {
-                if custom.kind() == io::ErrorKind::UnexpectedEof {
-                    return Ok(None);
-                }
-            }
+        if let Some(byte_size_key) = &self.byte_size_key {
+            log.insert(byte_size_key.as_str(), byte_size as i64);
+        }
 
-            let byte_size = des.position();
+        if let Some(received_timestamp_key) = &self.received_timestamp_key {
+            log.insert(received_timestamp_key.as_str(), Utc::now());
+        }
 
-            emit!(FluentMessageReceived { byte_size });
+        if let (Some(chunk_key), Some(chunk)) = (&self.chunk_key, chunk) {
+            log.insert(chunk_key.as_str(), chunk);
+        }
 
-            (byte_size as usize, res)
-        };
+        if let (Some(raw_message_key), Some(raw_message)) = (&self.raw_message_key, raw_message) {
+            log.insert(raw_message_key.as_str(), base64::encode(&raw_message));
+        }
 
-        src.advance(pos);
+        if let Some((key, label)) = &self.listener_label {
+            log.insert(key.as_str(), label.clone());
+        }
 
-        res
+        Some(Event::from(log))
     }
-}
 
-/// Normalized fluent message.
-#[derive(Debug, PartialEq)]
-struct FluentFrame {
-    tag: FluentTag,
-    timestamp: FluentTimestamp,
-    record: FluentRecord,
-}
+    fn connection_accepted(&self, peer_addr: SocketAddr) -> BoxFuture<'static, ()> {
+        match &self.peer_hostnames {
+            Some(peer_hostnames) => peer_hostnames.clone().resolve(peer_addr.ip()).boxed(),
+            None => futures::future::ready(()).boxed(),
+        }
+    }
 
-impl From<FluentFrame> for LogEvent {
-    fn from(frame: FluentFrame) -> LogEvent {
-        let FluentFrame {
-            tag,
-            timestamp,
-            record,
-        } = frame;
+    fn buffered_bytes_changed(&self, previous: usize, current: usize) {
+        emit!(FluentBufferedBytes { previous, current });
+    }
+}
 
-        let mut log = LogEvent::default();
-        log.insert(log_schema().timestamp_key(), timestamp);
-        log.insert("tag", tag);
-        for (key, value) in record.into_iter() {
-            log.insert_flat(key, value)
+/// Whether an event is kept when sampling a `rate` fraction of them. Events with the same value
+/// for `key` are all kept or all dropped, while those without it are sampled at random.
+fn sampled(log: &LogEvent, rate: f64, key: Option<&str>) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    if rate <= 0.0 {
+        return false;
+    }
+    match key.and_then(|key| log.get(key)) {
+        Some(value) => {
+            let hash = seahash::hash(value.to_string_lossy().as_bytes());
+            (hash as f64 / u64::MAX as f64) < rate
         }
-        log
+        None => rand::random::<f64>() < rate,
     }
 }
 
-/// Fluent msgpack messages can be encoded in one of three ways, each with and without
-/// options, all using arrays to encode the top-level fields.
-///
-/// The spec refers to 4 ways, but really CompressedPackedForward is encoded the same as
-/// PackedForward, it just has an additional decompression step.
-///
-/// Not yet handled are the handshake messages.
-///
-/// https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1#event-modes
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
-enum FluentMessage {
-    Message(FluentTag, FluentTimestamp, FluentRecord),
-    // I attempted to just one variant for each of these, with and without options, using an
-    // `Option` for the last element, but rmp expected the number of elements to match in that case
-    // still (it just allows the last element to be `nil`).
-    MessageWithOptions(
-        FluentTag,
-        FluentTimestamp,
-        FluentRecord,
-        FluentMessageOptions,
-    ),
-    Forward(FluentTag, Vec<FluentEntry>),
-    ForwardWithOptions(FluentTag, Vec<FluentEntry>, FluentMessageOptions),
-    PackedForward(FluentTag, serde_bytes::ByteBuf),
-    PackedForwardWithOptions(FluentTag, serde_bytes::ByteBuf, FluentMessageOptions),
+/// A timestamp from an RFC 3339 string, or from seconds since the Unix epoch, either as a number
+/// or as a string.
+fn parse_timestamp_field(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::Timestamp(timestamp) => Some(*timestamp),
+        Value::Integer(seconds) => Utc.timestamp_opt(*seconds, 0).single(),
+        Value::Float(seconds) if seconds.is_finite() => {
+            let whole = seconds.floor();
+            let nanos = ((seconds - whole) * 1e9) as u32;
+            Utc.timestamp_opt(whole as i64, nanos).single()
+        }
+        Value::Bytes(bytes) => {
+            let text = std::str::from_utf8(bytes).ok()?.trim();
+            match DateTime::parse_from_rfc3339(text) {
+                Ok(timestamp) => Some(timestamp.with_timezone(&Utc)),
+                Err(_) => text
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(|seconds| parse_timestamp_field(&Value::Float(seconds))),
+            }
+        }
+        _ => None,
+    }
+}
 
-    // should be last as it'll match any other message
-    Heartbeat(rmpv::Value), // should be Nil if heartbeat
+/// The address of the peer, including the scope id of link-local IPv6 addresses, such as
+/// `fe80::1%2`, as those are ambiguous without it.
+fn host(peer_addr: SocketAddr) -> String {
+    match peer_addr {
+        SocketAddr::V6(addr)
+            if addr.scope_id() != 0 && addr.ip().segments()[0] & 0xffc0 == 0xfe80 =>
+        {
+            format!("{}%{}", addr.ip(), addr.scope_id())
+        }
+        _ => peer_addr.ip().to_string(),
+    }
 }
 
-/// Server options sent by client.
-///
-/// https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1#option
-#[derive(Default, Debug, Deserialize)]
-#[serde(default)]
-struct FluentMessageOptions {
-    size: Option<u64>,          // client provided hint for the number of entries
-    chunk: Option<String>,      // unused right now, would be used for acks
-    compressed: Option<String>, // this one is required if present
+/// Turns fluent messages into events without a listener, for bytes that come from elsewhere,
+/// such as when fuzzing or embedding the source. Events are built as if they were received
+/// from `peer_addr`.
+#[derive(Debug)]
+pub struct FluentEventDecoder {
+    source: FluentSource,
+    decoder: FluentDecoder,
+    peer_addr: SocketAddr,
 }
 
-/// Fluent entry consisting of timestamp and record.
-///
-/// https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1#forward-mode
-#[derive(Debug, Deserialize)]
-struct FluentEntry(FluentTimestamp, FluentRecord);
+impl FluentEventDecoder {
+    pub fn new(config: &FluentConfig, peer_addr: SocketAddr) -> Self {
+        let source = FluentSource::new(config);
+        let decoder = source.decoder(peer_addr);
+        Self {
+            source,
+            decoder,
+            peer_addr,
+        }
+    }
 
-/// Fluent record is just key/value pairs.
-type FluentRecord = BTreeMap<String, FluentValue>;
+    /// Decodes the complete messages in `src`, leaving an incomplete message at its end for
+    /// once more bytes are appended. Like on a connection, messages that fail to decode are
+    /// skipped, unless the error is fatal.
+    pub fn decode_bytes(&mut self, src: &mut BytesMut) -> Result<Vec<Event>, DecodeError> {
+        let mut events = Vec::new();
+        loop {
+            match self.decoder.decode(src) {
+                Ok(Some(frame)) => events.extend(self.source.build_event(frame, self.peer_addr)),
+                Ok(None) => return Ok(events),
+                Err(error) if error.is_error_fatal() => return Err(error),
+                Err(_) => {}
+            }
+        }
+    }
+}
 
-/// Fluent message tag.
-type FluentTag = String;
+#[derive(Debug)]
+pub enum DecodeError {
+    IO(io::Error),
+    Decode(decode::Error),
+    UnknownCompression(String),
+    FrameTooLarge(usize),
+    DecompressedTooLarge(usize),
+    EntryTooLarge(usize),
+    EntryCountMismatch { expected: u64, actual: usize },
+    TooManyDecodeErrors(usize),
+    TooDeep(usize),
+}
 
-/// Value for fluent record key.
-///
-/// Used mostly just to implement value conversion.
-#[derive(Debug, Deserialize, PartialEq)]
-struct FluentValue(rmpv::Value);
+impl DecodeError {
+    pub fn error_type(&self) -> &'static str {
+        match self {
+            DecodeError::IO(_) => "io",
+            DecodeError::Decode(_) => "decode",
+            DecodeError::UnknownCompression(_) => "unknown_compression",
+            DecodeError::FrameTooLarge(_) => "frame_too_large",
+            DecodeError::DecompressedTooLarge(_) => "decompressed_too_large",
+            DecodeError::EntryTooLarge(_) => "entry_too_large",
+            DecodeError::EntryCountMismatch { .. } => "entry_count_mismatch",
+            DecodeError::TooManyDecodeErrors(_) => "too_many_decode_errors",
+            DecodeError::TooDeep(_) => "too_deep",
+        }
+    }
+}
 
-impl From<FluentValue> for Value {
-    fn from(value: FluentValue) -> Self {
-        match value.0 {
-            rmpv::Value::Nil => Value::Null,
-            rmpv::Value::Boolean(b) => Value::Boolean(b),
-            rmpv::Value::Integer(i) => i
-                .as_i64()
-                .map(Value::Integer)
-                // unwrap large numbers to string similar to how `From<serde_json::Value> for Value` handles it
-                .unwrap_or_else(|| Value::Bytes(i.to_string().into())),
-            rmpv::Value::F32(f) => Value::Float(f.into()),
-            rmpv::Value::F64(f) => Value::Float(f),
-            rmpv::Value::String(s) => Value::Bytes(s.into_bytes().into()),
-            rmpv::Value::Binary(bytes) => Value::Bytes(bytes.into()),
-            rmpv::Value::Array(values) => Value::Array(
-                values
-                    .into_iter()
-                    .map(|value| Value::from(FluentValue(value)))
-                    .collect(),
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::IO(err) => write!(f, "{}", err),
+            DecodeError::Decode(err) => write!(f, "{}", err),
+            DecodeError::UnknownCompression(compression) => {
+                write!(f, "unknown compression: {}", compression)
+            }
+            DecodeError::FrameTooLarge(max_frame_bytes) => {
+                write!(f, "message exceeds the limit of {} bytes", max_frame_bytes)
+            }
+            DecodeError::DecompressedTooLarge(max_decompressed_bytes) => write!(
+                f,
+                "decompressed message exceeds the limit of {} bytes",
+                max_decompressed_bytes
             ),
-            rmpv::Value::Map(values) => Value::Map(
-                values
-                    .into_iter()
-                    .map(|(key, value)| (format!("{}", key), Value::from(FluentValue(value))))
-                    .collect(),
+            DecodeError::EntryTooLarge(max_entry_bytes) => write!(
+                f,
+                "packed entry exceeds the limit of {} bytes",
+                max_entry_bytes
+            ),
+            DecodeError::EntryCountMismatch { expected, actual } => write!(
+                f,
+                "message has {} entries, but its size option says {}",
+                actual, expected
+            ),
+            DecodeError::TooManyDecodeErrors(max_decode_errors) => write!(
+                f,
+                "{} messages in a row failed to decode, closing connection",
+                max_decode_errors
+            ),
+            DecodeError::TooDeep(max_depth) => write!(
+                f,
+                "message has record values nested more than {} levels deep",
+                max_depth
             ),
-            rmpv::Value::Ext(code, bytes) => {
-                let mut fields = BTreeMap::new();
-                fields.insert(
-                    String::from("msgpack_extension_code"),
-                    Value::Integer(code.into()),
-                );
-                fields.insert(String::from("bytes"), Value::Bytes(bytes.into()));
-                Value::Map(fields)
-            }
         }
     }
 }
 
-/// Fluent message timestamp.
-///
-/// Message timestamps can be a unix timestamp or EventTime messagepack ext.
-#[derive(Clone, Debug, Deserialize, PartialEq)]
-#[serde(untagged)]
-enum FluentTimestamp {
-    #[serde(with = "ts_seconds")]
-    Unix(DateTime<Utc>),
-    Ext(FluentEventTime),
-}
+impl TcpIsErrorFatal for DecodeError {
+    fn is_error_fatal(&self) -> bool {
+        match self {
+            DecodeError::IO(_) => true,
+            DecodeError::Decode(_) => false,
+            DecodeError::UnknownCompression(_) => false,
+            DecodeError::FrameTooLarge(_) => true,
+            DecodeError::DecompressedTooLarge(_) => true,
+            DecodeError::EntryTooLarge(_) => true,
+            DecodeError::EntryCountMismatch { .. } => false,
+            DecodeError::TooManyDecodeErrors(_) => true,
+            // where the message ends isn't known, as it's not scanned any further
+            DecodeError::TooDeep(_) => true,
+        }
+    }
 
-impl From<FluentTimestamp> for Value {
-    fn from(timestamp: FluentTimestamp) -> Self {
-        match timestamp {
-            FluentTimestamp::Unix(timestamp) | FluentTimestamp::Ext(FluentEventTime(timestamp)) => {
-                Value::Timestamp(timestamp)
-            }
+    fn is_disconnect(&self) -> bool {
+        match self {
+            DecodeError::IO(error) => error.is_disconnect(),
+            _ => false,
         }
     }
 }
 
-/// Custom decoder for Fluent's EventTime msgpack extension.
-///
-/// https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1#eventtime-ext-format
-#[derive(Clone, Debug, PartialEq)]
-struct FluentEventTime(DateTime<Utc>);
+impl From<io::Error> for DecodeError {
+    fn from(e: io::Error) -> Self {
+        DecodeError::IO(e)
+    }
+}
 
-impl<'de> serde::de::Deserialize<'de> for FluentEventTime {
-    fn deserialize<D>(deserializer: D) -> Result<FluentEventTime, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        struct FluentEventTimeVisitor;
+impl From<decode::Error> for DecodeError {
+    fn from(e: decode::Error) -> Self {
+        DecodeError::Decode(e)
+    }
+}
 
-        impl<'de> serde::de::Visitor<'de> for FluentEventTimeVisitor {
-            type Value = FluentEventTime;
+/// How a `FluentDecoder` decodes messages, see the source options of the same names. The
+/// defaults are those of the source.
+#[derive(Clone, Debug)]
+struct FluentDecoderOptions {
+    max_frame_bytes: Option<usize>,
+    max_decompressed_bytes: Option<usize>,
+    max_entry_bytes: Option<usize>,
+    strict_entry_count: bool,
+    entry_byte_sizes: bool,
+    max_decode_errors: Option<usize>,
+    max_pending_frames: usize,
+    lenient_timestamps: bool,
+    batch_entries: bool,
+    lenient_decompression: bool,
+    lenient_message_order: bool,
+    fallback_uncompressed: bool,
+    framing: FluentFraming,
+    decode_error_sample_bytes: usize,
+    emit_decode_errors: bool,
+    raw_messages: bool,
+    array_record_key: Option<String>,
+}
 
-            fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                formatter.write_str("fluent timestamp extension")
-            }
+impl Default for FluentDecoderOptions {
+    fn default() -> Self {
+        Self {
+            max_frame_bytes: None,
+            max_decompressed_bytes: None,
+            max_entry_bytes: None,
+            strict_entry_count: false,
+            entry_byte_sizes: false,
+            max_decode_errors: None,
+            max_pending_frames: DEFAULT_MAX_PENDING_FRAMES,
+            lenient_timestamps: false,
+            batch_entries: false,
+            lenient_decompression: false,
+            lenient_message_order: false,
+            fallback_uncompressed: false,
+            framing: FluentFraming::default(),
+            decode_error_sample_bytes: DEFAULT_DECODE_ERROR_SAMPLE_BYTES,
+            emit_decode_errors: false,
+            raw_messages: false,
+            array_record_key: None,
+        }
+    }
+}
 
-            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
-            where
-                D: serde::de::Deserializer<'de>,
-            {
-                deserializer.deserialize_tuple(2, self)
-            }
+#[derive(Debug)]
+struct FluentDecoder {
+    // where decode errors come from, for the emitted events
+    peer_addr: SocketAddr,
+    bytes_consumed: usize,
+    message_offset: usize,
+    // how far the message at the start of the buffer was scanned, so that a message arriving over
+    // many reads is only scanned once
+    message_scan: MessageScan,
+    // unread frames from previous fluent message
+    unread_frames: VecDeque<FluentFrame>,
+    // how many of them were last reported
+    reported_unread_frames: usize,
+    max_frame_bytes: Option<usize>,
+    max_decompressed_bytes: Option<usize>,
+    // fail packed forward messages with an entry larger than this
+    max_entry_bytes: Option<usize>,
+    // reject messages whose entry count doesn't match their size option
+    strict_entry_count: bool,
+    // find out the size of each entry of forward mode messages
+    entry_byte_sizes: bool,
+    // close the connection after this many non-fatal errors in a row
+    max_decode_errors: Option<usize>,
+    consecutive_errors: usize,
+    // entries of the last message not yet turned into frames, as only up to
+    // `max_pending_frames` are buffered at a time
+    max_pending_frames: usize,
+    pending_entries: Option<PendingEntries>,
+    // replace malformed EventTime timestamps with the current time instead of failing the message
+    lenient_timestamps: bool,
+    // turn all entries of a forward mode message into a single frame
+    batch_entries: bool,
+    // ignore data after the last complete gzip member that isn't another member
+    lenient_decompression: bool,
+    // put the elements of messages that don't match any mode in order, if that makes them match
+    lenient_message_order: bool,
+    // decode the entries of messages with an unknown compression as if they were uncompressed
+    fallback_uncompressed: bool,
+    framing: FluentFraming,
+    // how much of the data that failed to decode is logged, as it may be large or sensitive
+    decode_error_sample_bytes: usize,
+    // turn messages that fail to decode into dead-letter frames rather than dropping them
+    emit_decode_errors: bool,
+    // the last message that failed to decode, for its dead-letter frame
+    failed_message: Option<Bytes>,
+    // keep the msgpack bytes of each message with the frames decoded from it
+    raw_messages: bool,
+    // wrap records that are arrays rather than maps under this key
+    array_record_key: Option<String>,
+}
 
-            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
-            where
-                A: serde::de::SeqAccess<'de>,
-            {
-                let tag: u32 = seq
-                    .next_element()?
-                    .ok_or_else(|| serde::de::Error::invalid_length(0, &self))?;
+impl FluentDecoder {
+    fn new(peer_addr: SocketAddr, options: FluentDecoderOptions) -> Self {
+        let FluentDecoderOptions {
+            max_frame_bytes,
+            max_decompressed_bytes,
+            max_entry_bytes,
+            strict_entry_count,
+            entry_byte_sizes,
+            max_decode_errors,
+            max_pending_frames,
+            lenient_timestamps,
+            batch_entries,
+            lenient_decompression,
+            lenient_message_order,
+            fallback_uncompressed,
+            framing,
+            decode_error_sample_bytes,
+            emit_decode_errors,
+            raw_messages,
+            array_record_key,
+        } = options;
+        FluentDecoder {
+            peer_addr,
+            bytes_consumed: 0,
+            message_offset: 0,
+            message_scan: MessageScan::new(),
+            unread_frames: VecDeque::new(),
+            reported_unread_frames: 0,
+            max_frame_bytes,
+            max_decompressed_bytes,
+            max_entry_bytes,
+            strict_entry_count,
+            entry_byte_sizes,
+            max_decode_errors,
+            consecutive_errors: 0,
+            max_pending_frames,
+            pending_entries: None,
+            lenient_timestamps,
+            batch_entries,
+            lenient_decompression,
+            lenient_message_order,
+            fallback_uncompressed,
+            framing,
+            decode_error_sample_bytes,
+            emit_decode_errors,
+            failed_message: None,
+            raw_messages,
+            array_record_key,
+        }
+    }
 
-                if tag != 0 {
-                    return Err(serde::de::Error::custom(format!(
-                        "expected extension type 0 for fluent timestamp, got got {}",
-                        tag
-                    )));
+    /// A malformed EventTime timestamp fails the message, unless `lenient_timestamps` is set.
+    fn check_timestamp(&self, timestamp: &FluentTimestamp) -> Result<(), DecodeError> {
+        match timestamp {
+            FluentTimestamp::Ext(FluentEventTime(Err(reason), _)) => {
+                if self.lenient_timestamps {
+                    emit!(FluentMalformedTimestamp { reason });
+                    Ok(())
+                } else {
+                    Err(DecodeError::Decode(decode::Error::Syntax(reason.clone())))
                 }
+            }
+            _ => Ok(()),
+        }
+    }
 
-                let bytes: serde_bytes::ByteBuf = seq
-                    .next_element()?
-                    .ok_or_else(|| serde::de::Error::invalid_length(1, &self))?;
-
-                if bytes.len() != 8 {
-                    return Err(serde::de::Error::custom(format!(
-                        "expected exactly 8 bytes for binary encoded fluent timestamp, got {}",
-                        bytes.len()
-                    )));
+    /// Compares the number of entries in a message with the `size` option the client sent, if
+    /// any. A mismatch hints at a truncated or corrupt message.
+    fn check_entry_count(
+        &self,
+        options: &FluentMessageOptions,
+        actual: usize,
+    ) -> Result<(), DecodeError> {
+        match options.size {
+            Some(expected) if expected != actual as u64 => {
+                emit!(FluentEntryCountMismatch { expected, actual });
+                if self.strict_entry_count {
+                    Err(DecodeError::EntryCountMismatch { expected, actual })
+                } else {
+                    Ok(())
                 }
-
-                // length checked right above
-                let seconds = u32::from_be_bytes(bytes[..4].try_into().expect("exactly 4 bytes"));
-                let nanoseconds =
-                    u32::from_be_bytes(bytes[4..].try_into().expect("exactly 4 bytes"));
-
-                Ok(FluentEventTime(Utc.timestamp(seconds.into(), nanoseconds)))
             }
+            _ => Ok(()),
         }
-
-        deserializer.deserialize_any(FluentEventTimeVisitor)
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use shared::{assert_event_data_eq, btreemap};
+
+    /// Turns pending entries into frames until `max_pending_frames` of them are buffered. Once
+    /// all entries are decoded, the message as a whole is checked, and if that fails, none of its
+    /// remaining frames are kept.
+    fn expand_pending_entries(&mut self) -> Result<(), DecodeError> {
+        let mut pending = match self.pending_entries.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        // A batch is a single frame, so all of its entries are needed at once.
+        let max_pending_frames = if self.batch_entries {
+            usize::MAX
+        } else {
+            self.max_pending_frames.max(1)
+        };
+
+        let result = loop {
+            if self.unread_frames.len() >= max_pending_frames {
+                self.pending_entries = Some(pending);
+                return Ok(());
+            }
+            match pending.next_frame() {
+                Ok(Some(frame)) => match self.check_timestamp(&frame.timestamp) {
+                    Ok(()) => self.unread_frames.push_back(frame),
+                    Err(error) => break Err(error),
+                },
+                Ok(None) => {
+                    let finished = pending.finish();
+                    if finished.is_ok() {
+                        emit!(FluentForwardEntriesDecoded {
+                            count: pending.count
+                        });
+                    }
+                    break finished;
+                }
+                // a payload that decompresses to too much is reported as such however it decodes
+                Err(error) => match pending.finish() {
+                    Err(too_large @ DecodeError::DecompressedTooLarge(_)) => break Err(too_large),
+                    _ => break Err(error),
+                },
+            }
+        };
+        let result = result.and_then(|()| match &pending.options {
+            Some(options) => self.check_entry_count(options, pending.count),
+            None => Ok(()),
+        });
+
+        if result.is_err() {
+            self.unread_frames.clear();
+        } else if self.batch_entries && !self.unread_frames.is_empty() {
+            // Frames are only decoded once the previous message's are all returned, so these are
+            // all from this message.
+            let frames = std::mem::take(&mut self.unread_frames);
+            self.unread_frames.push_back(FluentFrame::batch(frames));
+        }
+        result
+    }
+
+    /// `frame` holds the msgpack bytes `message` was decoded from.
+    fn handle_message(&mut self, message: FluentMessage, frame: &Bytes) -> Result<(), DecodeError> {
+        let compression = match &message {
+            FluentMessage::Heartbeat(_) => None,
+            FluentMessage::PackedForwardWithOptions(_, _, options) => {
+                match options.compressed.as_deref() {
+                    Some("gzip") => Some("gzip"),
+                    Some("text") => Some("text"),
+                    None => Some("none"),
+                    // rejected below
+                    Some(_) => None,
+                }
+            }
+            _ => Some("none"),
+        };
+        if let Some(compression) = compression {
+            emit!(FluentMessageDecoded { compression });
+        }
+
+        match message {
+            FluentMessage::Message(tag, timestamp, record) => {
+                self.check_timestamp(&timestamp)?;
+                self.unread_frames.push_back(FluentFrame {
+                    tag: tag.into(),
+                    timestamp,
+                    record,
+                    byte_size: frame.len(),
+                    chunk: None,
+                    entries: Vec::new(),
+                    raw_message: None,
+                });
+                Ok(())
+            }
+            FluentMessage::MessageWithOptions(tag, timestamp, record, options) => {
+                self.check_timestamp(&timestamp)?;
+                self.unread_frames.push_back(FluentFrame {
+                    tag: tag.into(),
+                    timestamp,
+                    record,
+                    byte_size: frame.len(),
+                    chunk: options.chunk,
+                    entries: Vec::new(),
+                    raw_message: None,
+                });
+                Ok(())
+            }
+            FluentMessage::Forward(tag, entries) => {
+                self.pend_forward_entries(tag, entries, None, frame);
+                Ok(())
+            }
+            FluentMessage::ForwardWithOptions(tag, entries, options) => {
+                self.check_entry_count(&options, entries.len())?;
+                self.pend_forward_entries(tag, entries, options.chunk, frame);
+                Ok(())
+            }
+            FluentMessage::PackedForward(tag, bin) => {
+                self.pending_entries = Some(PendingEntries::new(
+                    tag,
+                    None,
+                    PendingEntrySource::packed(
+                        Box::new(io::Cursor::new(bin.into_vec())),
+                        None,
+                        self.max_entry_bytes,
+                    ),
+                    None,
+                    frame.clone(),
+                ));
+                Ok(())
+            }
+            FluentMessage::PackedForwardWithOptions(tag, bin, options) => {
+                let entries = match options.compressed.as_deref() {
+                    Some("gzip") if self.lenient_decompression => PendingEntrySource::packed(
+                        Box::new(LenientGzDecoder::new(bin.into_vec())),
+                        self.max_decompressed_bytes,
+                        self.max_entry_bytes,
+                    ),
+                    Some("gzip") => PendingEntrySource::packed(
+                        Box::new(MultiGzDecoder::new(io::Cursor::new(bin.into_vec()))),
+                        self.max_decompressed_bytes,
+                        self.max_entry_bytes,
+                    ),
+                    Some("text") | None => PendingEntrySource::packed(
+                        Box::new(io::Cursor::new(bin.into_vec())),
+                        None,
+                        self.max_entry_bytes,
+                    ),
+                    Some(compression) if self.fallback_uncompressed => {
+                        emit!(FluentUnknownCompressionIgnored { compression });
+                        PendingEntrySource::packed(
+                            Box::new(io::Cursor::new(bin.into_vec())),
+                            None,
+                            self.max_entry_bytes,
+                        )
+                    }
+                    Some(s) => return Err(DecodeError::UnknownCompression(s.to_owned())),
+                };
+                self.pending_entries = Some(PendingEntries::new(
+                    tag,
+                    options.chunk.clone(),
+                    entries,
+                    Some(options),
+                    frame.clone(),
+                ));
+                Ok(())
+            }
+            FluentMessage::Heartbeat(rmpv::Value::Nil) => Ok(()),
+            FluentMessage::Heartbeat(value) => {
+                if let Some(key) = &self.array_record_key {
+                    if let Some(message) = wrap_array_record(&value, key) {
+                        emit!(FluentArrayRecordWrapped);
+                        return self.handle_message(message, frame);
+                    }
+                }
+                if self.lenient_message_order {
+                    if let Some(message) = reorder_message(&value) {
+                        emit!(FluentMessageReordered);
+                        return self.handle_message(message, frame);
+                    }
+                }
+                // Some clients send heartbeats with a payload, which is harmless.
+                emit!(FluentUnexpectedHeartbeat { value: &value });
+                Ok(())
+            }
+        }
+    }
+
+    /// `frame` holds the msgpack bytes of the message `forward` was scanned from.
+    fn handle_forward(&mut self, forward: ForwardMessage, frame: Bytes) -> Result<(), DecodeError> {
+        emit!(FluentMessageDecoded {
+            compression: "none"
+        });
+        let chunk = match forward.options {
+            Some(options) => {
+                self.check_entry_count(&options, forward.count)?;
+                options.chunk
+            }
+            None => None,
+        };
+        self.pending_entries = Some(PendingEntries::new(
+            forward.tag,
+            chunk,
+            PendingEntrySource::Encoded {
+                entries: io::Cursor::new(frame.slice(forward.entries)),
+                remaining: forward.count,
+            },
+            None,
+            frame,
+        ));
+        Ok(())
+    }
+
+    fn pend_forward_entries(
+        &mut self,
+        tag: FluentTag,
+        entries: Vec<FluentEntry>,
+        chunk: Option<String>,
+        frame: &Bytes,
+    ) {
+        // Scanning the frame again is only worth it if the sizes are used.
+        let byte_sizes = if self.entry_byte_sizes {
+            forward_entry_sizes(frame, entries.len()).unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        self.pending_entries = Some(PendingEntries::new(
+            tag,
+            chunk,
+            PendingEntrySource::Forward {
+                entries: entries.into_iter(),
+                byte_sizes: byte_sizes.into_iter(),
+            },
+            None,
+            frame.clone(),
+        ));
+    }
+
+    fn decode_message(&mut self, src: &mut BytesMut) -> Result<Option<FluentFrame>, DecodeError> {
+        // More of the pending entries are expanded once the frames drain below half the limit.
+        let low_water_mark = self.max_pending_frames / 2;
+        if let Some(pending) = &self.pending_entries {
+            if self.unread_frames.len() <= low_water_mark {
+                // reported as the message the entries are from
+                let message = pending.message.clone();
+                if let Err(error) = self.expand_pending_entries() {
+                    return self.handled(Err(error), &message);
+                }
+            }
+        }
+
+        // No new message is decoded, and so no more is read from the connection, until the frames
+        // of the previous one have all been handed out, which only happens as fast as they're
+        // sent on.
+        if let Some(frame) = self.unread_frames.pop_front() {
+            return Ok(Some(frame));
+        }
+        debug_assert!(self.pending_entries.is_none());
+
+        if src.is_empty() {
+            return Ok(None);
+        }
+        self.message_offset = self.bytes_consumed;
+
+        if self.framing == FluentFraming::LengthDelimited {
+            return self.decode_length_delimited(src);
+        }
+
+        // Everything below recurses into the message, so it's checked to be nested shallowly
+        // enough first. Messages that aren't fully buffered are only checked as far as they are,
+        // and are only decoded once they are.
+        let len = match self.message_scan.resume(src, MESSAGE_NESTING) {
+            Ok(Some(len)) => len,
+            Ok(None) => match self.max_frame_bytes {
+                Some(max_frame_bytes) if src.len() > max_frame_bytes => {
+                    self.message_scan = MessageScan::new();
+                    let error = DecodeError::FrameTooLarge(max_frame_bytes);
+                    // the buffered data can be up to `max_frame_bytes` long, so it isn't logged
+                    emit!(self.decode_error(&error, String::new()));
+                    return Err(error);
+                }
+                _ => return Ok(None),
+            },
+            Err(error) => {
+                self.message_scan = MessageScan::new();
+                emit!(self.decode_error(&error, self.error_sample(src)));
+                return Err(error);
+            }
+        };
+        self.message_scan = MessageScan::new();
+
+        // Forward mode messages are only scanned, so that their entries are decoded one at a time
+        // rather than all at once.
+        if let Some(forward) = scan_forward(&src[..len]) {
+            let frame = src.split_to(forward.len).freeze();
+            self.bytes_consumed += frame.len();
+            let res = match self.max_frame_bytes {
+                Some(max_frame_bytes) if frame.len() > max_frame_bytes => {
+                    Err(DecodeError::FrameTooLarge(max_frame_bytes))
+                }
+                _ => self.handle_forward(forward, frame.clone()),
+            };
+            return self.handled(res, &frame);
+        }
+
+        let (pos, res) = {
+            let mut des = Deserializer::new(io::Cursor::new(&src[..len]));
+
+            let res = Deserialize::deserialize(&mut des).map_err(DecodeError::Decode);
+
+            // check for unexpected EOF to indicate that we need more data
+            if let Err(DecodeError::Decode(ref error)) = res {
+                if is_incomplete(error) {
+                    return Ok(None);
+                }
+            }
+
+            (des.position() as usize, res)
+        };
+
+        let frame = src.split_to(pos).freeze();
+        self.bytes_consumed += pos;
+
+        let res = match self.max_frame_bytes {
+            Some(max_frame_bytes) if pos > max_frame_bytes => {
+                Err(DecodeError::FrameTooLarge(max_frame_bytes))
+            }
+            _ => res,
+        };
+
+        let res = res.and_then(|message| self.handle_message(message, &frame));
+        self.handled(res, &frame)
+    }
+
+    /// The first frame of `message` once handled with `result`, or the error it failed with,
+    /// reported with a sample of the message.
+    fn handled(
+        &mut self,
+        result: Result<(), DecodeError>,
+        message: &Bytes,
+    ) -> Result<Option<FluentFrame>, DecodeError> {
+        result
+            .and_then(|()| {
+                if self.raw_messages {
+                    self.attach_raw_message(message);
+                }
+                self.expand_pending_entries()?;
+                Ok(self.unread_frames.pop_front())
+            })
+            .map_err(|error| {
+                emit!(self.decode_error(&error, self.error_sample(message)));
+                if self.emit_decode_errors {
+                    self.failed_message = Some(message.clone());
+                }
+                error
+            })
+    }
+
+    /// Shares the msgpack bytes of `message` with the frames decoded from it, which are the only
+    /// ones around while it's handled.
+    fn attach_raw_message(&mut self, message: &Bytes) {
+        for frame in self.unread_frames.iter_mut() {
+            frame.raw_message = Some(message.clone());
+        }
+        if let Some(pending) = self.pending_entries.as_mut() {
+            pending.raw_message = Some(message.clone());
+        }
+    }
+
+    /// Decodes the message at the start of `src` once its length prefix and as many bytes as that
+    /// says are buffered. The message has to take up exactly that many bytes.
+    fn decode_length_delimited(
+        &mut self,
+        src: &mut BytesMut,
+    ) -> Result<Option<FluentFrame>, DecodeError> {
+        if src.len() < LENGTH_PREFIX_BYTES {
+            return Ok(None);
+        }
+        let mut prefix = [0; LENGTH_PREFIX_BYTES];
+        prefix.copy_from_slice(&src[..LENGTH_PREFIX_BYTES]);
+        let len = u32::from_be_bytes(prefix) as usize;
+
+        if let Some(max_frame_bytes) = self.max_frame_bytes {
+            if len > max_frame_bytes {
+                let error = DecodeError::FrameTooLarge(max_frame_bytes);
+                emit!(self.decode_error(&error, String::new()));
+                return Err(error);
+            }
+        }
+        if src.len() < LENGTH_PREFIX_BYTES + len {
+            return Ok(None);
+        }
+
+        let frame = src
+            .split_to(LENGTH_PREFIX_BYTES + len)
+            .split_off(LENGTH_PREFIX_BYTES)
+            .freeze();
+        self.bytes_consumed += LENGTH_PREFIX_BYTES + len;
+
+        if let Err(error) = check_nesting(&frame, MESSAGE_NESTING) {
+            return self.handled(Err(error), &frame);
+        }
+
+        if let Some(forward) = scan_forward(&frame).filter(|forward| forward.len == len) {
+            let res = self.handle_forward(forward, frame.clone());
+            return self.handled(res, &frame);
+        }
+
+        let mut des = Deserializer::new(io::Cursor::new(&frame[..]));
+        let res = FluentMessage::deserialize(&mut des)
+            .map_err(DecodeError::Decode)
+            .and_then(|message| {
+                let pos = des.position() as usize;
+                if pos < len {
+                    Err(DecodeError::Decode(decode::Error::Syntax(format!(
+                        "{} bytes after the message in a frame of {} bytes",
+                        len - pos,
+                        len
+                    ))))
+                } else {
+                    Ok(message)
+                }
+            })
+            .and_then(|message| self.handle_message(message, &frame));
+        self.handled(res, &frame)
+    }
+
+    /// Reports the number of unread frames if it changed since it was last reported, which it
+    /// doesn't while messages hold a single event each.
+    fn report_unread_frames(&mut self) {
+        let current = self.unread_frames.len();
+        if current != self.reported_unread_frames {
+            emit!(FluentUnreadFrames {
+                peer_addr: self.peer_addr,
+                previous: self.reported_unread_frames,
+                current,
+            });
+            self.reported_unread_frames = current;
+        }
+    }
+
+    /// The start of `data`, base64 encoded, as a sample of what failed to decode.
+    fn error_sample(&self, data: &[u8]) -> String {
+        base64::encode(&data[..data.len().min(self.decode_error_sample_bytes)])
+    }
+
+    /// A frame for the message that failed to decode with `error`, holding the error and the
+    /// message base64 encoded, so that it's sent on rather than dropped.
+    fn dead_letter(&mut self, error: &DecodeError) -> FluentFrame {
+        let message = self.failed_message.take().unwrap_or_default();
+        let field = |key: &str, value: String| {
+            (
+                rmpv::Value::from(key),
+                FluentValue(rmpv::Value::from(value)),
+            )
+        };
+        FluentFrame {
+            tag: DEAD_LETTER_TAG.into(),
+            timestamp: FluentTimestamp::Unix(Utc::now(), None),
+            record: FluentRecord(vec![
+                field("error", error.to_string()),
+                field("error_type", error.error_type().to_owned()),
+                field("message", base64::encode(&message)),
+            ]),
+            byte_size: message.len(),
+            chunk: None,
+            entries: Vec::new(),
+            raw_message: None,
+        }
+    }
+
+    /// The event for a decode error, with the peer and the offset within the connection of the
+    /// message that failed to decode.
+    fn decode_error<'a>(
+        &self,
+        error: &'a DecodeError,
+        base64_encoded_message: String,
+    ) -> FluentMessageDecodeError<'a> {
+        FluentMessageDecodeError {
+            error,
+            base64_encoded_message,
+            peer_addr: self.peer_addr,
+            offset: self.message_offset,
+        }
+    }
+}
+
+impl Decoder for FluentDecoder {
+    type Item = FluentFrame;
+    type Error = DecodeError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let result = loop {
+            let len = src.len();
+            match self.decode_message(src) {
+                // A message without any events, such as a heartbeat, was consumed, so go on with
+                // the next one, as the framing only reads more data once `None` is returned.
+                Ok(None) if src.len() < len => continue,
+                result => break result,
+            }
+        };
+        match &result {
+            Ok(Some(_)) => self.consecutive_errors = 0,
+            Err(error) if !error.is_error_fatal() => {
+                self.consecutive_errors += 1;
+                match self.max_decode_errors {
+                    Some(max_decode_errors) if self.consecutive_errors >= max_decode_errors => {
+                        let error = DecodeError::TooManyDecodeErrors(max_decode_errors);
+                        emit!(self.decode_error(&error, String::new()));
+                        return Err(error);
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        let result = match result {
+            Err(error) if self.emit_decode_errors && !error.is_error_fatal() => {
+                Ok(Some(self.dead_letter(&error)))
+            }
+            result => result,
+        };
+        self.report_unread_frames();
+        result
+    }
+}
+
+impl Drop for FluentDecoder {
+    fn drop(&mut self) {
+        self.unread_frames.clear();
+        self.report_unread_frames();
+    }
+}
+
+/// Whether decoding failed only because the message isn't fully buffered yet.
+fn is_incomplete(error: &decode::Error) -> bool {
+    match error {
+        decode::Error::InvalidDataRead(error) | decode::Error::InvalidMarkerRead(error) => {
+            error.kind() == io::ErrorKind::UnexpectedEof
+        }
+        _ => false,
+    }
+}
+
+/// Entries of a forward mode message that haven't been turned into frames yet.
+struct PendingEntries {
+    // shared by the frames of all entries, rather than copied for each of them
+    tag: Arc<str>,
+    chunk: Option<String>,
+    entries: PendingEntrySource,
+    // to check the number of entries against once all are decoded
+    options: Option<FluentMessageOptions>,
+    count: usize,
+    raw_message: Option<Bytes>,
+    // msgpack bytes of the message the entries are from, to report it with if one fails
+    message: Bytes,
+}
+
+impl PendingEntries {
+    fn new(
+        tag: FluentTag,
+        chunk: Option<String>,
+        entries: PendingEntrySource,
+        options: Option<FluentMessageOptions>,
+        message: Bytes,
+    ) -> Self {
+        Self {
+            tag: tag.into(),
+            chunk,
+            entries,
+            options,
+            count: 0,
+            raw_message: None,
+            message,
+        }
+    }
+
+    /// `None` once all entries are decoded.
+    fn next_frame(&mut self) -> Result<Option<FluentFrame>, DecodeError> {
+        let (FluentEntry(timestamp, record), byte_size) = match &mut self.entries {
+            PendingEntrySource::Forward {
+                entries,
+                byte_sizes,
+            } => match entries.next() {
+                Some(entry) => (entry, byte_sizes.next().unwrap_or(0)),
+                None => return Ok(None),
+            },
+            PendingEntrySource::Encoded { entries, remaining } => {
+                if *remaining == 0 {
+                    return Ok(None);
+                }
+                *remaining -= 1;
+                let start = entries.position();
+                let entry = FluentEntry::deserialize(&mut Deserializer::new(&mut *entries))?;
+                (entry, (entries.position() - start) as usize)
+            }
+            PendingEntrySource::Packed {
+                reader,
+                max_entry_bytes,
+                ..
+            } => {
+                let max_entry_bytes = *max_entry_bytes;
+                match reader.inner.fill_buf() {
+                    Ok(buf) if buf.is_empty() => return Ok(None),
+                    Ok(_) => {}
+                    Err(error) => return Err(DecodeError::IO(error)),
+                }
+                reader.limit =
+                    max_entry_bytes.map(|max_entry_bytes| reader.count + max_entry_bytes);
+                // Each entry is scanned out of the stream before it's deserialized, which
+                // recurses into it.
+                let mut bytes = Vec::new();
+                match scan_nesting(reader, &mut bytes, ENTRY_NESTING) {
+                    Ok(()) => {}
+                    Err(_) if reader.limit_reached => {
+                        return Err(DecodeError::EntryTooLarge(max_entry_bytes.unwrap_or(0)))
+                    }
+                    // a truncated last entry is ignored
+                    Err(DecodeError::IO(error)) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                        return Ok(None)
+                    }
+                    Err(DecodeError::IO(error)) => {
+                        return Err(DecodeError::Decode(decode::Error::InvalidDataRead(error)))
+                    }
+                    Err(error) => return Err(error),
+                }
+                match Option::<FluentEntry>::deserialize(&mut Deserializer::new(&bytes[..]))? {
+                    Some(entry) => {
+                        emit!(FluentMessageReceived {
+                            byte_size: bytes.len() as u64
+                        });
+                        (entry, bytes.len())
+                    }
+                    // as are any entries after a nil
+                    None => return Ok(None),
+                }
+            }
+        };
+        self.count += 1;
+        Ok(Some(FluentFrame {
+            tag: self.tag.clone(),
+            timestamp,
+            record,
+            byte_size,
+            chunk: self.chunk.clone(),
+            entries: Vec::new(),
+            raw_message: self.raw_message.clone(),
+        }))
+    }
+
+    /// The stream of packed entries is read to its end even if decoding stopped early, giving up
+    /// as soon as more than `max_bytes` come out, so that a small, highly compressed payload is
+    /// rejected no matter what it decompresses to.
+    fn finish(&mut self) -> Result<(), DecodeError> {
+        if let PendingEntrySource::Packed {
+            reader, max_bytes, ..
+        } = &mut self.entries
+        {
+            // Reading the rest also surfaces errors in compressed data after the last entry. It
+            // isn't an entry, so `max_entry_bytes` doesn't apply.
+            let drained = io::copy(&mut reader.inner, &mut io::sink());
+            if let Some(max_bytes) = *max_bytes {
+                if reader.inner.get_ref().limit() == 0 {
+                    return Err(DecodeError::DecompressedTooLarge(max_bytes));
+                }
+            }
+            drained?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for PendingEntries {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PendingEntries")
+            .field("tag", &self.tag)
+            .field("count", &self.count)
+            .finish()
+    }
+}
+
+enum PendingEntrySource {
+    Forward {
+        entries: std::vec::IntoIter<FluentEntry>,
+        byte_sizes: std::vec::IntoIter<usize>,
+    },
+    /// The still encoded entries of a forward mode message, see `scan_forward`.
+    Encoded {
+        entries: io::Cursor<Bytes>,
+        remaining: usize,
+    },
+    /// The stream of entries of a packed forward message, which are decoded one at a time so that
+    /// compressed entries are never decompressed into memory all at once.
+    Packed {
+        reader: PackedEntryReader,
+        max_bytes: Option<usize>,
+        max_entry_bytes: Option<usize>,
+    },
+}
+
+type PackedEntryReader = CountingReader<io::BufReader<io::Take<Box<dyn Read + Send>>>>;
+
+impl PendingEntrySource {
+    fn packed(
+        reader: Box<dyn Read + Send>,
+        max_bytes: Option<usize>,
+        max_entry_bytes: Option<usize>,
+    ) -> Self {
+        let limit = max_bytes.map_or(u64::MAX, |max_bytes| max_bytes as u64 + 1);
+        Self::Packed {
+            reader: CountingReader {
+                inner: io::BufReader::new(reader.take(limit)),
+                count: 0,
+                limit: None,
+                limit_reached: false,
+            },
+            max_bytes,
+            max_entry_bytes,
+        }
+    }
+}
+
+/// Reader counting the bytes read through it, to know how large each entry of a
+/// MessagePackEventStream, which is just a stream of entries, is.
+///
+/// Reads fail once the count would go past `limit`, so that a single entry can't read an
+/// unbounded amount of data however large it claims to be.
+struct CountingReader<R> {
+    inner: R,
+    count: usize,
+    limit: Option<usize>,
+    limit_reached: bool,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = match self.limit {
+            Some(limit) if !buf.is_empty() && self.count >= limit => {
+                self.limit_reached = true;
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "entry exceeds the size limit",
+                ));
+            }
+            Some(limit) => buf.len().min(limit - self.count),
+            None => buf.len(),
+        };
+        let read = self.inner.read(&mut buf[..len])?;
+        self.count += read;
+        Ok(read)
+    }
+}
+
+/// Decodes concatenated gzip members like `MultiGzDecoder`, except that data following a
+/// complete member that fails to decode before producing anything, such as padding added by some
+/// clients, ends the stream instead of failing it.
+struct LenientGzDecoder {
+    decoder: GzDecoder<io::Cursor<Vec<u8>>>,
+    // offset of the current member in the payload
+    member_start: usize,
+    // whether the current member has produced any output
+    member_started: bool,
+    members: usize,
+    ignored_rest: bool,
+}
+
+impl LenientGzDecoder {
+    fn new(payload: Vec<u8>) -> Self {
+        Self {
+            decoder: GzDecoder::new(io::Cursor::new(payload)),
+            member_start: 0,
+            member_started: false,
+            members: 0,
+            ignored_rest: false,
+        }
+    }
+}
+
+impl Read for LenientGzDecoder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.ignored_rest {
+            return Ok(0);
+        }
+        loop {
+            match self.decoder.read(buf) {
+                Ok(0) => {
+                    let payload = self.decoder.get_ref();
+                    let position = payload.position() as usize;
+                    if position >= payload.get_ref().len() {
+                        return Ok(0);
+                    }
+                    // Another member follows this one.
+                    let payload = std::mem::replace(
+                        &mut self.decoder,
+                        GzDecoder::new(io::Cursor::new(Vec::new())),
+                    )
+                    .into_inner();
+                    self.decoder = GzDecoder::new(payload);
+                    self.member_start = position;
+                    self.member_started = false;
+                    self.members += 1;
+                }
+                Ok(read) => {
+                    self.member_started = true;
+                    return Ok(read);
+                }
+                Err(error) if self.members > 0 && !self.member_started => {
+                    emit!(FluentTrailingGzipDataIgnored {
+                        byte_size: self.decoder.get_ref().get_ref().len() - self.member_start,
+                        error: &error,
+                    });
+                    self.ignored_rest = true;
+                    return Ok(0);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+/// A forward mode message whose entries haven't been decoded yet.
+struct ForwardMessage {
+    tag: FluentTag,
+    // where the entries are within the message
+    entries: std::ops::Range<usize>,
+    count: usize,
+    options: Option<FluentMessageOptions>,
+    // of the whole message
+    len: usize,
+}
+
+/// Scans the forward mode message at the start of `src`, decoding only its tag and options.
+/// `None` if it isn't a complete, well-formed forward mode message, in which case it's left to
+/// be decoded as a whole, which tells apart incomplete messages from invalid ones.
+fn scan_forward(src: &[u8]) -> Option<ForwardMessage> {
+    // [tag, [entry, ...], options?]
+    let (fields, mut rest) = read_array_header(src)?;
+    if fields != 2 && fields != 3 {
+        return None;
+    }
+    let tag = FluentTag::deserialize(&mut Deserializer::new(&mut rest)).ok()?;
+    let (count, mut rest) = read_array_header(rest)?;
+    let start = src.len() - rest.len();
+    for _ in 0..count {
+        rest = skip_value(rest)?;
+    }
+    let end = src.len() - rest.len();
+    let options = if fields == 3 {
+        Some(FluentMessageOptions::deserialize(&mut Deserializer::new(&mut rest)).ok()?)
+    } else {
+        None
+    };
+    Some(ForwardMessage {
+        tag,
+        entries: start..end,
+        count,
+        options,
+        len: src.len() - rest.len(),
+    })
+}
+
+/// The length of the array whose header is at the start of `src`, and what follows the header.
+fn read_array_header(src: &[u8]) -> Option<(usize, &[u8])> {
+    let (len, header) = match *src.first()? {
+        marker @ 0x90..=0x9f => ((marker & 0x0f) as usize, 1),
+        0xdc => (
+            u16::from_be_bytes(src.get(1..3)?.try_into().ok()?) as usize,
+            3,
+        ),
+        0xdd => (
+            u32::from_be_bytes(src.get(1..5)?.try_into().ok()?) as usize,
+            5,
+        ),
+        _ => return None,
+    };
+    Some((len, &src[header..]))
+}
+
+/// The number of bytes each of the `count` entries of the forward mode message in `frame` takes
+/// up. The frame was already decoded, so this only fails if it isn't a forward mode message.
+fn forward_entry_sizes(frame: &[u8], count: usize) -> Option<Vec<usize>> {
+    // [tag, [entry, ...], options?]
+    let rest = skip_value(skip_array_header(frame)?)?;
+    let mut rest = skip_array_header(rest)?;
+    (0..count)
+        .map(|_| {
+            let len = rest.len();
+            rest = skip_value(rest)?;
+            Some(len - rest.len())
+        })
+        .collect()
+}
+
+fn skip_array_header(src: &[u8]) -> Option<&[u8]> {
+    let len = match *src.first()? {
+        0x90..=0x9f => 1,
+        0xdc => 3,
+        0xdd => 5,
+        _ => return None,
+    };
+    src.get(len..)
+}
+
+fn skip_value(mut src: &[u8]) -> Option<&[u8]> {
+    serde::de::IgnoredAny::deserialize(&mut Deserializer::new(&mut src)).ok()?;
+    Some(src)
+}
+
+/// Fails with `DecodeError::TooDeep` if the message at the start of `src` has record values
+/// nested more than `MAX_VALUE_DEPTH` levels deep, given that they are `nesting` levels deep in
+/// it. Anything else wrong with the message, including it being incomplete, is left to
+/// deserializing it, which won't recurse any deeper than that.
+fn check_nesting(mut src: &[u8], nesting: usize) -> Result<(), DecodeError> {
+    match scan_nesting(&mut src, &mut io::sink(), nesting) {
+        Err(error @ DecodeError::TooDeep(_)) => Err(error),
+        _ => Ok(()),
+    }
+}
+
+/// Copies the msgpack value at the start of `reader` into `out` without deserializing it,
+/// failing as soon as its record values, `nesting` levels deep in it, have arrays and maps nested
+/// more than `MAX_VALUE_DEPTH` levels deep. It's scanned one marker at a time rather than
+/// recursively, so a value nested arbitrarily deep can't exhaust the stack.
+fn scan_nesting<R: Read, W: io::Write>(
+    reader: &mut R,
+    out: &mut W,
+    nesting: usize,
+) -> Result<(), DecodeError> {
+    fn read_len<R: Read, W: io::Write>(
+        reader: &mut R,
+        out: &mut W,
+        bytes: usize,
+    ) -> io::Result<u64> {
+        let mut buf = [0; 4];
+        reader.read_exact(&mut buf[4 - bytes..])?;
+        out.write_all(&buf[4 - bytes..])?;
+        Ok(u64::from(u32::from_be_bytes(buf)))
+    }
+
+    let max_depth = MAX_VALUE_DEPTH + nesting;
+    // elements left to read at each level, the first one being the value itself
+    let mut levels: Vec<u64> = vec![1];
+    while let Some(left) = levels.last_mut() {
+        if *left == 0 {
+            levels.pop();
+            continue;
+        }
+        *left -= 1;
+
+        let mut marker = [0; 1];
+        reader.read_exact(&mut marker)?;
+        out.write_all(&marker)?;
+        let (len_bytes, len, layout) = item_layout(marker[0]);
+        let len = if len_bytes > 0 {
+            read_len(reader, out, len_bytes)?
+        } else {
+            len
+        };
+        let (data, elements) = layout(len);
+        if io::copy(&mut reader.by_ref().take(data), out)? < data {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        if elements > 0 {
+            if levels.len() > max_depth {
+                return Err(DecodeError::TooDeep(MAX_VALUE_DEPTH));
+            }
+            levels.push(elements);
+        }
+    }
+    Ok(())
+}
+
+/// How a msgpack item goes on after its `marker`: the bytes its length takes up, or its length if
+/// that's part of the marker or fixed, and given the length, the bytes of data and the elements
+/// that follow.
+fn item_layout(marker: u8) -> (usize, u64, fn(u64) -> (u64, u64)) {
+    let data: fn(u64) -> (u64, u64) = |len| (len, 0);
+    // ext 8, 16 and 32 have their type after the length
+    let ext: fn(u64) -> (u64, u64) = |len| (len + 1, 0);
+    let array: fn(u64) -> (u64, u64) = |len| (0, len);
+    let map: fn(u64) -> (u64, u64) = |len| (0, 2 * len);
+    match marker {
+        byte @ 0x80..=0x8f => (0, u64::from(byte & 0x0f), map),
+        byte @ 0x90..=0x9f => (0, u64::from(byte & 0x0f), array),
+        byte @ 0xa0..=0xbf => (0, u64::from(byte & 0x1f), data),
+        0xc4 | 0xd9 => (1, 0, data),
+        0xc5 | 0xda => (2, 0, data),
+        0xc6 | 0xdb => (4, 0, data),
+        0xc7 => (1, 0, ext),
+        0xc8 => (2, 0, ext),
+        0xc9 => (4, 0, ext),
+        0xcc | 0xd0 => (0, 1, data),
+        0xcd | 0xd1 => (0, 2, data),
+        0xca | 0xce | 0xd2 => (0, 4, data),
+        0xcb | 0xcf | 0xd3 => (0, 8, data),
+        // fixext 1 to 16, with their type
+        0xd4 => (0, 2, data),
+        0xd5 => (0, 3, data),
+        0xd6 => (0, 5, data),
+        0xd7 => (0, 9, data),
+        0xd8 => (0, 17, data),
+        0xdc => (2, 0, array),
+        0xdd => (4, 0, array),
+        0xde => (2, 0, map),
+        0xdf => (4, 0, map),
+        // nil, booleans, fixints, and the reserved 0xc1 which fails deserializing
+        _ => (0, 0, data),
+    }
+}
+
+/// Scans the message at the start of a buffer for where it ends as it's buffered, picking up where
+/// the last scan left off, like `scan_nesting` does all at once.
+#[derive(Debug)]
+struct MessageScan {
+    // bytes of the message scanned so far, which are whole items
+    len: usize,
+    // elements left to scan at each level, the first one being the message itself
+    levels: Vec<u64>,
+}
+
+impl MessageScan {
+    fn new() -> Self {
+        Self {
+            len: 0,
+            levels: vec![1],
+        }
+    }
+
+    /// The length of the message at the start of `src` once it's all buffered. Fails with
+    /// `DecodeError::TooDeep` as soon as its record values, `nesting` levels deep in it, are
+    /// found to be nested more than `MAX_VALUE_DEPTH` levels deep.
+    fn resume(&mut self, src: &[u8], nesting: usize) -> Result<Option<usize>, DecodeError> {
+        let max_depth = MAX_VALUE_DEPTH + nesting;
+        while let Some(left) = self.levels.last_mut() {
+            if *left == 0 {
+                self.levels.pop();
+                continue;
+            }
+
+            let rest = &src[self.len..];
+            let (len_bytes, len, layout) = match rest.first() {
+                Some(marker) => item_layout(*marker),
+                None => return Ok(None),
+            };
+            let len = if len_bytes > 0 {
+                match rest.get(1..1 + len_bytes) {
+                    Some(bytes) => bytes
+                        .iter()
+                        .fold(0, |len, byte| (len << 8) | u64::from(*byte)),
+                    None => return Ok(None),
+                }
+            } else {
+                len
+            };
+            let (data, elements) = layout(len);
+            let item_len = 1 + len_bytes as u64 + data;
+            if (rest.len() as u64) < item_len {
+                return Ok(None);
+            }
+
+            *left -= 1;
+            self.len += item_len as usize;
+            if elements > 0 {
+                if self.levels.len() > max_depth {
+                    return Err(DecodeError::TooDeep(MAX_VALUE_DEPTH));
+                }
+                self.levels.push(elements);
+            }
+        }
+        Ok(Some(self.len))
+    }
+}
+
+/// Normalized fluent message.
+#[derive(Debug, PartialEq)]
+struct FluentFrame {
+    tag: Arc<str>,
+    timestamp: FluentTimestamp,
+    record: FluentRecord,
+    // msgpack bytes of the message, or of the entry for forward modes
+    byte_size: usize,
+    // shared by all entries of a forward mode message
+    chunk: Option<String>,
+    // all entries of a forward mode message with `batch_mode` set to `per_message`, in which
+    // case `timestamp` is that of the first one and `record` is empty
+    entries: Vec<FluentEntry>,
+    // msgpack bytes of the whole message with `raw_message_key` set, shared by all its entries
+    raw_message: Option<Bytes>,
+}
+
+impl FluentFrame {
+    fn batch(frames: VecDeque<FluentFrame>) -> Self {
+        let byte_size = frames.iter().map(|frame| frame.byte_size).sum();
+        let mut frames = frames.into_iter();
+        let first = frames.next().expect("at least one frame");
+        let mut entries = vec![FluentEntry(first.timestamp.clone(), first.record)];
+        entries.extend(frames.map(|frame| FluentEntry(frame.timestamp, frame.record)));
+        Self {
+            tag: first.tag,
+            timestamp: first.timestamp,
+            record: FluentRecord(Vec::new()),
+            byte_size,
+            chunk: first.chunk,
+            entries,
+            raw_message: first.raw_message,
+        }
+    }
+}
+
+/// Fluent msgpack messages can be encoded in one of three ways, each with and without
+/// options, all using arrays to encode the top-level fields.
+///
+/// The spec refers to 4 ways, but really CompressedPackedForward is encoded the same as
+/// PackedForward, it just has an additional decompression step.
+///
+/// Not yet handled are the handshake messages.
+///
+/// https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1#event-modes
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum FluentMessage {
+    Message(FluentTag, FluentTimestamp, FluentRecord),
+    // I attempted to just one variant for each of these, with and without options, using an
+    // `Option` for the last element, but rmp expected the number of elements to match in that case
+    // still (it just allows the last element to be `nil`).
+    MessageWithOptions(
+        FluentTag,
+        FluentTimestamp,
+        FluentRecord,
+        FluentMessageOptions,
+    ),
+    Forward(FluentTag, Vec<FluentEntry>),
+    ForwardWithOptions(FluentTag, Vec<FluentEntry>, FluentMessageOptions),
+    PackedForward(FluentTag, serde_bytes::ByteBuf),
+    PackedForwardWithOptions(FluentTag, serde_bytes::ByteBuf, FluentMessageOptions),
+
+    // should be last as it'll match any other message
+    Heartbeat(rmpv::Value), // should be Nil if heartbeat
+}
+
+/// Puts the elements of a message some clients send in a non-conformant order, such as with the
+/// options before the record, in the order of the specification, returning the message that
+/// matches if there is one. The record and the options are told apart by the keys options have.
+fn reorder_message(value: &rmpv::Value) -> Option<FluentMessage> {
+    let elements = match value {
+        rmpv::Value::Array(elements) if (2..=4).contains(&elements.len()) => elements,
+        _ => return None,
+    };
+    let mut tag = None;
+    let mut timestamp = None;
+    let mut entries = None;
+    let mut maps = Vec::new();
+    for element in elements {
+        match element {
+            rmpv::Value::String(_) if tag.is_none() => tag = Some(element),
+            rmpv::Value::Integer(_)
+            | rmpv::Value::F32(_)
+            | rmpv::Value::F64(_)
+            | rmpv::Value::Ext(_, _)
+                if timestamp.is_none() =>
+            {
+                timestamp = Some(element)
+            }
+            rmpv::Value::Array(_) | rmpv::Value::Binary(_) if entries.is_none() => {
+                entries = Some(element)
+            }
+            rmpv::Value::Map(_) => maps.push(element),
+            _ => return None,
+        }
+    }
+    let is_options = |map: &rmpv::Value| match map {
+        rmpv::Value::Map(options) => options.iter().all(|(key, _)| {
+            matches!(
+                key.as_str(),
+                Some("size") | Some("chunk") | Some("compressed")
+            )
+        }),
+        _ => false,
+    };
+
+    let mut reordered = vec![tag?.clone()];
+    match (timestamp, entries, maps.len()) {
+        (Some(timestamp), None, 1) | (Some(timestamp), None, 2) => {
+            reordered.push(timestamp.clone());
+            if maps.len() == 2 && is_options(maps[0]) && !is_options(maps[1]) {
+                maps.swap(0, 1);
+            }
+        }
+        (None, Some(entries), 0) | (None, Some(entries), 1) => reordered.push(entries.clone()),
+        _ => return None,
+    }
+    reordered.extend(maps.into_iter().cloned());
+    if reordered == *elements {
+        return None;
+    }
+
+    let mut message = Vec::new();
+    rmpv::encode::write_value(&mut message, &rmpv::Value::Array(reordered)).ok()?;
+    match FluentMessage::deserialize(&mut Deserializer::new(io::Cursor::new(&message[..]))) {
+        Ok(FluentMessage::Heartbeat(_)) | Err(_) => None,
+        Ok(message) => Some(message),
+    }
+}
+
+/// Wraps the record of a message in message mode that some clients send as an array, rather than a
+/// map, in a map under `key`, returning the message that then matches if there is one.
+fn wrap_array_record(value: &rmpv::Value, key: &str) -> Option<FluentMessage> {
+    let mut elements = match value {
+        rmpv::Value::Array(elements) if (3..=4).contains(&elements.len()) => elements.clone(),
+        _ => return None,
+    };
+    if !elements[2].is_array() {
+        return None;
+    }
+    let record = std::mem::replace(&mut elements[2], rmpv::Value::Nil);
+    elements[2] = rmpv::Value::Map(vec![(key.into(), record)]);
+
+    let mut message = Vec::new();
+    rmpv::encode::write_value(&mut message, &rmpv::Value::Array(elements)).ok()?;
+    match FluentMessage::deserialize(&mut Deserializer::new(io::Cursor::new(&message[..]))) {
+        Ok(message @ FluentMessage::Message(..))
+        | Ok(message @ FluentMessage::MessageWithOptions(..)) => Some(message),
+        _ => None,
+    }
+}
+
+/// Server options sent by client.
+///
+/// https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1#option
+#[derive(Default, Debug, Deserialize)]
+#[serde(default)]
+struct FluentMessageOptions {
+    size: Option<u64>,          // client provided hint for the number of entries
+    chunk: Option<String>,      // message id, would also be used for acks
+    compressed: Option<String>, // this one is required if present
+}
+
+/// Fluent entry consisting of timestamp and record.
+///
+/// https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1#forward-mode
+#[derive(Debug, Deserialize, PartialEq)]
+struct FluentEntry(FluentTimestamp, FluentRecord);
+
+/// Fluent record is just key/value pairs.
+///
+/// Keys are kept as they are, as they aren't necessarily valid UTF-8, and only converted to
+/// strings once the encoding for invalid ones is known.
+#[derive(Debug, PartialEq)]
+struct FluentRecord(Vec<(rmpv::Value, FluentValue)>);
+
+impl<'de> serde::de::Deserialize<'de> for FluentRecord {
+    fn deserialize<D>(deserializer: D) -> Result<FluentRecord, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct FluentRecordVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for FluentRecordVisitor {
+            type Value = FluentRecord;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                formatter.write_str("fluent record map")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                let mut entries = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(FluentRecord(entries))
+            }
+        }
+
+        deserializer.deserialize_map(FluentRecordVisitor)
+    }
+}
+
+/// Fluent message tag.
+type FluentTag = String;
+
+/// Value for fluent record key.
+///
+/// Used mostly just to implement value conversion.
+#[derive(Debug, Deserialize, PartialEq)]
+struct FluentValue(rmpv::Value);
+
+/// Replaces the arrays and maps in `value` that are nested more than `max_depth` levels deep with
+/// `TOO_DEEP_PLACEHOLDER`, returning whether there were any. The value was deserialized already,
+/// so it's at most `MAX_VALUE_DEPTH` levels deep, which bounds the recursion here and in the
+/// conversion into a `Value` afterwards.
+fn truncate_depth(value: &mut rmpv::Value, max_depth: usize) -> bool {
+    match value {
+        rmpv::Value::Array(_) | rmpv::Value::Map(_) if max_depth == 0 => {
+            *value = rmpv::Value::from(TOO_DEEP_PLACEHOLDER);
+            true
+        }
+        rmpv::Value::Array(values) => values.iter_mut().fold(false, |truncated, value| {
+            truncate_depth(value, max_depth - 1) | truncated
+        }),
+        rmpv::Value::Map(entries) => entries.iter_mut().fold(false, |truncated, (key, value)| {
+            truncate_depth(key, max_depth - 1) | truncate_depth(value, max_depth - 1) | truncated
+        }),
+        _ => false,
+    }
+}
+
+impl FluentValue {
+    /// Extension values with a code in `ext_decoders` are decoded accordingly, any others are
+    /// kept as a map of their code and bytes. Integers that don't fit in an `i64` are kept as
+    /// strings, or as the nearest float with `large_int_as_float`.
+    fn into_value(
+        self,
+        ext_decoders: &BTreeMap<i8, FluentExtDecoding>,
+        large_int_as_float: bool,
+    ) -> Value {
+        match self.0 {
+            rmpv::Value::Nil => Value::Null,
+            rmpv::Value::Boolean(b) => Value::Boolean(b),
+            rmpv::Value::Integer(i) => {
+                i.as_i64()
+                    .map(Value::Integer)
+                    .unwrap_or_else(|| match i.as_f64() {
+                        Some(f) if large_int_as_float => Value::Float(f),
+                        // unwrap large numbers to string similar to how `From<serde_json::Value> for Value` handles it
+                        _ => Value::Bytes(i.to_string().into()),
+                    })
+            }
+            rmpv::Value::F32(f) => Value::Float(f.into()),
+            rmpv::Value::F64(f) => Value::Float(f),
+            rmpv::Value::String(s) => Value::Bytes(s.into_bytes().into()),
+            rmpv::Value::Binary(bytes) => Value::Bytes(bytes.into()),
+            rmpv::Value::Array(values) => Value::Array(
+                values
+                    .into_iter()
+                    .map(|value| FluentValue(value).into_value(ext_decoders, large_int_as_float))
+                    .collect(),
+            ),
+            rmpv::Value::Map(values) => Value::Map(
+                values
+                    .into_iter()
+                    .map(|(key, value)| {
+                        (
+                            format!("{}", key),
+                            FluentValue(value).into_value(ext_decoders, large_int_as_float),
+                        )
+                    })
+                    .collect(),
+            ),
+            rmpv::Value::Ext(code, bytes) => {
+                if let Some(value) = ext_decoders
+                    .get(&code)
+                    .and_then(|decoding| decoding.decode(&bytes))
+                {
+                    return value;
+                }
+                let mut fields = BTreeMap::new();
+                fields.insert(
+                    String::from("msgpack_extension_code"),
+                    Value::Integer(code.into()),
+                );
+                fields.insert(String::from("bytes"), Value::Bytes(bytes.into()));
+                Value::Map(fields)
+            }
+        }
+    }
+}
+
+/// Fluent message timestamp.
+///
+/// Message timestamps can be a unix timestamp, either integer or floating-point, or EventTime
+/// messagepack ext.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+enum FluentTimestamp {
+    /// Along with the number of seconds as received, if it was received at all.
+    #[serde(deserialize_with = "deserialize_unix_timestamp")]
+    Unix(DateTime<Utc>, Option<Value>),
+    Ext(FluentEventTime),
+}
+
+impl FluentTimestamp {
+    /// The timestamp as received, for `raw_timestamp_key`: the number of seconds of a unix
+    /// timestamp, or the type and base64 encoded payload of an extension.
+    fn raw(&self) -> Option<Value> {
+        match self {
+            FluentTimestamp::Unix(_, seconds) => seconds.clone(),
+            FluentTimestamp::Ext(FluentEventTime(_, (tag, data))) => {
+                let mut raw = BTreeMap::new();
+                raw.insert("type".to_owned(), Value::Integer(i64::from(*tag)));
+                raw.insert("data".to_owned(), Value::from(base64::encode(data)));
+                Some(Value::Map(raw))
+            }
+        }
+    }
+}
+
+impl From<FluentTimestamp> for Value {
+    fn from(timestamp: FluentTimestamp) -> Self {
+        match timestamp {
+            FluentTimestamp::Unix(timestamp, _)
+            | FluentTimestamp::Ext(FluentEventTime(Ok(timestamp), _)) => {
+                Value::Timestamp(timestamp)
+            }
+            // only kept with `lenient_timestamps`
+            FluentTimestamp::Ext(FluentEventTime(Err(_), _)) => Value::Timestamp(Utc::now()),
+        }
+    }
+}
+
+/// Decodes a unix timestamp in seconds, keeping the fractional part of floating-point values, as
+/// well as the number itself.
+fn deserialize_unix_timestamp<'de, D>(
+    deserializer: D,
+) -> Result<(DateTime<Utc>, Option<Value>), D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    struct UnixTimestampVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for UnixTimestampVisitor {
+        type Value = (DateTime<Utc>, Option<Value>);
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            formatter.write_str("unix timestamp as an integer or float")
+        }
+
+        fn visit_i64<E>(self, seconds: i64) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            Utc.timestamp_opt(seconds, 0)
+                .single()
+                .map(|timestamp| (timestamp, Some(Value::Integer(seconds))))
+                .ok_or_else(|| E::custom(format!("timestamp out of range: {}", seconds)))
+        }
+
+        fn visit_u64<E>(self, seconds: u64) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            let seconds = i64::try_from(seconds)
+                .map_err(|_| E::custom(format!("timestamp out of range: {}", seconds)))?;
+            self.visit_i64(seconds)
+        }
+
+        fn visit_f64<E>(self, value: f64) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            if !value.is_finite() || value.abs() >= i64::MAX as f64 {
+                return Err(E::custom(format!("timestamp out of range: {}", value)));
+            }
+
+            let seconds = value.floor();
+            // A double only holds about microsecond precision for current dates, anything finer
+            // is floating-point noise.
+            let micros = ((value - seconds) * 1_000_000.0).round() as u32;
+            Utc.timestamp_opt(seconds as i64, 0)
+                .single()
+                .map(|timestamp| {
+                    (
+                        timestamp + chrono::Duration::microseconds(micros.into()),
+                        Some(Value::Float(value)),
+                    )
+                })
+                .ok_or_else(|| E::custom(format!("timestamp out of range: {}", value)))
+        }
+    }
+
+    deserializer.deserialize_any(UnixTimestampVisitor)
+}
+
+/// Custom decoder for Fluent's EventTime msgpack extension, as well as msgpack's own timestamp
+/// extension, which some clients send instead.
+///
+/// https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1#eventtime-ext-format
+/// https://github.com/msgpack/msgpack/blob/master/spec.md#timestamp-extension-type
+///
+/// Extensions with another type or a payload of the wrong length are decoded as the reason they're
+/// malformed, so that the decoder can decide whether that fails the message. Anything that isn't
+/// shaped like an extension, such as an array that isn't one, fails to deserialize so that it
+/// doesn't match frames it isn't part of. The extension type and payload are kept as received.
+#[derive(Clone, Debug, PartialEq)]
+struct FluentEventTime(Result<DateTime<Utc>, String>, (i8, Vec<u8>));
+
+impl<'de> serde::de::Deserialize<'de> for FluentEventTime {
+    fn deserialize<D>(deserializer: D) -> Result<FluentEventTime, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct FluentEventTimeVisitor {
+            // whether the sequence is the content of an extension rather than a plain array
+            ext: bool,
+        }
+
+        impl<'de> serde::de::Visitor<'de> for FluentEventTimeVisitor {
+            type Value = FluentEventTime;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                formatter.write_str("fluent or msgpack timestamp extension")
+            }
+
+            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+            where
+                D: serde::de::Deserializer<'de>,
+            {
+                deserializer.deserialize_tuple(2, FluentEventTimeVisitor { ext: true })
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                let tag: i8 = seq
+                    .next_element()?
+                    .ok_or_else(|| serde::de::Error::invalid_length(0, &self))?;
+                let bytes: serde_bytes::ByteBuf = seq
+                    .next_element()?
+                    .ok_or_else(|| serde::de::Error::invalid_length(1, &self))?;
+                if seq.next_element::<serde::de::IgnoredAny>()?.is_some() {
+                    return Err(serde::de::Error::invalid_length(3, &self));
+                }
+
+                // lengths checked by the patterns
+                let be_u32 = |bytes: &[u8]| u32::from_be_bytes(bytes.try_into().expect("4 bytes"));
+                let decoded = match (tag, bytes.len()) {
+                    // fluent EventTime
+                    (0, 8) => Ok((i64::from(be_u32(&bytes[..4])), be_u32(&bytes[4..]))),
+                    (0, len) => Err(format!(
+                        "expected exactly 8 bytes for binary encoded fluent timestamp, got {}",
+                        len
+                    )),
+                    // msgpack timestamp 32
+                    (-1, 4) => Ok((i64::from(be_u32(&bytes[..])), 0)),
+                    // msgpack timestamp 64, 30 bits of nanoseconds then 34 bits of seconds
+                    (-1, 8) => {
+                        let value = u64::from_be_bytes(bytes[..].try_into().expect("8 bytes"));
+                        Ok(((value & 0x3_ffff_ffff) as i64, (value >> 34) as u32))
+                    }
+                    // msgpack timestamp 96
+                    (-1, 12) => Ok((
+                        i64::from_be_bytes(bytes[4..].try_into().expect("8 bytes")),
+                        be_u32(&bytes[..4]),
+                    )),
+                    (-1, len) => Err(format!(
+                        "expected 4, 8 or 12 bytes for binary encoded msgpack timestamp, got {}",
+                        len
+                    )),
+                    (tag, _) => Err(format!(
+                        "expected extension type 0 or -1 for fluent timestamp, got {}",
+                        tag
+                    )),
+                };
+                let timestamp = decoded.and_then(|(seconds, nanoseconds)| {
+                    Utc.timestamp_opt(seconds, nanoseconds)
+                        .single()
+                        .ok_or_else(|| {
+                            format!(
+                                "timestamp out of range: {} seconds and {} nanoseconds",
+                                seconds, nanoseconds
+                            )
+                        })
+                });
+
+                match timestamp {
+                    // only extensions are kept when malformed, plain arrays are no timestamps
+                    Err(reason) if !self.ext => Err(serde::de::Error::custom(reason)),
+                    timestamp => Ok(FluentEventTime(timestamp, (tag, bytes.into_vec()))),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(FluentEventTimeVisitor { ext: false })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        event::metric::MetricValue,
+        test_util::{
+            collect_n, counter, fluent::FluentClient, next_addr, tagged_counter, wait_for_tcp,
+        },
+        Pipeline,
+    };
+    use futures::StreamExt;
+    use openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslVerifyMode};
+    use shared::{assert_event_data_eq, btreemap};
+    use std::{pin::Pin, time::Duration};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        time::{sleep, timeout},
+    };
+
+    #[test]
+    fn generate_config() {
+        crate::test_util::test_generate_config::<FluentConfig>();
+    }
+
+    // useful references for msgpack:
+    // Spec: https://github.com/msgpack/msgpack/blob/master/spec.md
+    // Encode to array of bytes: https://kawanet.github.io/msgpack-lite/
+    // Decode base64: https://toolslick.com/conversion/data/messagepack-to-json
+
+    #[test]
+    fn decode_message_mode() {
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+
+        let expected = LogEvent::from(btreemap! {
+            "message" => "bar",
+            "tag" => "tag.name",
+            "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:04Z").unwrap().into()),
+        });
+        assert_event_data_eq!(decode_all(message).unwrap()[0], expected);
+    }
+
+    #[test]
+    fn decode_message_mode_with_options() {
+        //[
+        //  "tag.name",
+        //   1441588984,
+        //   { "message": "bar" },
+        //   { "size": 1 }
+        //]
+        let message: Vec<u8> = vec![
+            148, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114, 129, 164, 115, 105, 122, 101, 1,
+        ];
+
+        let expected = LogEvent::from(btreemap! {
+            "message" => "bar",
+            "tag" => "tag.name",
+            "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:04Z").unwrap().into()),
+        });
+        assert_event_data_eq!(decode_all(message).unwrap()[0], expected);
+    }
+
+    #[test]
+    fn decode_forward_mode() {
+        //[
+        //    "tag.name",
+        //    [
+        //        [1441588984, {"message": "foo"}],
+        //        [1441588985, {"message": "bar"}],
+        //        [1441588986, {"message": "baz"}]
+        //    ]
+        //]
+        let message: Vec<u8> = vec![
+            146, 168, 116, 97, 103, 46, 110, 97, 109, 101, 147, 146, 206, 85, 236, 230, 248, 129,
+            167, 109, 101, 115, 115, 97, 103, 101, 163, 102, 111, 111, 146, 206, 85, 236, 230, 249,
+            129, 167, 109, 101, 115, 115, 97, 103, 101, 163, 98, 97, 114, 146, 206, 85, 236, 230,
+            250, 129, 167, 109, 101, 115, 115, 97, 103, 101, 163, 98, 97, 122,
+        ];
+
+        let expected = vec![
+            LogEvent::from(btreemap! {
+                "message" => "foo",
+                "tag" => "tag.name",
+                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:04Z").unwrap().into()),
+            }),
+            LogEvent::from(btreemap! {
+                "message" => "bar",
+                "tag" => "tag.name",
+                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:05Z").unwrap().into()),
+            }),
+            LogEvent::from(btreemap! {
+                "message" => "baz",
+                "tag" => "tag.name",
+                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:06Z").unwrap().into()),
+            }),
+        ];
+
+        let got = decode_all(message).unwrap();
+
+        assert_event_data_eq!(got[0], expected[0]);
+        assert_event_data_eq!(got[1], expected[1]);
+        assert_event_data_eq!(got[2], expected[2]);
+    }
+
+    #[test]
+    fn shared_tag() {
+        let entry = rmpv::Value::Array(vec![
+            1441588984.into(),
+            rmpv::Value::Map(vec![("message".into(), "bar".into())]),
+        ]);
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                rmpv::Value::Array(vec![entry; 1000]),
+            ]),
+        )
+        .unwrap();
+
+        let mut buf = BytesMut::from(&message[..]);
+        let mut decoder = source("").decoder(test_peer_addr());
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.decode(&mut buf).unwrap() {
+            frames.push(frame);
+        }
+
+        // a single allocation of the tag for all entries
+        assert_eq!(frames.len(), 1000);
+        assert_eq!(&*frames[0].tag, "tag.name");
+        assert!(frames
+            .iter()
+            .all(|frame| Arc::ptr_eq(&frame.tag, &frames[0].tag)));
+    }
+
+    #[test]
+    fn batch_mode() {
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                rmpv::Value::Array(
+                    (0..3)
+                        .map(|i| {
+                            rmpv::Value::Array(vec![
+                                (1441588984 + i).into(),
+                                rmpv::Value::Map(vec![("index".into(), i.into())]),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ]),
+        )
+        .unwrap();
+        let timestamp = |secs| Value::Timestamp(Utc.timestamp(secs, 0));
+
+        let got = decode_all_with(&source("batch_mode = \"per_entry\""), message.clone()).unwrap();
+        assert_eq!(got.len(), 3);
+        for (i, log) in got.iter().enumerate() {
+            assert_eq!(log["tag"], "tag.name".into());
+            assert_eq!(log["index"], Value::Integer(i as i64));
+            assert_eq!(log["timestamp"], timestamp(1441588984 + i as i64));
+        }
+
+        let got = decode_all_with(&source("batch_mode = \"per_message\""), message).unwrap();
+        assert_eq!(got.len(), 1);
+        assert_event_data_eq!(
+            got[0],
+            LogEvent::from(btreemap! {
+                "tag" => "tag.name",
+                "timestamp" => timestamp(1441588984),
+                "records" => (0..3)
+                    .map(|i| Value::from(btreemap! {
+                        "index" => Value::Integer(i),
+                        "timestamp" => timestamp(1441588984 + i),
+                    }))
+                    .collect::<Vec<_>>(),
+            })
+        );
+    }
+
+    #[test]
+    fn decode_forward_mode_with_options() {
+        //[
+        //    "tag.name",
+        //    [
+        //        [1441588984, {"message": "foo"}],
+        //        [1441588985, {"message": "bar"}],
+        //        [1441588986, {"message": "baz"}]
+        //    ],
+        //    {"size": 3}
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 147, 146, 206, 85, 236, 230, 248, 129,
+            167, 109, 101, 115, 115, 97, 103, 101, 163, 102, 111, 111, 146, 206, 85, 236, 230, 249,
+            129, 167, 109, 101, 115, 115, 97, 103, 101, 163, 98, 97, 114, 146, 206, 85, 236, 230,
+            250, 129, 167, 109, 101, 115, 115, 97, 103, 101, 163, 98, 97, 122, 129, 164, 115, 105,
+            122, 101, 3,
+        ];
+
+        let expected = vec![
+            LogEvent::from(btreemap! {
+                "message" => "foo",
+                "tag" => "tag.name",
+                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:04Z").unwrap().into()),
+            }),
+            LogEvent::from(btreemap! {
+                "message" => "bar",
+                "tag" => "tag.name",
+                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:05Z").unwrap().into()),
+            }),
+            LogEvent::from(btreemap! {
+                "message" => "baz",
+                "tag" => "tag.name",
+                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:06Z").unwrap().into()),
+            }),
+        ];
+
+        let got = decode_all(message).unwrap();
+
+        assert_event_data_eq!(got[0], expected[0]);
+        assert_event_data_eq!(got[1], expected[1]);
+        assert_event_data_eq!(got[2], expected[2]);
+    }
+
+    #[test]
+    fn decode_packed_forward_mode() {
+        //[
+        //    "tag.name",
+        //    <packed messages>
+        //]
+        //
+        //With packed messages as bin:
+        // [1441588984, {"message": "foo"}]
+        // [1441588985, {"message": "bar"}]
+        // [1441588986, {"message": "baz"}]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 196, 57, 146, 206, 85, 236, 230, 248,
+            129, 167, 109, 101, 115, 115, 97, 103, 101, 163, 102, 111, 111, 146, 206, 85, 236, 230,
+            249, 129, 167, 109, 101, 115, 115, 97, 103, 101, 163, 98, 97, 114, 146, 206, 85, 236,
+            230, 250, 129, 167, 109, 101, 115, 115, 97, 103, 101, 163, 98, 97, 122, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 102, 111, 111,
+        ];
+
+        let expected = vec![
+            LogEvent::from(btreemap! {
+                "message" => "foo",
+                "tag" => "tag.name",
+                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:04Z").unwrap().into()),
+            }),
+            LogEvent::from(btreemap! {
+                "message" => "bar",
+                "tag" => "tag.name",
+                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:05Z").unwrap().into()),
+            }),
+            LogEvent::from(btreemap! {
+                "message" => "baz",
+                "tag" => "tag.name",
+                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:06Z").unwrap().into()),
+            }),
+        ];
+
+        let got = decode_all(message).unwrap();
+
+        assert_event_data_eq!(got[0], expected[0]);
+        assert_event_data_eq!(got[1], expected[1]);
+        assert_event_data_eq!(got[2], expected[2]);
+    }
+
+    //  TODO
+    #[test]
+    fn decode_compressed_packed_forward_mode() {
+        //[
+        //    "tag.name",
+        //    <packed messages>,
+        //    {"compressed": "gzip"}
+        //]
+        //
+        //With gzip'd packed messages as bin:
+        // [1441588984, {"message": "foo"}]
+        // [1441588985, {"message": "bar"}]
+        // [1441588986, {"message": "baz"}]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 196, 55, 31, 139, 8, 0, 245, 10, 168,
+            96, 0, 3, 155, 116, 46, 244, 205, 179, 31, 141, 203, 115, 83, 139, 139, 19, 211, 83,
+            23, 167, 229, 231, 79, 2, 9, 253, 68, 8, 37, 37, 22, 129, 133, 126, 33, 11, 85, 1, 0,
+            53, 3, 158, 28, 57, 0, 0, 0, 129, 170, 99, 111, 109, 112, 114, 101, 115, 115, 101, 100,
+            164, 103, 122, 105, 112,
+        ];
+
+        let expected = vec![
+            LogEvent::from(btreemap! {
+                "message" => "foo",
+                "tag" => "tag.name",
+                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:04Z").unwrap().into()),
+            }),
+            LogEvent::from(btreemap! {
+                "message" => "bar",
+                "tag" => "tag.name",
+                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:05Z").unwrap().into()),
+            }),
+            LogEvent::from(btreemap! {
+                "message" => "baz",
+                "tag" => "tag.name",
+                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:06Z").unwrap().into()),
+            }),
+        ];
+
+        let got = decode_all(message).unwrap();
+
+        assert_event_data_eq!(got[0], expected[0]);
+        assert_event_data_eq!(got[1], expected[1]);
+        assert_event_data_eq!(got[2], expected[2]);
+    }
+
+    #[test]
+    fn peer_port() {
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+        let peer_addr = "10.1.2.3:51234".parse().unwrap();
+
+        let source = source("");
+        let event = build_all(&source, message.clone(), peer_addr).remove(0);
+        assert_eq!(event.as_log()[log_schema().host_key()], "10.1.2.3".into());
+        assert!(!event.as_log().contains("port"));
+
+        let source = source(r#"port_key = "port""#);
+        let event = build_all(&source, message, peer_addr).remove(0);
+        assert_eq!(event.as_log()[log_schema().host_key()], "10.1.2.3".into());
+        assert_eq!(event.as_log()["port"], 51234.into());
+    }
+
+    #[test]
+    fn key_remapping() {
+        let message = |fields: Vec<(&str, &str)>| {
+            let mut message = Vec::new();
+            rmpv::encode::write_value(
+                &mut message,
+                &rmpv::Value::Array(vec![
+                    "tag.name".into(),
+                    1441588984.into(),
+                    rmpv::Value::Map(
+                        fields
+                            .into_iter()
+                            .map(|(key, value)| (key.into(), value.into()))
+                            .collect(),
+                    ),
+                ]),
+            )
+            .unwrap();
+            message
+        };
+        let source = source(
+            r#"
+            [key_remapping]
+            "@timestamp" = "event_time"
+            "kubernetes.pod name" = "pod_name"
+            "#,
+        );
+
+        let event = decode_all_with(
+            &source,
+            message(vec![
+                ("@timestamp", "2021-01-01T00:00:00Z"),
+                ("level", "info"),
+            ]),
+        )
+        .unwrap()
+        .remove(0);
+        assert_eq!(event["event_time"], "2021-01-01T00:00:00Z".into());
+        assert!(!event.as_map().contains_key("@timestamp"));
+        // unlisted keys are left as they are
+        assert_eq!(event["level"], "info".into());
+
+        // a renamed field wins over one that already has its new name, whichever comes first
+        let fields = vec![("pod_name", "existing"), ("kubernetes.pod name", "renamed")];
+        let event = decode_all_with(&source, message(fields.clone()))
+            .unwrap()
+            .remove(0);
+        assert_eq!(event["pod_name"], "renamed".into());
+        assert!(!event.as_map().contains_key("kubernetes.pod name"));
+        let reversed = fields.into_iter().rev().collect();
+        let event = decode_all_with(&source, message(reversed))
+            .unwrap()
+            .remove(0);
+        assert_eq!(event["pod_name"], "renamed".into());
+    }
+
+    #[test]
+    fn flatten_records() {
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"kubernetes": {"pod": {"name": "foo", "labels": {}}, "namespace": "bar"}, "message": "baz"},
+        //]
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                1441588984.into(),
+                rmpv::Value::Map(vec![
+                    (
+                        "kubernetes".into(),
+                        rmpv::Value::Map(vec![
+                            (
+                                "pod".into(),
+                                rmpv::Value::Map(vec![
+                                    ("name".into(), "foo".into()),
+                                    ("labels".into(), rmpv::Value::Map(vec![])),
+                                ]),
+                            ),
+                            ("namespace".into(), "bar".into()),
+                        ]),
+                    ),
+                    ("message".into(), "baz".into()),
+                ]),
+            ]),
+        )
+        .unwrap();
+
+        let event = decode_all_with(&source("flatten_records = true"), message.clone())
+            .unwrap()
+            .remove(0);
+        let fields = event.as_map();
+        assert_eq!(fields["kubernetes.pod.name"], "foo".into());
+        assert_eq!(fields["kubernetes.pod.labels"], Value::Map(BTreeMap::new()));
+        assert_eq!(fields["kubernetes.namespace"], "bar".into());
+        assert_eq!(fields["message"], "baz".into());
+        assert!(!fields.contains_key("kubernetes"));
+
+        let event = decode_all_with(
+            &source(
+                r#"
+                flatten_records = true
+                flatten_separator = "_"
+                flatten_max_depth = 1
+                "#,
+            ),
+            message,
+        )
+        .unwrap()
+        .remove(0);
+        let fields = event.as_map();
+        assert_eq!(
+            fields["kubernetes_pod"],
+            Value::Map(btreemap! {
+                "name" => "foo",
+                "labels" => Value::Map(BTreeMap::new()),
+            })
+        );
+        assert_eq!(fields["kubernetes_namespace"], "bar".into());
+    }
+
+    #[test]
+    fn parse_key_paths() {
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"kubernetes.pod_name": "foo"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 179, 107,
+            117, 98, 101, 114, 110, 101, 116, 101, 115, 46, 112, 111, 100, 95, 110, 97, 109, 101,
+            163, 102, 111, 111,
+        ];
+
+        let event = decode_all(message.clone()).unwrap().remove(0);
+        assert_eq!(
+            event.as_map().get("kubernetes.pod_name"),
+            Some(&Value::from("foo"))
+        );
+        assert!(!event.contains("kubernetes"));
+
+        let event = decode_all_with(&source("parse_key_paths = true"), message)
+            .unwrap()
+            .remove(0);
+        assert_eq!(event["kubernetes.pod_name"], "foo".into());
+        assert!(!event.as_map().contains_key("kubernetes.pod_name"));
+    }
+
+    #[test]
+    fn host_key() {
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+        let peer_addr = "10.1.2.3:51234".parse().unwrap();
+
+        let event = build_all(&source(""), message.clone(), peer_addr).remove(0);
+        assert_eq!(event.as_log()[log_schema().host_key()], "10.1.2.3".into());
+
+        let source_with_key = source(r#"host_key = "source_ip""#);
+        let event = build_all(&source_with_key, message.clone(), peer_addr).remove(0);
+        assert_eq!(event.as_log()["source_ip"], "10.1.2.3".into());
+        assert!(!event.as_log().contains(log_schema().host_key()));
+
+        let source_without_key = source(r#"host_key = """#);
+        let event = build_all(&source_without_key, message, peer_addr).remove(0);
+        assert!(!event.as_log().contains(log_schema().host_key()));
+        assert_eq!(event.as_log().keys().count(), 4);
+    }
+
+    #[test]
+    fn source_type_key() {
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+        let peer_addr = test_peer_addr();
+
+        let event = build_all(&source(""), message.clone(), peer_addr).remove(0);
+        assert_eq!(
+            event.as_log()[log_schema().source_type_key()],
+            "fluent".into()
+        );
+
+        let source_with_key = source(r#"source_type_key = "origin""#);
+        let event = build_all(&source_with_key, message.clone(), peer_addr).remove(0);
+        assert_eq!(event.as_log()["origin"], "fluent".into());
+        assert!(!event.as_log().contains(log_schema().source_type_key()));
+
+        let source_without_key = source(r#"source_type_key = """#);
+        let event = build_all(&source_without_key, message, peer_addr).remove(0);
+        assert!(!event.as_log().contains(log_schema().source_type_key()));
+    }
+
+    #[tokio::test]
+    async fn peer_hostname() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+        let known = "10.1.2.3:51234".parse().unwrap();
+        let unknown = "10.1.2.4:51234".parse().unwrap();
+
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let mut source = source("");
+        source.peer_hostnames = Some(Arc::new(PeerHostnames::new("peer_hostname".to_owned(), {
+            let lookups = Arc::clone(&lookups);
+            move |ip| {
+                lookups.fetch_add(1, Ordering::SeqCst);
+                let hostname = if ip == "10.1.2.3".parse::<IpAddr>().unwrap() {
+                    Some("client.example.com".to_owned())
+                } else {
+                    None
+                };
+                futures::future::ready(hostname).boxed()
+            }
+        })));
+
+        source.connection_accepted(known).await;
+        let event = build_all(&source, message.clone(), known).remove(0);
+        assert_eq!(event.as_log()["peer_hostname"], "client.example.com".into());
+
+        // falls back to the address without a hostname
+        source.connection_accepted(unknown).await;
+        let event = build_all(&source, message, unknown).remove(0);
+        assert_eq!(event.as_log()["peer_hostname"], "10.1.2.4".into());
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+
+        // both are cached
+        source.connection_accepted(known).await;
+        source.connection_accepted(unknown).await;
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn host_scope_id() {
+        use std::net::{Ipv6Addr, SocketAddrV6};
+
+        let addr = |ip: &str, scope_id| {
+            SocketAddr::V6(SocketAddrV6::new(
+                ip.parse::<Ipv6Addr>().unwrap(),
+                51234,
+                0,
+                scope_id,
+            ))
+        };
+        assert_eq!(host(addr("fe80::1", 2)), "fe80::1%2");
+        assert_eq!(host(addr("fe80::1", 0)), "fe80::1");
+        assert_eq!(host(addr("2001:db8::1", 2)), "2001:db8::1");
+        assert_eq!(host("10.1.2.3:51234".parse().unwrap()), "10.1.2.3");
+
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+        let event = build_all(&source(""), message, addr("fe80::1", 2)).remove(0);
+        assert_eq!(event.as_log()[log_schema().host_key()], "fe80::1%2".into());
+    }
+
+    #[test]
+    fn listener_label() {
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+        let peer_addr = "10.1.2.3:51234".parse().unwrap();
+
+        let first = source(r#"listener_label = "first""#);
+        let second = source(
+            r#"
+            listener_label = "second"
+            listener_label_key = "input"
+            "#,
+        );
+        let event = build_all(&first, message.clone(), peer_addr).remove(0);
+        assert_eq!(event.as_log()["listener"], "first".into());
+        let event = build_all(&second, message.clone(), peer_addr).remove(0);
+        assert_eq!(event.as_log()["input"], "second".into());
+        assert!(!event.as_log().contains("listener"));
+
+        let event = build_all(&source(""), message, peer_addr).remove(0);
+        assert!(!event.as_log().contains("listener"));
+    }
+
+    #[test]
+    fn max_record_fields() {
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                1441588984.into(),
+                rmpv::Value::Map(
+                    (0..5)
+                        .rev()
+                        .map(|i| (format!("field{}", i).into(), i.into()))
+                        .collect(),
+                ),
+            ]),
+        )
+        .unwrap();
+        let peer_addr = "10.1.2.3:51234".parse().unwrap();
+
+        // The first fields in the order sent are kept.
+        let truncate = source("max_record_fields = 2");
+        let event = build_all(&truncate, message.clone(), peer_addr).remove(0);
+        let log = event.as_log();
+        assert_eq!(log["field4"], Value::Integer(4));
+        assert_eq!(log["field3"], Value::Integer(3));
+        assert!(!log.contains("field2"));
+        assert!(!log.contains("field0"));
+        assert_eq!(log["tag"], "tag.name".into());
+
+        let drop = source(
+            r#"
+            max_record_fields = 4
+            on_oversized = "drop"
+            "#,
+        );
+        assert!(build_all(&drop, message.clone(), peer_addr).is_empty());
+
+        let wide_enough = source(
+            r#"
+            max_record_fields = 5
+            on_oversized = "drop"
+            "#,
+        );
+        let event = build_all(&wide_enough, message, peer_addr).remove(0);
+        assert_eq!(event.as_log()["field0"], Value::Integer(0));
+    }
+
+    #[test]
+    fn sample_rate() {
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                rmpv::Value::Array(
+                    (0..100)
+                        .map(|i| {
+                            rmpv::Value::Array(vec![
+                                1441588984.into(),
+                                rmpv::Value::Map(vec![
+                                    ("user".into(), format!("user{}", i % 10).into()),
+                                    ("index".into(), i.into()),
+                                ]),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ]),
+        )
+        .unwrap();
+        let peer_addr = "10.1.2.3:51234".parse().unwrap();
+
+        assert!(build_all(&source("sample_rate = 0.0"), message.clone(), peer_addr).is_empty());
+        assert_eq!(
+            build_all(&source("sample_rate = 1.0"), message.clone(), peer_addr).len(),
+            100
+        );
+
+        let by_user = source(
+            r#"
+            sample_rate = 0.5
+            sample_key = "user"
+            "#,
+        );
+        let kept = |events: Vec<Event>| {
+            events
+                .iter()
+                .map(|event| event.as_log()["index"].clone())
+                .collect::<Vec<_>>()
+        };
+        let first = kept(build_all(&by_user, message.clone(), peer_addr));
+        assert_eq!(first, kept(build_all(&by_user, message.clone(), peer_addr)));
+        // the events of a user are all kept or all dropped
+        assert_eq!(first.len() % 10, 0);
+        let users = build_all(&by_user, message, peer_addr)
+            .iter()
+            .map(|event| event.as_log()["user"].to_string_lossy())
+            .collect::<std::collections::BTreeSet<_>>();
+        assert_eq!(users.len() * 10, first.len());
+    }
+
+    #[test]
+    fn max_value_depth() {
+        // A field nested 200 arrays deep, next to one nested 2 deep.
+        let nested = (0..200).fold(rmpv::Value::from("bottom"), |value, _| {
+            rmpv::Value::Array(vec![value])
+        });
+        let message = rmp_serde::to_vec(&rmpv::Value::Array(vec![
+            "tag.name".into(),
+            1441588984.into(),
+            rmpv::Value::Map(vec![
+                ("nested".into(), nested),
+                (
+                    "shallow".into(),
+                    rmpv::Value::Array(vec![rmpv::Value::Array(vec![1.into()])]),
+                ),
+            ]),
+        ]))
+        .unwrap();
+        let peer_addr = "10.1.2.3:51234".parse().unwrap();
+
+        let truncate = source("max_value_depth = 2");
+        let event = build_all(&truncate, message.clone(), peer_addr).remove(0);
+        let log = event.as_log();
+        assert_eq!(
+            log["nested"],
+            Value::Array(vec![Value::Array(vec![TOO_DEEP_PLACEHOLDER.into()])])
+        );
+        assert_eq!(
+            log["shallow"],
+            Value::Array(vec![Value::Array(vec![Value::Integer(1)])])
+        );
+
+        let drop = source(
+            r#"
+            max_value_depth = 2
+            on_too_deep = "drop"
+            "#,
+        );
+        assert!(build_all(&drop, message.clone(), peer_addr).is_empty());
+
+        let deep_enough = source(
+            r#"
+            max_value_depth = 200
+            on_too_deep = "drop"
+            "#,
+        );
+        let event = build_all(&deep_enough, message, peer_addr).remove(0);
+        let mut value = &event.as_log()["nested"];
+        for _ in 0..200 {
+            value = match value {
+                Value::Array(values) => &values[0],
+                _ => panic!("expected an array, got {:?}", value),
+            };
+        }
+        assert_eq!(*value, "bottom".into());
+    }
+
+    #[test]
+    fn max_value_depth_limit() {
+        // {"nested": [[[...1...]]]} with 100k arrays, which would overflow the stack if
+        // deserialized
+        let mut record = vec![0x81];
+        record.extend(rmp_serde::to_vec("nested").unwrap());
+        record.extend(std::iter::repeat(0x91).take(100_000));
+        record.push(0x01);
+
+        // ["tag.name", 1441588984, record]
+        let mut message = vec![0x93];
+        message.extend(rmp_serde::to_vec("tag.name").unwrap());
+        message.extend(rmp_serde::to_vec(&1441588984).unwrap());
+        message.extend(&record);
+        let error = decode_all_with(&source("max_value_depth = 2"), message).unwrap_err();
+        assert!(matches!(error, DecodeError::TooDeep(MAX_VALUE_DEPTH)));
+
+        // ["tag.name", bin([1441588984, record])], where the entry is only scanned once it's
+        // read from the packed entries
+        let mut entry = vec![0x92];
+        entry.extend(rmp_serde::to_vec(&1441588984).unwrap());
+        entry.extend(&record);
+        let mut message = vec![0x92];
+        message.extend(rmp_serde::to_vec("tag.name").unwrap());
+        message.push(0xc6);
+        message.extend(&(entry.len() as u32).to_be_bytes());
+        message.extend(&entry);
+        let error = decode_all(message).unwrap_err();
+        assert!(matches!(error, DecodeError::TooDeep(MAX_VALUE_DEPTH)));
+    }
+
+    #[test]
+    fn message_scan_resumes() {
+        // ["tag.name", 1441588984, {"message": "bar"}]
+        let message = rmp_serde::to_vec(&(
+            "tag.name",
+            1441588984,
+            vec![("message", "bar")]
+                .into_iter()
+                .collect::<BTreeMap<_, _>>(),
+        ))
+        .unwrap();
+
+        // each byte is scanned once however little arrives at a time
+        let mut scan = MessageScan::new();
+        for buffered in 1..message.len() {
+            let scanned = scan.len;
+            assert_eq!(
+                scan.resume(&message[..buffered], MESSAGE_NESTING).unwrap(),
+                None
+            );
+            assert!(scanned <= scan.len && scan.len <= buffered);
+        }
+        assert_eq!(
+            scan.resume(&message, MESSAGE_NESTING).unwrap(),
+            Some(message.len())
+        );
+
+        // values nested too deep are found before the message is complete
+        let mut message = vec![0x93];
+        message.extend(rmp_serde::to_vec("tag.name").unwrap());
+        message.extend(rmp_serde::to_vec(&1441588984).unwrap());
+        message.push(0x81);
+        message.extend(rmp_serde::to_vec("nested").unwrap());
+        message.extend(std::iter::repeat(0x91).take(1_000));
+        let error = MessageScan::new()
+            .resume(&message, MESSAGE_NESTING)
+            .unwrap_err();
+        assert!(matches!(error, DecodeError::TooDeep(MAX_VALUE_DEPTH)));
+    }
+
+    #[test]
+    fn raw_message_key() {
+        let entry = |message: &str| {
+            rmpv::Value::Array(vec![
+                1441588984.into(),
+                rmpv::Value::Map(vec![("message".into(), message.into())]),
+            ])
+        };
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                1441588984.into(),
+                rmpv::Value::Map(vec![("message".into(), "foo".into())]),
+            ]),
+        )
+        .unwrap();
+        let mut forward = Vec::new();
+        rmpv::encode::write_value(
+            &mut forward,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                rmpv::Value::Array(vec![entry("bar"), entry("baz")]),
+            ]),
+        )
+        .unwrap();
+        let mut wire = message.clone();
+        wire.extend_from_slice(&forward);
+
+        let raw_message = |event: &Event| match &event.as_log()["raw_message"] {
+            Value::Bytes(bytes) => base64::decode(bytes).unwrap(),
+            value => panic!("expected bytes, got {:?}", value),
+        };
+        let peer_addr = "127.0.0.1:51234".parse().unwrap();
+        let with_key = source(r#"raw_message_key = "raw_message""#);
+        let events = build_all(&with_key, wire.clone(), peer_addr);
+        assert_eq!(events.len(), 3);
+        assert_eq!(raw_message(&events[0]), message);
+        assert_eq!(raw_message(&events[1]), forward);
+        assert_eq!(raw_message(&events[2]), forward);
+
+        let events = build_all(&source(""), wire, peer_addr);
+        assert!(!events[0].as_log().contains("raw_message"));
+    }
+
+    #[test]
+    fn received_timestamp_key() {
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+        let peer_addr = "127.0.0.1:51234".parse().unwrap();
+
+        let event = build_all(&source(""), message.clone(), peer_addr).remove(0);
+        assert!(!event.as_log().contains("received_at"));
+
+        let before = Utc::now();
+        let source = source(r#"received_timestamp_key = "received_at""#);
+        let event = build_all(&source, message, peer_addr).remove(0);
+        let log = event.as_log();
+        assert_eq!(
+            log[log_schema().timestamp_key()],
+            Utc.timestamp(1441588984, 0).into()
+        );
+        match &log["received_at"] {
+            Value::Timestamp(received_at) => {
+                assert!(*received_at >= before);
+                assert!(*received_at <= Utc::now());
+            }
+            value => panic!("expected a timestamp, got {:?}", value),
+        }
+    }
+
+    #[test]
+    fn timestamp_from_field() {
+        fn message(timestamp: Option<rmpv::Value>) -> Vec<u8> {
+            let mut record = vec![("message".into(), "bar".into())];
+            record.extend(timestamp.map(|timestamp| ("@timestamp".into(), timestamp)));
+            let mut message = Vec::new();
+            rmpv::encode::write_value(
+                &mut message,
+                &rmpv::Value::Array(vec![
+                    "tag.name".into(),
+                    1441588984.into(),
+                    rmpv::Value::Map(record),
+                ]),
+            )
+            .unwrap();
+            message
+        }
+        let source = source(r#"timestamp_from_field = "@timestamp""#);
+        let timestamp = |message: Vec<u8>| {
+            let log = decode_all_with(&source, message).unwrap().remove(0);
+            log[log_schema().timestamp_key()].clone()
+        };
+        let message_timestamp = Value::Timestamp(Utc.timestamp(1441588984, 0));
+
+        let rfc3339 = message(Some("2021-06-01T12:30:00.5Z".into()));
+        assert_eq!(
+            timestamp(rfc3339),
+            Value::Timestamp(Utc.timestamp(1622550600, 500_000_000))
+        );
+        let epoch = message(Some(1622550600.into()));
+        assert_eq!(
+            timestamp(epoch),
+            Value::Timestamp(Utc.timestamp(1622550600, 0))
+        );
+        let epoch_string = message(Some("1622550600.25".into()));
+        assert_eq!(
+            timestamp(epoch_string),
+            Value::Timestamp(Utc.timestamp(1622550600, 250_000_000))
+        );
+
+        // the message timestamp is kept otherwise
+        assert_eq!(timestamp(message(None)), message_timestamp);
+        let unparseable = message(Some("yesterday".into()));
+        let log = decode_all_with(&source, unparseable).unwrap().remove(0);
+        assert_eq!(log[log_schema().timestamp_key()], message_timestamp);
+        assert_eq!(log["@timestamp"], "yesterday".into());
+    }
+
+    #[test]
+    fn timestamp_key() {
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+        let timestamp: Value = Utc.timestamp(1441588984, 0).into();
+
+        let event = decode_all(message.clone()).unwrap().remove(0);
+        assert_eq!(event[log_schema().timestamp_key()], timestamp);
+
+        let source_with_key = source(r#"timestamp_key = "fluent_time""#);
+        let event = decode_all_with(&source_with_key, message.clone())
+            .unwrap()
+            .remove(0);
+        assert_eq!(event["fluent_time"], timestamp);
+        assert!(!event.contains(log_schema().timestamp_key()));
+
+        let source_without_key = source(r#"timestamp_key = """#);
+        let event = decode_all_with(&source_without_key, message)
+            .unwrap()
+            .remove(0);
+        assert!(!event.contains(log_schema().timestamp_key()));
+        assert_eq!(event.keys().count(), 2);
+    }
+
+    #[test]
+    fn ext_decoders() {
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {
+        //    "id": ext(2, [222, 173, 190, 239]),
+        //    "request": ext(3, [0, 1, ..., 15]),
+        //    "other": ext(5, [1, 2]),
+        //  },
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 131, 162, 105,
+            100, 214, 2, 222, 173, 190, 239, 167, 114, 101, 113, 117, 101, 115, 116, 216, 3, 0, 1,
+            2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 165, 111, 116, 104, 101, 114, 213, 5,
+            1, 2,
+        ];
+        let source = source(
+            r#"
+            [[ext_decoders]]
+            code = 2
+            decoding = "hex"
+
+            [[ext_decoders]]
+            code = 3
+            decoding = "uuid"
+            "#,
+        );
+
+        let log = decode_all_with(&source, message).unwrap().remove(0);
+        assert_eq!(log["id"], "deadbeef".into());
+        assert_eq!(
+            log["request"],
+            "00010203-0405-0607-0809-0a0b0c0d0e0f".into()
+        );
+        assert_eq!(
+            log["other"],
+            Value::from(btreemap! {
+                "msgpack_extension_code" => 5,
+                "bytes" => Value::Bytes(vec![1, 2].into()),
+            })
+        );
+    }
+
+    #[test]
+    fn non_utf8_keys() {
+        let message = rmpv::Value::Array(vec![
+            "tag.name".into(),
+            1441588984.into(),
+            rmpv::Value::Map(vec![
+                (vec![222u8, 173, 190, 239].into(), "binary".into()),
+                (b"valid".to_vec().into(), "utf8".into()),
+                (1.into(), "integer".into()),
+            ]),
+        ]);
+        let mut bytes = Vec::new();
+        rmpv::encode::write_value(&mut bytes, &message).unwrap();
+
+        let log = decode_all_with(&source(""), bytes.clone())
+            .unwrap()
+            .remove(0);
+        assert_eq!(log.get_flat("deadbeef"), Some(&"binary".into()));
+        assert_eq!(log.get_flat("valid"), Some(&"utf8".into()));
+        assert_eq!(log.get_flat("1"), Some(&"integer".into()));
+
+        let source = source(r#"non_utf8_key_encoding = "base64""#);
+        let log = decode_all_with(&source, bytes).unwrap().remove(0);
+        assert_eq!(log.get_flat("3q2+7w=="), Some(&"binary".into()));
+    }
+
+    #[test]
+    fn byte_size_key() {
+        fn encode(value: &rmpv::Value) -> Vec<u8> {
+            let mut bytes = Vec::new();
+            rmpv::encode::write_value(&mut bytes, value).unwrap();
+            bytes
+        }
+        fn byte_sizes(message: Vec<u8>) -> Vec<Value> {
+            let source = source(r#"byte_size_key = "byte_size""#);
+            build_all(&source, message, "127.0.0.1:51234".parse().unwrap())
+                .iter()
+                .map(|event| event.as_log()["byte_size"].clone())
+                .collect()
+        }
+        let entry = |message: &str| {
+            rmpv::Value::Array(vec![
+                1441588984.into(),
+                rmpv::Value::Map(vec![("message".into(), message.into())]),
+            ])
+        };
+        let entries = vec![entry("foo"), entry("a longer message")];
+        let entry_sizes = entries
+            .iter()
+            .map(|entry| Value::from(encode(entry).len() as i64))
+            .collect::<Vec<_>>();
+
+        let message = encode(&rmpv::Value::Array(vec![
+            "tag.name".into(),
+            1441588984.into(),
+            rmpv::Value::Map(vec![("message".into(), "foo".into())]),
+        ]));
+        let size = message.len() as i64;
+        assert_eq!(byte_sizes(message), vec![Value::from(size)]);
+
+        let forward = encode(&rmpv::Value::Array(vec![
+            "tag.name".into(),
+            rmpv::Value::Array(entries.clone()),
+        ]));
+        assert_eq!(byte_sizes(forward), entry_sizes);
+
+        let packed_forward = encode(&rmpv::Value::Array(vec![
+            "tag.name".into(),
+            rmpv::Value::Binary(entries.iter().flat_map(encode).collect()),
+        ]));
+        assert_eq!(byte_sizes(packed_forward), entry_sizes);
+
+        // without the key, the event doesn't get the field
+        let event = build_all(
+            &source(""),
+            encode(&rmpv::Value::Array(vec![
+                "tag.name".into(),
+                rmpv::Value::Array(entries),
+            ])),
+            "127.0.0.1:51234".parse().unwrap(),
+        )
+        .remove(0);
+        assert!(!event.as_log().contains("byte_size"));
+    }
+
+    #[test]
+    fn chunk_key() {
+        fn encode(value: &rmpv::Value) -> Vec<u8> {
+            let mut bytes = Vec::new();
+            rmpv::encode::write_value(&mut bytes, value).unwrap();
+            bytes
+        }
+        fn chunks(config: &str, message: Vec<u8>) -> Vec<Option<Value>> {
+            build_all(&source(config), message, "127.0.0.1:51234".parse().unwrap())
+                .iter()
+                .map(|event| event.as_log().get("chunk").cloned())
+                .collect()
+        }
+        let options = rmpv::Value::Map(vec![("chunk".into(), "p8n9gmxTQVC8/nh2wlKKeQ==".into())]);
+        let chunk = Some(Value::from("p8n9gmxTQVC8/nh2wlKKeQ=="));
+        let entry = |message: &str| {
+            rmpv::Value::Array(vec![
+                1441588984.into(),
+                rmpv::Value::Map(vec![("message".into(), message.into())]),
+            ])
+        };
+
+        let message = encode(&rmpv::Value::Array(vec![
+            "tag.name".into(),
+            1441588984.into(),
+            rmpv::Value::Map(vec![("message".into(), "foo".into())]),
+            options.clone(),
+        ]));
+        assert_eq!(
+            chunks(r#"chunk_key = "chunk""#, message.clone()),
+            vec![chunk.clone()]
+        );
+        assert_eq!(chunks("", message), vec![None]);
+
+        // all entries of a message share its chunk
+        let packed_forward = encode(&rmpv::Value::Array(vec![
+            "tag.name".into(),
+            rmpv::Value::Binary(
+                vec![entry("foo"), entry("bar")]
+                    .iter()
+                    .flat_map(encode)
+                    .collect(),
+            ),
+            options,
+        ]));
+        assert_eq!(
+            chunks(r#"chunk_key = "chunk""#, packed_forward),
+            vec![chunk.clone(), chunk]
+        );
+
+        let forward = encode(&rmpv::Value::Array(vec![
+            "tag.name".into(),
+            rmpv::Value::Array(vec![entry("foo")]),
+        ]));
+        assert_eq!(chunks(r#"chunk_key = "chunk""#, forward), vec![None]);
+    }
+
+    #[test]
+    fn tag_key() {
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+
+        let event = decode_all_with(&source(r#"tag_key = "fluent.tag""#), message)
+            .unwrap()
+            .remove(0);
+        assert_eq!(event["fluent.tag"], "tag.name".into());
+        assert!(!event.contains("tag"));
+    }
+
+    #[test]
+    fn tag_key_collision() {
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar", "tag": "record"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 130, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114, 163, 116, 97, 103, 166, 114, 101, 99,
+            111, 114, 100,
+        ];
+
+        // the record field takes precedence over the fluent tag
+        let event = decode_all(message).unwrap().remove(0);
+        assert_eq!(event["tag"], "record".into());
+        assert_eq!(event["message"], "bar".into());
+    }
+
+    #[test]
+    fn on_key_conflict() {
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                1441588984.into(),
+                rmpv::Value::Map(vec![
+                    ("message".into(), "bar".into()),
+                    ("host".into(), "record.example.com".into()),
+                ]),
+            ]),
+        )
+        .unwrap();
+        let peer_addr = "10.1.2.3:51234".parse().unwrap();
+        let event = |config: &str| build_all(&source(config), message.clone(), peer_addr).remove(0);
+
+        let event_record_wins = event("");
+        let log = event_record_wins.as_log();
+        assert_eq!(log["host"], "record.example.com".into());
+        assert!(!log.contains("fluent_host"));
+
+        let event_reserved_wins = event(r#"on_key_conflict = "reserved_wins""#);
+        let log = event_reserved_wins.as_log();
+        assert_eq!(log["host"], "10.1.2.3".into());
+        assert!(!log.contains("fluent_host"));
+
+        let event_prefix_record = event(r#"on_key_conflict = "prefix_record""#);
+        let log = event_prefix_record.as_log();
+        assert_eq!(log["host"], "10.1.2.3".into());
+        assert_eq!(log["fluent_host"], "record.example.com".into());
+        assert_eq!(log["message"], "bar".into());
+    }
+
+    #[test]
+    fn stringify_values() {
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                1441588984.into(),
+                rmpv::Value::Map(vec![
+                    ("message".into(), "bar".into()),
+                    ("count".into(), 42.into()),
+                    ("ratio".into(), 1.5.into()),
+                    ("ok".into(), true.into()),
+                    (
+                        "values".into(),
+                        rmpv::Value::Array(vec!["a".into(), 1.into()]),
+                    ),
+                    (
+                        "nested".into(),
+                        rmpv::Value::Map(vec![("a".into(), 1.into())]),
+                    ),
+                    ("none".into(), rmpv::Value::Nil),
+                ]),
+            ]),
+        )
+        .unwrap();
+        let peer_addr = "10.1.2.3:51234".parse().unwrap();
+        let event = |config: &str| build_all(&source(config), message.clone(), peer_addr).remove(0);
+
+        let typed = event("");
+        assert_eq!(typed.as_log()["count"], Value::Integer(42));
+
+        let stringified = event("stringify_values = true");
+        let log = stringified.as_log();
+        assert_eq!(log["message"], "bar".into());
+        assert_eq!(log["count"], "42".into());
+        assert_eq!(log["ratio"], "1.5".into());
+        assert_eq!(log["ok"], "true".into());
+        assert_eq!(log["values"], r#"["a",1]"#.into());
+        assert_eq!(log["nested"], r#"{"a":1}"#.into());
+        assert_eq!(log["none"], Value::Null);
+        // the fields the source adds itself keep their types
+        assert!(matches!(log["timestamp"], Value::Timestamp(_)));
+        assert_eq!(log["host"], "10.1.2.3".into());
+        assert_eq!(log["tag"], "tag.name".into());
+    }
+
+    #[test]
+    fn max_frame_bytes() {
+        let source = source("max_frame_bytes = 64");
+
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar..." (str32 header claiming 4294967280 bytes)
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 219, 255, 255, 255, 240, 98, 97, 114,
+        ];
+        // not enough data to exceed the limit yet
+        assert!(decode_all_with(&source, message.clone())
+            .unwrap()
+            .is_empty());
+
+        let mut message = message;
+        message.resize(128, b'a');
+        let error = decode_all_with(&source, message).unwrap_err();
+        assert!(matches!(error, DecodeError::FrameTooLarge(64)));
+        assert!(error.is_error_fatal());
+
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+        assert_eq!(decode_all_with(&source, message).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn decode_large_compressed_packed_forward() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        fn message(entries: &[u8]) -> Vec<u8> {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(entries).unwrap();
+            let mut message = Vec::new();
+            rmpv::encode::write_value(
+                &mut message,
+                &rmpv::Value::Array(vec![
+                    "tag.name".into(),
+                    rmpv::Value::Binary(encoder.finish().unwrap()),
+                    rmpv::Value::Map(vec![("compressed".into(), "gzip".into())]),
+                ]),
+            )
+            .unwrap();
+            message
+        }
+
+        // About 2MiB once decompressed, which is decoded without being buffered as a whole.
+        let mut entries = Vec::new();
+        for i in 0..20_000 {
+            rmpv::encode::write_value(
+                &mut entries,
+                &rmpv::Value::Array(vec![
+                    1441588984.into(),
+                    rmpv::Value::Map(vec![
+                        ("message".into(), format!("{:0>80}", i).into()),
+                        ("index".into(), i.into()),
+                    ]),
+                ]),
+            )
+            .unwrap();
+        }
+
+        let events = decode_all_with(
+            &source("max_decompressed_bytes = 4194304"),
+            message(&entries),
+        )
+        .unwrap();
+        assert_eq!(events.len(), 20_000);
+        assert_eq!(events[19_999]["index"], Value::Integer(19_999));
+
+        // Data beyond the limit is still detected after the entries that fit.
+        entries.extend_from_slice(&[0; 4 * 1024 * 1024]);
+        let error = decode_all_with(
+            &source("max_decompressed_bytes = 4194304"),
+            message(&entries),
+        )
+        .unwrap_err();
+        assert!(matches!(error, DecodeError::DecompressedTooLarge(4194304)));
+    }
+
+    #[test]
+    fn lenient_message_order() {
+        fn message(elements: Vec<rmpv::Value>) -> Vec<u8> {
+            let mut message = Vec::new();
+            rmpv::encode::write_value(&mut message, &rmpv::Value::Array(elements)).unwrap();
+            message
+        }
+        let record = |message: &str| rmpv::Value::Map(vec![("message".into(), message.into())]);
+        let options = |size: i64| rmpv::Value::Map(vec![("size".into(), size.into())]);
+
+        // the options before the timestamp and record
+        let reordered = message(vec![
+            "tag.name".into(),
+            options(1),
+            1441588984.into(),
+            record("bar"),
+        ]);
+        // the options before the entries
+        let reordered_forward = message(vec![
+            "tag.name".into(),
+            options(2),
+            rmpv::Value::Array(vec![
+                rmpv::Value::Array(vec![1441588984.into(), record("foo")]),
+                rmpv::Value::Array(vec![1441588985.into(), record("baz")]),
+            ]),
+        ]);
+
+        // ignored like a heartbeat otherwise
+        assert!(decode_all(reordered.clone()).unwrap().is_empty());
+        assert!(decode_all(reordered_forward.clone()).unwrap().is_empty());
+
+        let lenient = source("lenient_message_order = true");
+        let got = decode_all_with(&lenient, reordered).unwrap();
+        assert_eq!(got.len(), 1);
+        assert_event_data_eq!(
+            got[0],
+            LogEvent::from(btreemap! {
+                "message" => "bar",
+                "tag" => "tag.name",
+                "timestamp" => Value::Timestamp(Utc.timestamp(1441588984, 0)),
+            })
+        );
+        let got = decode_all_with(&lenient, reordered_forward).unwrap();
+        assert_eq!(
+            got.iter()
+                .map(|log| log["message"].clone())
+                .collect::<Vec<_>>(),
+            vec![Value::from("foo"), Value::from("baz")]
+        );
+
+        // still ignored if putting the elements in order doesn't make a message either
+        let unrecognized = message(vec![record("bar"), "tag.name".into()]);
+        assert!(decode_all_with(&lenient, unrecognized).unwrap().is_empty());
+    }
+
+    #[test]
+    fn lenient_array_records() {
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                1441588984.into(),
+                rmpv::Value::Array(vec!["foo".into(), 1.into()]),
+            ]),
+        )
+        .unwrap();
+        let record = Value::Array(vec![Value::from("foo"), Value::from(1)]);
+
+        // ignored like a heartbeat otherwise
+        assert!(decode_all(message.clone()).unwrap().is_empty());
+
+        let got =
+            decode_all_with(&source("lenient_array_records = true"), message.clone()).unwrap();
+        assert_eq!(got.len(), 1);
+        assert_event_data_eq!(
+            got[0],
+            LogEvent::from(btreemap! {
+                "message" => record.clone(),
+                "tag" => "tag.name",
+                "timestamp" => Value::Timestamp(Utc.timestamp(1441588984, 0)),
+            })
+        );
+
+        let got = decode_all_with(
+            &source("lenient_array_records = true\narray_record_key = \"payload\""),
+            message,
+        )
+        .unwrap();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0]["payload"], record);
+        assert!(got[0].get("message").is_none());
+    }
+
+    #[test]
+    fn lenient_decompression() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        fn member(index: i64) -> Vec<u8> {
+            let mut entry = Vec::new();
+            rmpv::encode::write_value(
+                &mut entry,
+                &rmpv::Value::Array(vec![
+                    1441588984.into(),
+                    rmpv::Value::Map(vec![("index".into(), index.into())]),
+                ]),
+            )
+            .unwrap();
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&entry).unwrap();
+            encoder.finish().unwrap()
+        }
+
+        fn message(payload: Vec<u8>) -> Vec<u8> {
+            let mut message = Vec::new();
+            rmpv::encode::write_value(
+                &mut message,
+                &rmpv::Value::Array(vec![
+                    "tag.name".into(),
+                    rmpv::Value::Binary(payload),
+                    rmpv::Value::Map(vec![("compressed".into(), "gzip".into())]),
+                ]),
+            )
+            .unwrap();
+            message
+        }
+
+        let indexes = |events: Vec<LogEvent>| {
+            events
+                .iter()
+                .map(|event| event["index"].clone())
+                .collect::<Vec<_>>()
+        };
+        let lenient = source("lenient_decompression = true");
+
+        // two members followed by junk
+        let mut payload = member(0);
+        payload.extend(member(1));
+        payload.extend_from_slice(b"junk\0\0\0\0");
+
+        let error = decode_all(message(payload.clone())).unwrap_err();
+        assert!(matches!(error, DecodeError::Decode(_) | DecodeError::IO(_)));
+        let events = decode_all_with(&lenient, message(payload)).unwrap();
+        assert_eq!(indexes(events), vec![Value::Integer(0), Value::Integer(1)]);
+
+        // corrupt data within a member still fails the message
+        let mut payload = member(0);
+        let len = payload.len();
+        payload[len - 4] ^= 0xff;
+        assert!(decode_all_with(&lenient, message(payload)).is_err());
+    }
+
+    #[test]
+    fn stream_forward_entries() {
+        const ENTRIES: usize = 50_000;
+        let entry = |i: usize| {
+            rmpv::Value::Array(vec![
+                1441588984.into(),
+                rmpv::Value::Map(vec![("index".into(), i.into())]),
+            ])
+        };
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                rmpv::Value::Array((0..ENTRIES).map(entry).collect()),
+                rmpv::Value::Map(vec![
+                    ("size".into(), ENTRIES.into()),
+                    ("chunk".into(), "p8n9gmxTQVC8/nh2wlKKeQ==".into()),
+                ]),
+            ]),
+        )
+        .unwrap();
+
+        let source = source(
+            r#"
+            max_pending_frames = 100
+            byte_size_key = "byte_size"
+            "#,
+        );
+        let mut decoder = source.decoder(test_peer_addr());
+
+        // nothing is decoded until the whole message is there
+        let mut buf = BytesMut::from(&message[..message.len() - 1]);
+        assert!(decoder.decode(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(&message[message.len() - 1..]);
+
+        let mut indexes = Vec::new();
+        while let Some(frame) = decoder.decode(&mut buf).unwrap() {
+            // the entries are only decoded as they're needed
+            assert!(decoder.unread_frames.len() <= 100);
+            if indexes.len() < ENTRIES - 100 {
+                assert!(matches!(
+                    decoder
+                        .pending_entries
+                        .as_ref()
+                        .map(|pending| &pending.entries),
+                    Some(PendingEntrySource::Encoded { .. })
+                ));
+            }
+            let mut encoded = Vec::new();
+            rmpv::encode::write_value(&mut encoded, &entry(indexes.len())).unwrap();
+            assert_eq!(frame.byte_size, encoded.len());
+            assert_eq!(frame.chunk.as_deref(), Some("p8n9gmxTQVC8/nh2wlKKeQ=="));
+            indexes.push(source.log_from_frame(frame)["index"].clone());
+        }
+
+        assert!(buf.is_empty());
+        assert!(decoder.pending_entries.is_none());
+        assert_eq!(
+            indexes,
+            (0..ENTRIES as i64).map(Value::Integer).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn default_pending_frames() {
+        let entry = rmpv::Value::Array(vec![
+            1441588984.into(),
+            rmpv::Value::Map(vec![("message".into(), "bar".into())]),
+        ]);
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                rmpv::Value::Array(vec![entry; 10_000]),
+            ]),
+        )
+        .unwrap();
+
+        // even without `max_pending_frames`, each call only decodes a limited number of entries
+        let mut decoder = source("").decoder(test_peer_addr());
+        let mut buf = BytesMut::from(&message[..]);
+        assert!(decoder.decode(&mut buf).unwrap().is_some());
+        assert!(decoder.pending_entries.is_some());
+        let mut count = 1;
+        while decoder.decode(&mut buf).unwrap().is_some() {
+            assert!(decoder.unread_frames.len() <= DEFAULT_MAX_PENDING_FRAMES);
+            count += 1;
+        }
+        assert_eq!(count, 10_000);
+        assert!(decoder.pending_entries.is_none());
+    }
+
+    #[test]
+    fn max_pending_frames() {
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                rmpv::Value::Array(
+                    (0..1000)
+                        .map(|i| {
+                            rmpv::Value::Array(vec![
+                                1441588984.into(),
+                                rmpv::Value::Map(vec![("index".into(), i.into())]),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ]),
+        )
+        .unwrap();
+
+        let source = source("max_pending_frames = 10");
+        let mut decoder = source.decoder(test_peer_addr());
+        let mut buf = BytesMut::from(&message[..]);
+        let mut indexes = Vec::new();
+        while let Some(frame) = decoder.decode(&mut buf).unwrap() {
+            assert!(decoder.unread_frames.len() <= 10);
+            indexes.push(source.log_from_frame(frame)["index"].clone());
+        }
+
+        assert!(buf.is_empty());
+        assert!(decoder.pending_entries.is_none());
+        assert_eq!(indexes, (0..1000).map(Value::Integer).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn pending_frames_hold_back_messages() {
+        let entries = (0..1000)
+            .map(|i| {
+                rmpv::Value::Array(vec![
+                    1441588984.into(),
+                    rmpv::Value::Map(vec![("index".into(), i.into())]),
+                ])
+            })
+            .collect();
+        let mut forward = Vec::new();
+        rmpv::encode::write_value(
+            &mut forward,
+            &rmpv::Value::Array(vec!["tag.name".into(), rmpv::Value::Array(entries)]),
+        )
+        .unwrap();
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                1441588985.into(),
+                rmpv::Value::Map(vec![("index".into(), 1000.into())]),
+            ]),
+        )
+        .unwrap();
+
+        let source = source("max_pending_frames = 10");
+        let mut decoder = source.decoder(test_peer_addr());
+        let mut buf = BytesMut::from(&forward[..]);
+        buf.extend_from_slice(&message);
+        let mut indexes = Vec::new();
+        while let Some(frame) = decoder.decode(&mut buf).unwrap() {
+            indexes.push(source.log_from_frame(frame)["index"].clone());
+            assert!(decoder.unread_frames.len() <= 10);
+            // the next message is left alone until all entries of the forward one are out
+            if indexes.len() < 1000 {
+                assert_eq!(buf.len(), message.len());
+            }
+        }
+
+        assert!(buf.is_empty());
+        assert_eq!(indexes, (0..=1000).map(Value::Integer).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn slow_downstream_backpressure() {
+        // the events are never taken from the pipeline
+        let (tx, _rx) = Pipeline::new_with_buffer(1, vec![]);
+        let address = next_addr();
+        let config: FluentConfig = toml::from_str(&format!(
+            r#"
+            address = "{}"
+            max_pending_frames = 10
+            receive_buffer_bytes = 4096
+            "#,
+            address
+        ))
+        .unwrap();
+        tokio::spawn(config.build(SourceContext::new_test(tx)).await.unwrap());
+        wait_for_tcp(address).await;
+
+        let entry = rmpv::Value::Array(vec![
+            1441588984.into(),
+            rmpv::Value::Map(vec![("message".into(), "bar".into())]),
+        ]);
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                rmpv::Value::Array(vec![entry; 100]),
+            ]),
+        )
+        .unwrap();
+        // far more than the socket buffers on both ends hold
+        let data = message.repeat(64 * 1024 * 1024 / message.len());
+
+        // were the messages decoded regardless of the pipeline, all of them would be read
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        assert!(timeout(Duration::from_secs(2), stream.write_all(&data))
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn max_decompressed_bytes() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        // 1MiB of zeros compresses down to about 1KiB
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&[0; 1024 * 1024]).unwrap();
+        let bomb = encoder.finish().unwrap();
+
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                rmpv::Value::Binary(bomb),
+                rmpv::Value::Map(vec![("compressed".into(), "gzip".into())]),
+            ]),
+        )
+        .unwrap();
+
+        let error =
+            decode_all_with(&source("max_decompressed_bytes = 65536"), message).unwrap_err();
+        assert!(matches!(error, DecodeError::DecompressedTooLarge(65536)));
+        assert!(error.is_error_fatal());
+    }
+
+    #[test]
+    fn max_entry_bytes() {
+        let packed = |messages: &[String]| {
+            let mut entries = Vec::new();
+            for message in messages {
+                rmpv::encode::write_value(
+                    &mut entries,
+                    &rmpv::Value::Array(vec![
+                        1441588984.into(),
+                        rmpv::Value::Map(vec![("message".into(), message.as_str().into())]),
+                    ]),
+                )
+                .unwrap();
+            }
+            let mut message = Vec::new();
+            rmpv::encode::write_value(
+                &mut message,
+                &rmpv::Value::Array(vec!["tag.name".into(), rmpv::Value::Binary(entries)]),
+            )
+            .unwrap();
+            message
+        };
+        let source = source("max_entry_bytes = 1024");
+
+        let small = packed(&["foo".to_owned(), "a".repeat(1000)]);
+        assert_eq!(decode_all_with(&source, small).unwrap().len(), 2);
+
+        // a single oversized entry fails the message however small the others are
+        let oversized = packed(&["foo".to_owned(), "a".repeat(64 * 1024), "bar".to_owned()]);
+        let error = decode_all_with(&source, oversized.clone()).unwrap_err();
+        assert!(matches!(error, DecodeError::EntryTooLarge(1024)));
+        assert!(error.is_error_fatal());
+        assert_eq!(decode_all(oversized).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn unexpected_heartbeat() {
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+        // nil and 1 heartbeats, the latter with an unexpected value, around the messages
+        let mut buf = vec![0xc0, 1];
+        buf.extend_from_slice(&message);
+        buf.push(1);
+        buf.extend_from_slice(&message);
+
+        let events = decode_all(buf).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1]["message"], "bar".into());
+    }
+
+    #[test]
+    fn decode_error_metric() {
+        let unknown_compression_errors = || {
+            tagged_counter(
+                "fluent_decode_errors_total",
+                "error_type",
+                "unknown_compression",
+            )
+        };
+
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                rmpv::Value::Binary(vec![]),
+                rmpv::Value::Map(vec![("compressed".into(), "lz4".into())]),
+            ]),
+        )
+        .unwrap();
+
+        let before = unknown_compression_errors();
+        let error = decode_all(message).unwrap_err();
+        assert!(matches!(error, DecodeError::UnknownCompression(_)));
+        assert_eq!(unknown_compression_errors() - before, 1.0);
+    }
+
+    #[test]
+    fn fallback_uncompressed() {
+        let message = |payload: Vec<u8>| {
+            let mut message = Vec::new();
+            rmpv::encode::write_value(
+                &mut message,
+                &rmpv::Value::Array(vec![
+                    "tag.name".into(),
+                    rmpv::Value::Binary(payload),
+                    rmpv::Value::Map(vec![("compressed".into(), "zstd-next".into())]),
+                ]),
+            )
+            .unwrap();
+            message
+        };
+        let mut payload = Vec::new();
+        for index in 0..2i64 {
+            rmpv::encode::write_value(
+                &mut payload,
+                &rmpv::Value::Array(vec![
+                    1441588984.into(),
+                    rmpv::Value::Map(vec![("index".into(), index.into())]),
+                ]),
+            )
+            .unwrap();
+        }
+
+        let error = decode_all(message(payload.clone())).unwrap_err();
+        assert!(matches!(error, DecodeError::UnknownCompression(_)));
+
+        let fallback = source("fallback_uncompressed = true");
+        let events = decode_all_with(&fallback, message(payload)).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["index"], Value::Integer(0));
+        assert_eq!(events[1]["index"], Value::Integer(1));
+        assert_eq!(events[1]["tag"], "tag.name".into());
+
+        // a payload that really is compressed still fails the message
+        assert!(decode_all_with(&fallback, message(vec![0x28, 0xb5, 0x2f, 0xfd, 0xc1])).is_err());
+    }
+
+    #[test]
+    fn compression_metric() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let messages =
+            |compression: &str| tagged_counter("fluent_messages_total", "compression", compression);
+
+        let mut entries = Vec::new();
+        rmpv::encode::write_value(
+            &mut entries,
+            &rmpv::Value::Array(vec![
+                1441588984.into(),
+                rmpv::Value::Map(vec![("message".into(), "foo".into())]),
+            ]),
+        )
+        .unwrap();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&entries).unwrap();
+        let mut packed = Vec::new();
+        rmpv::encode::write_value(
+            &mut packed,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                rmpv::Value::Binary(encoder.finish().unwrap()),
+                rmpv::Value::Map(vec![("compressed".into(), "gzip".into())]),
+            ]),
+        )
+        .unwrap();
+
+        let (gzip, none) = (messages("gzip"), messages("none"));
+        assert_eq!(decode_all(packed).unwrap().len(), 1);
+        assert_eq!(messages("gzip") - gzip, 1.0);
+
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+        assert_eq!(decode_all(message).unwrap().len(), 1);
+        assert!(messages("none") - none >= 1.0);
+    }
+
+    #[test]
+    fn tag_metric() {
+        let received = |tag: &str| tagged_counter("fluent_events_received_total", "tag", tag);
+        let message = |tag: &str| {
+            let mut message = Vec::new();
+            rmpv::encode::write_value(
+                &mut message,
+                &rmpv::Value::Array(vec![
+                    tag.into(),
+                    1441588984.into(),
+                    rmpv::Value::Map(vec![("message".into(), "bar".into())]),
+                ]),
+            )
+            .unwrap();
+            message
+        };
+        let tags = [
+            "tag_metric.a",
+            "tag_metric.b",
+            "tag_metric.c",
+            "tag_metric.d",
+        ];
+
+        let before = tags.iter().map(|tag| received(*tag)).collect::<Vec<_>>();
+        let other = received("other");
+        let source = source("max_tag_metric_labels = 2");
+        for tag in tags.iter().chain(&["tag_metric.a", "tag_metric.c"]) {
+            assert_eq!(build_all(&source, message(*tag), test_peer_addr()).len(), 1);
+        }
+
+        // the first two tags get their own label, the others are counted under `other`
+        let after = tags.iter().map(|tag| received(*tag)).collect::<Vec<_>>();
+        assert_eq!(after[0] - before[0], 2.0);
+        assert_eq!(after[1] - before[1], 1.0);
+        assert_eq!(after[2] - before[2], 0.0);
+        assert_eq!(after[3] - before[3], 0.0);
+        assert_eq!(received("other") - other, 3.0);
+    }
+
+    #[test]
+    fn forward_entries_metric() {
+        let _ = crate::metrics::init();
+        let controller = crate::metrics::get_controller().unwrap();
+        let samples = || {
+            crate::metrics::capture_metrics(controller)
+                .map(Event::into_metric)
+                .filter(|metric| metric.name() == "fluent_forward_entries")
+                .map(|metric| match metric.value() {
+                    MetricValue::AggregatedHistogram { count, sum, .. } => (*count, *sum),
+                    value => panic!("expected a histogram, got {:?}", value),
+                })
+                .fold((0, 0.0), |(count, sum), sample| {
+                    (count + sample.0, sum + sample.1)
+                })
+        };
+        let entry = |message: &str| {
+            rmpv::Value::Array(vec![
+                1441588984.into(),
+                rmpv::Value::Map(vec![("message".into(), message.into())]),
+            ])
+        };
+        let entries = |count: usize| (0..count).map(|_| entry("bar")).collect::<Vec<_>>();
+
+        let mut forward = Vec::new();
+        rmpv::encode::write_value(
+            &mut forward,
+            &rmpv::Value::Array(vec!["tag.name".into(), rmpv::Value::Array(entries(7))]),
+        )
+        .unwrap();
+        let mut packed_entries = Vec::new();
+        for entry in entries(5) {
+            rmpv::encode::write_value(&mut packed_entries, &entry).unwrap();
+        }
+        let mut packed = Vec::new();
+        rmpv::encode::write_value(
+            &mut packed,
+            &rmpv::Value::Array(vec!["tag.name".into(), packed_entries.into()]),
+        )
+        .unwrap();
+
+        // other tests record samples concurrently, so only a lower bound holds
+        let (count, sum) = samples();
+        assert_eq!(decode_all(forward).unwrap().len(), 7);
+        assert_eq!(decode_all(packed).unwrap().len(), 5);
+        let (count_after, sum_after) = samples();
+        assert!(count_after - count >= 2);
+        assert!(sum_after - sum >= 12.0);
+    }
+
+    #[test]
+    fn unread_frames() {
+        let _ = crate::metrics::init();
+        let controller = crate::metrics::get_controller().unwrap();
+        let unread_frames = || {
+            crate::metrics::capture_metrics(controller)
+                .map(Event::into_metric)
+                .filter(|metric| metric.name() == "fluent_unread_frames")
+                .map(|metric| match metric.value() {
+                    MetricValue::Gauge { value } => *value,
+                    value => panic!("expected a gauge, got {:?}", value),
+                })
+                .sum::<f64>()
+        };
+        let entry = rmpv::Value::Array(vec![
+            1441588984.into(),
+            rmpv::Value::Map(vec![("message".into(), "bar".into())]),
+        ]);
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec!["tag.name".into(), rmpv::Value::Array(vec![entry; 5])]),
+        )
+        .unwrap();
+
+        let mut buf = BytesMut::from(&message[..]);
+        let mut decoder = source("").decoder(test_peer_addr());
+        assert!(decoder.decode(&mut buf).unwrap().is_some());
+        assert_eq!(decoder.reported_unread_frames, 4);
+        // other decoders only ever add to the gauge, so it holds at least ours
+        assert!(unread_frames() >= 4.0);
+
+        while decoder.decode(&mut buf).unwrap().is_some() {}
+        assert_eq!(decoder.reported_unread_frames, 0);
+    }
+
+    #[test]
+    fn entry_count() {
+        let mismatches = || counter("fluent_entry_count_mismatches_total");
+        // Forward mode with two entries and the given size option.
+        let message = |size: u64| {
+            let entry = |message: &str| {
+                rmpv::Value::Array(vec![
+                    1441588984.into(),
+                    rmpv::Value::Map(vec![("message".into(), message.into())]),
+                ])
+            };
+            let mut message = Vec::new();
+            rmpv::encode::write_value(
+                &mut message,
+                &rmpv::Value::Array(vec![
+                    "tag.name".into(),
+                    rmpv::Value::Array(vec![entry("foo"), entry("bar")]),
+                    rmpv::Value::Map(vec![("size".into(), size.into())]),
+                ]),
+            )
+            .unwrap();
+            message
+        };
+        let strict = source("strict_entry_count = true");
+
+        let before = mismatches();
+        assert_eq!(decode_all(message(2)).unwrap().len(), 2);
+        assert_eq!(decode_all_with(&strict, message(2)).unwrap().len(), 2);
+        assert_eq!(mismatches() - before, 0.0);
+
+        // more entries announced than sent
+        let before = mismatches();
+        assert_eq!(decode_all(message(3)).unwrap().len(), 2);
+        assert!(matches!(
+            decode_all_with(&strict, message(3)),
+            Err(DecodeError::EntryCountMismatch {
+                expected: 3,
+                actual: 2
+            })
+        ));
+        assert_eq!(mismatches() - before, 2.0);
+
+        // fewer entries announced than sent
+        let before = mismatches();
+        assert_eq!(decode_all(message(1)).unwrap().len(), 2);
+        assert!(matches!(
+            decode_all_with(&strict, message(1)),
+            Err(DecodeError::EntryCountMismatch {
+                expected: 1,
+                actual: 2
+            })
+        ));
+        assert_eq!(mismatches() - before, 2.0);
+    }
+
+    #[test]
+    fn raw_timestamp_key() {
+        let source = source(r#"raw_timestamp_key = "raw_timestamp""#);
+
+        //[
+        //  "tag.name",
+        //  EventTime(1609459200, 123456789),
+        //  {"message": "bar"},
+        //]
+        let ext = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 215, 0, 95, 238, 102, 0, 7, 91, 205, 21,
+            129, 167, 109, 101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+        let log = decode_all_with(&source, ext).unwrap().remove(0);
+        assert_eq!(
+            log["timestamp"],
+            Value::Timestamp(Utc.timestamp(1609459200, 123_456_789))
+        );
+        assert_eq!(
+            log["raw_timestamp"],
+            Value::from(btreemap! {
+                "type" => 0,
+                "data" => base64::encode(&[95u8, 238, 102, 0, 7, 91, 205, 21]),
+            })
+        );
+
+        let mut float = Vec::new();
+        rmpv::encode::write_value(
+            &mut float,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                1609459200.5.into(),
+                rmpv::Value::Map(vec![("message".into(), "bar".into())]),
+            ]),
+        )
+        .unwrap();
+        let log = decode_all_with(&source, float.clone()).unwrap().remove(0);
+        assert_eq!(
+            log["timestamp"],
+            Value::Timestamp(Utc.timestamp(1609459200, 500_000_000))
+        );
+        assert_eq!(log["raw_timestamp"], Value::Float(1609459200.5));
+
+        assert!(!decode_all(float).unwrap()[0].contains("raw_timestamp"));
+    }
+
+    #[test]
+    fn large_int_as_float() {
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                1609459200.into(),
+                rmpv::Value::Map(vec![
+                    ("large".into(), u64::MAX.into()),
+                    ("small".into(), 42.into()),
+                    (
+                        "nested".into(),
+                        rmpv::Value::Array(vec![(i64::MAX as u64 + 1).into()]),
+                    ),
+                ]),
+            ]),
+        )
+        .unwrap();
+
+        let log = decode_all(message.clone()).unwrap().remove(0);
+        assert_eq!(log["large"], Value::from("18446744073709551615"));
+        assert_eq!(log["small"], Value::Integer(42));
+        assert_eq!(log["nested[0]"], Value::from("9223372036854775808"));
+
+        let source = source("large_int_as_float = true");
+        let log = decode_all_with(&source, message).unwrap().remove(0);
+        assert_eq!(log["large"], Value::Float(u64::MAX as f64));
+        assert_eq!(log["small"], Value::Integer(42));
+        assert_eq!(log["nested[0]"], Value::Float(i64::MAX as f64 + 1.0));
+    }
+
+    #[test]
+    fn subsecond_timestamps() {
+        fn timestamp(message: Vec<u8>) -> DateTime<Utc> {
+            match decode_all(message).unwrap().remove(0)["timestamp"] {
+                Value::Timestamp(timestamp) => timestamp,
+                ref value => panic!("expected a timestamp, got {:?}", value),
+            }
+        }
+
+        //[
+        //  "tag.name",
+        //  EventTime(1609459200, 123456789),
+        //  {"message": "bar"},
+        //]
+        let ext = timestamp(vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 215, 0, 95, 238, 102, 0, 7, 91, 205, 21,
+            129, 167, 109, 101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ]);
+        assert_eq!(ext.timestamp(), 1609459200);
+        assert_eq!(ext.timestamp_subsec_nanos(), 123_456_789);
+
+        //[
+        //  "tag.name",
+        //  1609459200.123456,
+        //  {"message": "bar"},
+        //]
+        let float = timestamp(vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 203, 65, 215, 251, 153, 128, 7, 230,
+            180, 129, 167, 109, 101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ]);
+        assert_eq!(float.timestamp(), 1609459200);
+        assert_eq!(float.timestamp_subsec_nanos(), 123_456_000);
+    }
+
+    #[test]
+    fn unix_timestamps() {
+        fn timestamp(timestamp: rmpv::Value) -> DateTime<Utc> {
+            let mut message = Vec::new();
+            rmpv::encode::write_value(
+                &mut message,
+                &rmpv::Value::Array(vec![
+                    "tag.name".into(),
+                    timestamp,
+                    rmpv::Value::Map(vec![("message".into(), "bar".into())]),
+                ]),
+            )
+            .unwrap();
+            match decode_all(message).unwrap().remove(0)["timestamp"] {
+                Value::Timestamp(timestamp) => timestamp,
+                ref value => panic!("expected a timestamp, got {:?}", value),
+            }
+        }
+
+        assert_eq!(timestamp(1609459200.into()), Utc.timestamp(1609459200, 0));
+        assert_eq!(
+            timestamp(1609459200.5.into()),
+            Utc.timestamp(1609459200, 500_000_000)
+        );
+    }
+
+    #[test]
+    fn msgpack_timestamps() {
+        fn timestamp(data: Vec<u8>) -> Result<DateTime<Utc>, DecodeError> {
+            let mut message = Vec::new();
+            rmpv::encode::write_value(
+                &mut message,
+                &rmpv::Value::Array(vec![
+                    "tag.name".into(),
+                    rmpv::Value::Ext(-1, data),
+                    rmpv::Value::Map(vec![("message".into(), "bar".into())]),
+                ]),
+            )
+            .unwrap();
+            decode_all(message).map(|mut events| match events.remove(0)["timestamp"] {
+                Value::Timestamp(timestamp) => timestamp,
+                ref value => panic!("expected a timestamp, got {:?}", value),
+            })
+        }
+
+        // timestamp 32
+        let timestamp32 = timestamp(1609459200u32.to_be_bytes().to_vec()).unwrap();
+        assert_eq!(timestamp32, Utc.timestamp(1609459200, 0));
+
+        // timestamp 64
+        let timestamp64 = timestamp(((123_456_789u64 << 34) | 1609459200).to_be_bytes().to_vec());
+        assert_eq!(timestamp64.unwrap(), Utc.timestamp(1609459200, 123_456_789));
+
+        // timestamp 96, which also covers times before the epoch
+        let mut data = 500_000_000u32.to_be_bytes().to_vec();
+        data.extend_from_slice(&(-1i64).to_be_bytes());
+        let timestamp96 = timestamp(data).unwrap();
+        assert_eq!(timestamp96, Utc.timestamp(-1, 500_000_000));
+
+        let error = timestamp(vec![0; 6]).unwrap_err();
+        assert!(matches!(error, DecodeError::Decode(_)));
+    }
+
+    #[test]
+    fn lenient_timestamps() {
+        //[
+        //  "tag.name",
+        //  EventTime with only the 4 bytes of seconds (1609459200),
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 214, 0, 95, 238, 102, 0, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+
+        let error = decode_all(message.clone()).unwrap_err();
+        assert!(matches!(error, DecodeError::Decode(_)));
+
+        let before = Utc::now();
+        let event = decode_all_with(&source("lenient_timestamps = true"), message)
+            .unwrap()
+            .remove(0);
+        assert_eq!(event["message"], "bar".into());
+        match event["timestamp"] {
+            Value::Timestamp(timestamp) => assert!(timestamp >= before),
+            ref value => panic!("expected a timestamp, got {:?}", value),
+        }
+    }
+
+    #[test]
+    fn lenient_timestamps_reject_plain_arrays() {
+        //[
+        //  "tag.name",
+        //  [],
+        //  {"message": "bar"},
+        //]
+        let empty: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 144, 129, 167, 109, 101, 115, 115, 97,
+            103, 101, 163, 98, 97, 114,
+        ];
+        //[
+        //  "tag.name",
+        //  [5, "ab"],
+        //  {"message": "bar"},
+        //]
+        let pair: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 146, 5, 162, 97, 98, 129, 167, 109, 101,
+            115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+
+        for message in vec![empty, pair] {
+            let error = decode_all_with(&source("lenient_timestamps = true"), message).unwrap_err();
+            assert!(matches!(error, DecodeError::Decode(_)));
+        }
+    }
+
+    #[tokio::test]
+    async fn proxy_protocol_host() {
+        let (tx, rx) = Pipeline::new_test();
+        let address = next_addr();
+        let config: FluentConfig = toml::from_str(&format!(
+            r#"
+            address = "{}"
+            proxy_protocol = true
+            "#,
+            address
+        ))
+        .unwrap();
+        tokio::spawn(config.build(SourceContext::new_test(tx)).await.unwrap());
+        wait_for_tcp(address).await;
+
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 24224\r\n")
+            .await
+            .unwrap();
+        stream.write_all(&message).await.unwrap();
+
+        let events = collect_n(rx, 1).await;
+        assert_eq!(
+            events[0].as_log()[log_schema().host_key()],
+            "192.168.0.1".into()
+        );
+        assert_eq!(events[0].as_log()["message"], "bar".into());
+    }
+
+    #[test]
+    fn keepalive_default() {
+        let config: FluentConfig = toml::from_str(r#"address = "0.0.0.0:24224""#).unwrap();
+        assert_eq!(
+            config.keepalive,
+            Some(TcpKeepaliveConfig {
+                time_secs: Some(DEFAULT_KEEPALIVE_SECS)
+            })
+        );
+
+        let config: FluentConfig = toml::from_str(
+            r#"
+            address = "0.0.0.0:24224"
+            keepalive.time_secs = 10
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.keepalive,
+            Some(TcpKeepaliveConfig {
+                time_secs: Some(10)
+            })
+        );
+
+        // an empty table leaves the operating system setting untouched
+        let config: FluentConfig = toml::from_str(
+            r#"
+            address = "0.0.0.0:24224"
+            keepalive = {}
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.keepalive,
+            Some(TcpKeepaliveConfig { time_secs: None })
+        );
+
+        let config: FluentConfig = toml::from_str(
+            r#"
+            address = "0.0.0.0:24224"
+            keepalive.enabled = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.keepalive, None);
+    }
 
     #[test]
-    fn generate_config() {
-        crate::test_util::test_generate_config::<FluentConfig>();
+    fn invalid_keepalive() {
+        for keepalive in &[
+            "keepalive.time_secs = 0",
+            "keepalive.time_secs = -1",
+            "keepalive = { enabled = false, time_secs = 10 }",
+        ] {
+            let error = toml::from_str::<FluentConfig>(&format!(
+                "address = \"0.0.0.0:24224\"\n{}",
+                keepalive
+            ))
+            .unwrap_err();
+            assert!(error.to_string().contains("keepalive"), "{}", error);
+        }
+    }
+
+    #[tokio::test]
+    async fn global_buffer_bytes_below_max_frame_bytes() {
+        let (tx, _rx) = Pipeline::new_test();
+        let config: FluentConfig = toml::from_str(
+            r#"
+            address = "0.0.0.0:24224"
+            max_frame_bytes = 1024
+            global_buffer_bytes = 512
+            "#,
+        )
+        .unwrap();
+        let error = config
+            .build(SourceContext::new_test(tx))
+            .await
+            .err()
+            .expect("config should be rejected");
+        assert!(
+            error.to_string().contains("global_buffer_bytes"),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn timestamp_from_field_without_timestamp_key() {
+        let (tx, _rx) = Pipeline::new_test();
+        let config: FluentConfig = toml::from_str(
+            r#"
+            address = "0.0.0.0:24224"
+            timestamp_key = ""
+            timestamp_from_field = "@timestamp"
+            "#,
+        )
+        .unwrap();
+        let error = config
+            .build(SourceContext::new_test(tx))
+            .await
+            .err()
+            .expect("config should be rejected");
+        assert!(
+            error.to_string().contains("timestamp_from_field"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn invalid_address() {
+        let error = toml::from_str::<FluentConfig>(r#"address = ["0.0.0.0:24224", "systemd#0"]"#)
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("systemd indices start from 1"),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn multiple_addresses() {
+        let (tx, rx) = Pipeline::new_test();
+        let addresses = [next_addr(), next_addr()];
+        let config: FluentConfig = toml::from_str(&format!(
+            r#"
+            address = ["{}", "{}"]
+            "#,
+            addresses[0], addresses[1]
+        ))
+        .unwrap();
+        assert_eq!(config.resources().len(), 2);
+        tokio::spawn(config.build(SourceContext::new_test(tx)).await.unwrap());
+
+        for address in &addresses {
+            wait_for_tcp(*address).await;
+            let mut stream = TcpStream::connect(address).await.unwrap();
+            let mut message = Vec::new();
+            rmpv::encode::write_value(
+                &mut message,
+                &rmpv::Value::Array(vec![
+                    "tag.name".into(),
+                    1441588984.into(),
+                    rmpv::Value::Map(vec![("message".into(), address.to_string().into())]),
+                ]),
+            )
+            .unwrap();
+            stream.write_all(&message).await.unwrap();
+        }
+
+        let mut messages = collect_n(rx, 2)
+            .await
+            .into_iter()
+            .map(|event| event.as_log()["message"].to_string_lossy())
+            .collect::<Vec<_>>();
+        messages.sort();
+        let mut expected = addresses
+            .iter()
+            .map(|address| address.to_string())
+            .collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(messages, expected);
+    }
+
+    #[tokio::test]
+    async fn tls_client_metadata() {
+        let (tx, rx) = Pipeline::new_test();
+        let address = next_addr();
+        let config: FluentConfig = toml::from_str(&format!(
+            r#"
+            address = "{}"
+            tls_client_metadata_key = "tls_client"
+
+            [tls]
+            enabled = true
+            crt_file = "tests/data/localhost.crt"
+            key_file = "tests/data/localhost.key"
+            ca_file = "tests/data/Client_CA.crt"
+            verify_certificate = true
+            "#,
+            address
+        ))
+        .unwrap();
+        tokio::spawn(config.build(SourceContext::new_test(tx)).await.unwrap());
+        wait_for_tcp(address).await;
+
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        connector.set_verify(SslVerifyMode::NONE);
+        connector
+            .set_certificate_file("tests/data/client.crt", SslFiletype::PEM)
+            .unwrap();
+        connector
+            .set_private_key_file("tests/data/client.key", SslFiletype::PEM)
+            .unwrap();
+        let ssl = connector
+            .build()
+            .configure()
+            .unwrap()
+            .into_ssl("localhost")
+            .unwrap();
+        let stream = TcpStream::connect(address).await.unwrap();
+        let mut stream = tokio_openssl::SslStream::new(ssl, stream).unwrap();
+        Pin::new(&mut stream).connect().await.unwrap();
+        stream.write_all(&message).await.unwrap();
+
+        let events = collect_n(rx, 1).await;
+        let log = events[0].as_log();
+        assert_eq!(log["tls_client.common_name"], "fluent-client".into());
+        assert_eq!(
+            log["tls_client.subject_alt_names"],
+            vec!["client.example.com", "client@example.com"].into()
+        );
+        assert_eq!(log["message"], "bar".into());
+    }
+
+    #[tokio::test]
+    async fn sni_key() {
+        let (tx, mut rx) = Pipeline::new_test();
+        let address = next_addr();
+        let config: FluentConfig = toml::from_str(&format!(
+            r#"
+            address = "{}"
+            sni_key = "server_name"
+
+            [tls]
+            enabled = true
+            crt_file = "tests/data/localhost.crt"
+            key_file = "tests/data/localhost.key"
+            "#,
+            address
+        ))
+        .unwrap();
+        tokio::spawn(config.build(SourceContext::new_test(tx)).await.unwrap());
+        wait_for_tcp(address).await;
+
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+
+        async fn send(address: SocketAddr, message: &[u8], sni: bool) {
+            let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+            connector.set_verify(SslVerifyMode::NONE);
+            let mut configuration = connector.build().configure().unwrap();
+            configuration.set_use_server_name_indication(sni);
+            let ssl = configuration.into_ssl("tenant.example.com").unwrap();
+            let stream = TcpStream::connect(address).await.unwrap();
+            let mut stream = tokio_openssl::SslStream::new(ssl, stream).unwrap();
+            Pin::new(&mut stream).connect().await.unwrap();
+            stream.write_all(message).await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+
+        send(address, &message, true).await;
+        let event = rx.next().await.unwrap();
+        assert_eq!(event.as_log()["server_name"], "tenant.example.com".into());
+
+        send(address, &message, false).await;
+        let event = rx.next().await.unwrap();
+        assert!(!event.as_log().contains("server_name"));
+        assert_eq!(event.as_log()["message"], "bar".into());
+    }
+
+    #[tokio::test]
+    async fn tls_handshake_timeout() {
+        let timeouts = || counter("connection_handshake_timeouts_total");
+
+        let (tx, _rx) = Pipeline::new_test();
+        let address = next_addr();
+        let config: FluentConfig = toml::from_str(&format!(
+            r#"
+            address = "{}"
+            tls_handshake_timeout_secs = 1
+
+            [tls]
+            enabled = true
+            crt_file = "tests/data/localhost.crt"
+            key_file = "tests/data/localhost.key"
+            "#,
+            address
+        ))
+        .unwrap();
+        tokio::spawn(config.build(SourceContext::new_test(tx)).await.unwrap());
+        wait_for_tcp(address).await;
+
+        // A client that connects but never starts the handshake.
+        let before = timeouts();
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let mut buf = [0u8; 1];
+        let read = timeout(Duration::from_secs(5), stream.read(&mut buf)).await;
+        assert!(
+            matches!(read, Ok(Ok(0)) | Ok(Err(_))),
+            "connection wasn't closed"
+        );
+        assert!(timeouts() - before >= 1.0);
+    }
+
+    #[tokio::test]
+    async fn connection_metadata_key() {
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+
+        let (tx, mut rx) = Pipeline::new_test();
+        let address = next_addr();
+        let config: FluentConfig = toml::from_str(&format!(
+            r#"
+            address = "{}"
+            connection_metadata_key = "connection"
+
+            [tls]
+            enabled = true
+            crt_file = "tests/data/localhost.crt"
+            key_file = "tests/data/localhost.key"
+            "#,
+            address
+        ))
+        .unwrap();
+        tokio::spawn(config.build(SourceContext::new_test(tx)).await.unwrap());
+        wait_for_tcp(address).await;
+
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        connector.set_verify(SslVerifyMode::NONE);
+        let ssl = connector
+            .build()
+            .configure()
+            .unwrap()
+            .into_ssl("localhost")
+            .unwrap();
+        let stream = TcpStream::connect(address).await.unwrap();
+        let mut stream = tokio_openssl::SslStream::new(ssl, stream).unwrap();
+        Pin::new(&mut stream).connect().await.unwrap();
+        let tls_version = stream.ssl().version_str();
+        let cipher = stream.ssl().current_cipher().unwrap().name();
+        stream.write_all(&message).await.unwrap();
+        stream.shutdown().await.unwrap();
+
+        let event = rx.next().await.unwrap();
+        let log = event.as_log();
+        assert_eq!(log["connection.local_addr"], address.to_string().into());
+        assert_eq!(log["connection.tls_version"], tls_version.into());
+        assert_eq!(log["connection.cipher"], cipher.into());
+
+        // only the local address for plaintext connections
+        let (tx, mut rx) = Pipeline::new_test();
+        let address = next_addr();
+        let config: FluentConfig = toml::from_str(&format!(
+            r#"
+            address = "{}"
+            connection_metadata_key = "connection"
+            "#,
+            address
+        ))
+        .unwrap();
+        tokio::spawn(config.build(SourceContext::new_test(tx)).await.unwrap());
+        wait_for_tcp(address).await;
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(&message).await.unwrap();
+
+        let event = rx.next().await.unwrap();
+        assert_eq!(
+            event.as_log()["connection"],
+            Value::from(btreemap! {
+                "local_addr" => address.to_string(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn client_message_modes() {
+        let (tx, rx) = Pipeline::new_test();
+        let address = next_addr();
+        let config: FluentConfig = toml::from_str(&format!(r#"address = "{}""#, address)).unwrap();
+        tokio::spawn(config.build(SourceContext::new_test(tx)).await.unwrap());
+        wait_for_tcp(address).await;
+
+        let record = |message: &str| rmpv::Value::Map(vec![("message".into(), message.into())]);
+        let mut client = FluentClient::connect(address).await;
+        client
+            .send_message("message", 1441588984, record("foo"))
+            .await;
+        client
+            .send_forward(
+                "forward",
+                vec![(1441588985, record("bar")), (1441588986, record("baz"))],
+            )
+            .await;
+        client
+            .send_packed_forward("packed", vec![(1441588987, record("qux"))], false)
+            .await;
+        client
+            .send_packed_forward(
+                "compressed",
+                vec![(1441588988, record("quux")), (1441588989, record("corge"))],
+                true,
+            )
+            .await;
+
+        let events = collect_n(rx, 6).await;
+        let expected = vec![
+            ("message", 1441588984, "foo"),
+            ("forward", 1441588985, "bar"),
+            ("forward", 1441588986, "baz"),
+            ("packed", 1441588987, "qux"),
+            ("compressed", 1441588988, "quux"),
+            ("compressed", 1441588989, "corge"),
+        ];
+        for (event, (tag, timestamp, message)) in events.iter().zip(expected) {
+            assert_event_data_eq!(
+                *event.as_log(),
+                LogEvent::from(btreemap! {
+                    "host" => "127.0.0.1",
+                    "message" => message,
+                    "source_type" => "fluent",
+                    "tag" => tag,
+                    "timestamp" => Value::Timestamp(Utc.timestamp(timestamp, 0)),
+                })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn per_source_keys() {
+        let (tx, rx) = Pipeline::new_test();
+        let mut addresses = Vec::new();
+        for keys in &[
+            r#"host_key = "client""#,
+            r#"
+            host_key = "peer"
+            timestamp_key = "time"
+            tag_key = "route"
+            source_type_key = "kind"
+            "#,
+        ] {
+            let address = next_addr();
+            let config: FluentConfig =
+                toml::from_str(&format!("address = \"{}\"\n{}", address, keys)).unwrap();
+            tokio::spawn(
+                config
+                    .build(SourceContext::new_test(tx.clone()))
+                    .await
+                    .unwrap(),
+            );
+            wait_for_tcp(address).await;
+            addresses.push(address);
+        }
+
+        let record = rmpv::Value::Map(vec![("message".into(), "foo".into())]);
+        for address in &addresses {
+            FluentClient::connect(*address)
+                .await
+                .send_message("tag.name", 1441588984, record.clone())
+                .await;
+        }
+
+        let mut events = collect_n(rx, 2).await;
+        events.sort_by_key(|event| event.as_log().contains("client"));
+        assert_event_data_eq!(
+            *events[1].as_log(),
+            LogEvent::from(btreemap! {
+                "client" => "127.0.0.1",
+                "message" => "foo",
+                "source_type" => "fluent",
+                "tag" => "tag.name",
+                "timestamp" => Value::Timestamp(Utc.timestamp(1441588984, 0)),
+            })
+        );
+        assert_event_data_eq!(
+            *events[0].as_log(),
+            LogEvent::from(btreemap! {
+                "peer" => "127.0.0.1",
+                "message" => "foo",
+                "kind" => "fluent",
+                "route" => "tag.name",
+                "time" => Value::Timestamp(Utc.timestamp(1441588984, 0)),
+            })
+        );
+    }
+
+    fn build_all(source: &FluentSource, message: Vec<u8>, peer_addr: SocketAddr) -> Vec<Event> {
+        let mut buf = BytesMut::from(&message[..]);
+
+        let mut decoder = source.decoder(peer_addr);
+
+        let mut events = vec![];
+        while let Some(frame) = decoder.decode(&mut buf).unwrap() {
+            events.extend(source.build_event(frame, peer_addr));
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn drain_on_shutdown() {
+        let (tx, rx) = Pipeline::new_test();
+        let (trigger, shutdown, _shutdown_done) = ShutdownSignal::new_wired();
+        let address = next_addr();
+        let server = source("")
+            .run(
+                address.into(),
+                None,
+                0,
+                MaybeTlsSettings::Raw(()),
+                None,
+                TcpSourceOptions::default(),
+                shutdown,
+                tx,
+            )
+            .unwrap();
+        tokio::spawn(server);
+        wait_for_tcp(address).await;
+
+        // More entries than the pipeline buffers, so most are still in the decoder at shutdown.
+        let mut entries = Vec::new();
+        for count in 0..500u64 {
+            rmpv::encode::write_value(
+                &mut entries,
+                &rmpv::Value::Array(vec![
+                    1441588984u32.into(),
+                    rmpv::Value::Map(vec![("count".into(), count.into())]),
+                ]),
+            )
+            .unwrap();
+        }
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec!["tag.name".into(), rmpv::Value::Binary(entries)]),
+        )
+        .unwrap();
+
+        // The client keeps the connection open, so it's only closed by the shutdown timeout.
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(&message).await.unwrap();
+        sleep(Duration::from_millis(200)).await;
+
+        trigger.cancel();
+        sleep(Duration::from_millis(200)).await;
+
+        let events = collect_n(rx, 500).await;
+        assert_eq!(events.len(), 500);
+        for (count, event) in events.iter().enumerate() {
+            assert_eq!(event.as_log()["count"], (count as i64).into());
+        }
+    }
+
+    #[tokio::test]
+    async fn udp_heartbeat() {
+        let (tx, _rx) = Pipeline::new_test();
+        let address = next_addr();
+        let heartbeat_address = next_addr();
+        let config: FluentConfig = toml::from_str(&format!(
+            r#"
+            address = "{}"
+            heartbeat_udp = "{}"
+            "#,
+            address, heartbeat_address
+        ))
+        .unwrap();
+        tokio::spawn(config.build(SourceContext::new_test(tx)).await.unwrap());
+        wait_for_tcp(address).await;
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(heartbeat_address).await.unwrap();
+        // not a heartbeat, so it isn't answered
+        client.send(&[0, 0]).await.unwrap();
+        client.send(&[0]).await.unwrap();
+
+        let mut buf = [0u8; 8];
+        let len = timeout(Duration::from_secs(1), client.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..len], &[0]);
+        assert!(timeout(Duration::from_millis(100), client.recv(&mut buf))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn udp_heartbeat_systemd() {
+        let address = next_addr();
+        let config: FluentConfig = toml::from_str(&format!(
+            r#"
+            address = "{}"
+            heartbeat_udp = "systemd#2"
+            "#,
+            address
+        ))
+        .unwrap();
+        assert_eq!(config.heartbeat_udp, Some(SocketListenAddr::SystemdFd(1)));
+        assert_eq!(
+            config.resources(),
+            vec![Resource::tcp(address), Resource::SystemFdOffset(1)]
+        );
+
+        // no sockets are passed to the tests
+        let (tx, _rx) = Pipeline::new_test();
+        let error = config
+            .build(SourceContext::new_test(tx))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "systemd socket #1 isn't open or is already taken."
+        );
+    }
+
+    #[tokio::test]
+    async fn buffered_bytes_metric() {
+        let _ = crate::metrics::init();
+        let controller = crate::metrics::get_controller().unwrap();
+        let buffered_bytes = || {
+            crate::metrics::capture_metrics(controller)
+                .map(Event::into_metric)
+                .filter(|metric| metric.name() == "fluent_buffered_bytes")
+                .map(|metric| match metric.value() {
+                    MetricValue::Gauge { value } => *value,
+                    value => panic!("expected a gauge, got {:?}", value),
+                })
+                .sum::<f64>()
+        };
+
+        let (tx, _rx) = Pipeline::new_test();
+        let address = next_addr();
+        let config: FluentConfig = toml::from_str(&format!("address = \"{}\"", address)).unwrap();
+        tokio::spawn(config.build(SourceContext::new_test(tx)).await.unwrap());
+        wait_for_tcp(address).await;
+
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                1441588984.into(),
+                rmpv::Value::Map(vec![("message".into(), "x".repeat(128 * 1024).into())]),
+            ]),
+        )
+        .unwrap();
+        // other tests may have bytes buffered at the same time, but not this many
+        let partial = 64 * 1024;
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(&message[..partial]).await.unwrap();
+        timeout(Duration::from_secs(5), async {
+            while buffered_bytes() < partial as f64 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("buffered bytes didn't rise");
+
+        // the partial frame is dropped along with the connection
+        drop(stream);
+        timeout(Duration::from_secs(5), async {
+            while buffered_bytes() >= partial as f64 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("buffered bytes didn't fall");
+    }
+
+    #[tokio::test]
+    async fn idle_timeout() {
+        let (tx, mut rx) = Pipeline::new_test();
+        let address = next_addr();
+        let config: FluentConfig = toml::from_str(&format!(
+            r#"
+            address = "{}"
+            idle_timeout_secs = 1
+            "#,
+            address
+        ))
+        .unwrap();
+        tokio::spawn(config.build(SourceContext::new_test(tx)).await.unwrap());
+        wait_for_tcp(address).await;
+
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+
+        let mut idle = TcpStream::connect(address).await.unwrap();
+        let mut active = TcpStream::connect(address).await.unwrap();
+
+        for _ in 0..6 {
+            sleep(Duration::from_millis(300)).await;
+            active.write_all(&message).await.unwrap();
+            assert!(rx.next().await.is_some());
+        }
+
+        // the idle connection has been closed by the source
+        let mut buf = [0u8; 1];
+        let read = timeout(Duration::from_secs(1), idle.read(&mut buf)).await;
+        assert!(matches!(read, Ok(Ok(0)) | Ok(Err(_))));
+
+        // while the active one is still open
+        let read = timeout(Duration::from_millis(100), active.read(&mut buf)).await;
+        assert!(read.is_err());
+        active.write_all(&message).await.unwrap();
+        assert!(rx.next().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn first_frame_timeout() {
+        let (tx, mut rx) = Pipeline::new_test();
+        let address = next_addr();
+        let config: FluentConfig = toml::from_str(&format!(
+            r#"
+            address = "{}"
+            idle_timeout_secs = 1
+            first_frame_timeout_secs = 2
+
+            [tls]
+            enabled = true
+            crt_file = "tests/data/localhost.crt"
+            key_file = "tests/data/localhost.key"
+            "#,
+            address
+        ))
+        .unwrap();
+        tokio::spawn(config.build(SourceContext::new_test(tx)).await.unwrap());
+        wait_for_tcp(address).await;
+
+        //[
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
+        //]
+        let message: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
+        ];
+
+        let connect = move || async move {
+            let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+            connector.set_verify(SslVerifyMode::NONE);
+            let ssl = connector
+                .build()
+                .configure()
+                .unwrap()
+                .into_ssl("localhost")
+                .unwrap();
+            let stream = TcpStream::connect(address).await.unwrap();
+            let mut stream = tokio_openssl::SslStream::new(ssl, stream).unwrap();
+            Pin::new(&mut stream).connect().await.unwrap();
+            stream
+        };
+        let mut slow = connect().await;
+        let mut idle = connect().await;
+
+        // Slower than the idle timeout, but within the first frame timeout.
+        sleep(Duration::from_millis(1500)).await;
+        slow.write_all(&message).await.unwrap();
+        let event = timeout(Duration::from_secs(1), rx.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.as_log()["message"], "bar".into());
+
+        // The idle connection never sent anything, so it's closed once the first frame timeout
+        // passes.
+        let mut buf = [0u8; 1];
+        let read = timeout(Duration::from_secs(2), idle.read(&mut buf)).await;
+        assert!(matches!(read, Ok(Ok(0)) | Ok(Err(_))));
+
+        // After its first frame the slow connection is subject to the idle timeout.
+        let read = timeout(Duration::from_secs(2), slow.read(&mut buf)).await;
+        assert!(matches!(read, Ok(Ok(0)) | Ok(Err(_))));
+    }
+
+    /// ["tag.name", <empty binary>, {"compressed": "lz4"}], which fails to decode but leaves the
+    /// connection open.
+    fn unknown_compression_message() -> Vec<u8> {
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec![
+                "tag.name".into(),
+                rmpv::Value::Binary(vec![]),
+                rmpv::Value::Map(vec![("compressed".into(), "lz4".into())]),
+            ]),
+        )
+        .unwrap();
+        message
     }
 
-    // useful references for msgpack:
-    // Spec: https://github.com/msgpack/msgpack/blob/master/spec.md
-    // Encode to array of bytes: https://kawanet.github.io/msgpack-lite/
-    // Decode base64: https://toolslick.com/conversion/data/messagepack-to-json
-
     #[test]
-    fn decode_message_mode() {
+    fn max_decode_errors_per_connection() {
+        let invalid = unknown_compression_message();
         //[
         //  "tag.name",
         //  1441588984,
@@ -555,220 +6570,332 @@ mod tests {
             147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
             101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
         ];
-
-        let expected = LogEvent::from(btreemap! {
-            "message" => "bar",
-            "tag" => "tag.name",
-            "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:04Z").unwrap().into()),
-        });
-        assert_event_data_eq!(decode_all(message).unwrap()[0], expected);
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&invalid);
+        buf.extend_from_slice(&message);
+        buf.extend_from_slice(&invalid);
+        buf.extend_from_slice(&invalid);
+        buf.extend_from_slice(&message);
+
+        let mut decoder = source("max_decode_errors_per_connection = 2").decoder(test_peer_addr());
+        assert!(matches!(
+            decoder.decode(&mut buf),
+            Err(DecodeError::UnknownCompression(_))
+        ));
+        // a decoded message resets the count
+        assert!(decoder.decode(&mut buf).unwrap().is_some());
+        assert!(matches!(
+            decoder.decode(&mut buf),
+            Err(DecodeError::UnknownCompression(_))
+        ));
+        let error = decoder.decode(&mut buf).unwrap_err();
+        assert!(matches!(error, DecodeError::TooManyDecodeErrors(2)));
+        assert!(error.is_error_fatal());
     }
 
     #[test]
-    fn decode_message_mode_with_options() {
+    fn event_decoder() {
+        let config: FluentConfig = toml::from_str(
+            r#"
+            address = "0.0.0.0:24224"
+            port_key = "port"
+            "#,
+        )
+        .unwrap();
+        let mut decoder = FluentEventDecoder::new(&config, "10.1.2.3:51234".parse().unwrap());
+
         //[
         //  "tag.name",
-        //   1441588984,
-        //   { "message": "bar" },
-        //   { "size": 1 }
+        //  1441588984,
+        //  {"message": "bar"},
         //]
         let message: Vec<u8> = vec![
-            148, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
-            101, 115, 115, 97, 103, 101, 163, 98, 97, 114, 129, 164, 115, 105, 122, 101, 1,
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
         ];
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&message);
+        buf.extend_from_slice(&unknown_compression_message());
+        buf.extend_from_slice(&message[..10]);
+
+        // The invalid message is skipped, and the incomplete one kept for later.
+        let events = decoder.decode_bytes(&mut buf).unwrap();
+        assert_eq!(events.len(), 1);
+        let log = events[0].as_log();
+        assert_eq!(log["message"], "bar".into());
+        assert_eq!(log["host"], "10.1.2.3".into());
+        assert_eq!(log["port"], Value::Integer(51234));
+        assert_eq!(buf.len(), 10);
+
+        buf.extend_from_slice(&message[10..]);
+        let events = decoder.decode_bytes(&mut buf).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].as_log()["tag"], "tag.name".into());
+        assert!(buf.is_empty());
+    }
 
-        let expected = LogEvent::from(btreemap! {
-            "message" => "bar",
-            "tag" => "tag.name",
-            "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:04Z").unwrap().into()),
-        });
-        assert_event_data_eq!(decode_all(message).unwrap()[0], expected);
+    #[test]
+    fn length_delimited_framing() {
+        let message = |message: &str| {
+            let mut encoded = Vec::new();
+            rmpv::encode::write_value(
+                &mut encoded,
+                &rmpv::Value::Array(vec![
+                    "tag.name".into(),
+                    1441588984.into(),
+                    rmpv::Value::Map(vec![("message".into(), message.into())]),
+                ]),
+            )
+            .unwrap();
+            let mut prefixed = (encoded.len() as u32).to_be_bytes().to_vec();
+            prefixed.extend_from_slice(&encoded);
+            prefixed
+        };
+        let framed = source(r#"framing = "length_delimited""#);
+
+        // split across reads anywhere, including within the length prefix
+        let mut data = message("foo");
+        data.extend_from_slice(&message("bar"));
+        let mut decoder = framed.decoder(test_peer_addr());
+        let mut buf = BytesMut::new();
+        let mut messages = Vec::new();
+        for byte in data {
+            buf.extend_from_slice(&[byte]);
+            while let Some(frame) = decoder.decode(&mut buf).unwrap() {
+                messages.push(framed.log_from_frame(frame)["message"].clone());
+            }
+        }
+        assert_eq!(messages, vec!["foo".into(), "bar".into()]);
+        assert!(buf.is_empty());
+
+        // a frame with more than the message is skipped as a whole
+        let mut padded = message("foo");
+        padded[3] += 1;
+        padded.push(0xc0);
+        padded.extend_from_slice(&message("bar"));
+        let mut buf = BytesMut::from(&padded[..]);
+        let mut decoder = framed.decoder(test_peer_addr());
+        assert!(matches!(
+            decoder.decode(&mut buf),
+            Err(DecodeError::Decode(_))
+        ));
+        let frame = decoder.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(framed.log_from_frame(frame)["message"], "bar".into());
+
+        // the limit applies to the length prefix, before the message is buffered
+        let limited = source(
+            r#"
+            framing = "length_delimited"
+            max_frame_bytes = 16
+            "#,
+        );
+        let mut buf = BytesMut::from(&message("foo")[..8]);
+        assert!(matches!(
+            limited.decoder(test_peer_addr()).decode(&mut buf),
+            Err(DecodeError::FrameTooLarge(16))
+        ));
+
+        // and a default one when it isn't configured
+        let mut buf = BytesMut::from(&u32::MAX.to_be_bytes()[..]);
+        assert!(matches!(
+            framed.decoder(test_peer_addr()).decode(&mut buf),
+            Err(DecodeError::FrameTooLarge(
+                DEFAULT_LENGTH_DELIMITED_MAX_FRAME_BYTES
+            ))
+        ));
     }
 
     #[test]
-    fn decode_forward_mode() {
+    fn decode_error_context() {
+        let invalid = unknown_compression_message();
         //[
-        //    "tag.name",
-        //    [
-        //        [1441588984, {"message": "foo"}],
-        //        [1441588985, {"message": "bar"}],
-        //        [1441588986, {"message": "baz"}]
-        //    ]
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
         //]
         let message: Vec<u8> = vec![
-            146, 168, 116, 97, 103, 46, 110, 97, 109, 101, 147, 146, 206, 85, 236, 230, 248, 129,
-            167, 109, 101, 115, 115, 97, 103, 101, 163, 102, 111, 111, 146, 206, 85, 236, 230, 249,
-            129, 167, 109, 101, 115, 115, 97, 103, 101, 163, 98, 97, 114, 146, 206, 85, 236, 230,
-            250, 129, 167, 109, 101, 115, 115, 97, 103, 101, 163, 98, 97, 122,
-        ];
-
-        let expected = vec![
-            LogEvent::from(btreemap! {
-                "message" => "foo",
-                "tag" => "tag.name",
-                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:04Z").unwrap().into()),
-            }),
-            LogEvent::from(btreemap! {
-                "message" => "bar",
-                "tag" => "tag.name",
-                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:05Z").unwrap().into()),
-            }),
-            LogEvent::from(btreemap! {
-                "message" => "baz",
-                "tag" => "tag.name",
-                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:06Z").unwrap().into()),
-            }),
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
         ];
-
-        let got = decode_all(message).unwrap();
-
-        assert_event_data_eq!(got[0], expected[0]);
-        assert_event_data_eq!(got[1], expected[1]);
-        assert_event_data_eq!(got[2], expected[2]);
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&message);
+        buf.extend_from_slice(&invalid);
+
+        let peer_addr = "10.1.2.3:51234".parse().unwrap();
+        let mut decoder = source("").decoder(peer_addr);
+        assert!(decoder.decode(&mut buf).unwrap().is_some());
+        let error = decoder.decode(&mut buf).unwrap_err();
+
+        let event = decoder.decode_error(&error, String::new());
+        assert_eq!(event.peer_addr, peer_addr);
+        assert_eq!(event.offset, message.len());
+        // the context is kept out of the message itself
+        assert!(!error.to_string().contains("10.1.2.3"));
     }
 
     #[test]
-    fn decode_forward_mode_with_options() {
+    fn emit_decode_errors_as_events() {
+        let invalid = unknown_compression_message();
         //[
-        //    "tag.name",
-        //    [
-        //        [1441588984, {"message": "foo"}],
-        //        [1441588985, {"message": "bar"}],
-        //        [1441588986, {"message": "baz"}]
-        //    ],
-        //    {"size": 3}
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
         //]
-        let message: Vec<u8> = vec![
-            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 147, 146, 206, 85, 236, 230, 248, 129,
-            167, 109, 101, 115, 115, 97, 103, 101, 163, 102, 111, 111, 146, 206, 85, 236, 230, 249,
-            129, 167, 109, 101, 115, 115, 97, 103, 101, 163, 98, 97, 114, 146, 206, 85, 236, 230,
-            250, 129, 167, 109, 101, 115, 115, 97, 103, 101, 163, 98, 97, 122, 129, 164, 115, 105,
-            122, 101, 3,
-        ];
-
-        let expected = vec![
-            LogEvent::from(btreemap! {
-                "message" => "foo",
-                "tag" => "tag.name",
-                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:04Z").unwrap().into()),
-            }),
-            LogEvent::from(btreemap! {
-                "message" => "bar",
-                "tag" => "tag.name",
-                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:05Z").unwrap().into()),
-            }),
-            LogEvent::from(btreemap! {
-                "message" => "baz",
-                "tag" => "tag.name",
-                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:06Z").unwrap().into()),
-            }),
+        let valid: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
         ];
+        let mut message = invalid.clone();
+        message.extend_from_slice(&valid);
+
+        let source = source("emit_decode_errors_as_events = true");
+        let got = decode_all_with(&source, message).unwrap();
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[0]["tag"], DEAD_LETTER_TAG.into());
+        assert_eq!(got[0]["error_type"], "unknown_compression".into());
+        assert_eq!(got[0]["error"], "unknown compression: lz4".into());
+        assert_eq!(got[0]["message"], base64::encode(&invalid).into());
+        assert_eq!(got[1]["tag"], "tag.name".into());
+        assert_eq!(got[1]["message"], "bar".into());
+
+        // dropped otherwise
+        assert!(decode_all(invalid).is_err());
+    }
 
-        let got = decode_all(message).unwrap();
+    #[test]
+    fn emit_decode_errors_as_events_pending_entry() {
+        let mut entries = Vec::new();
+        for i in 0..4 {
+            rmpv::encode::write_value(
+                &mut entries,
+                &rmpv::Value::Array(vec![
+                    1441588984.into(),
+                    rmpv::Value::Map(vec![("index".into(), i.into())]),
+                ]),
+            )
+            .unwrap();
+        }
+        // [nil, nil] isn't an entry
+        entries.extend(&[0x92, 0xc0, 0xc0]);
+        let mut message = Vec::new();
+        rmpv::encode::write_value(
+            &mut message,
+            &rmpv::Value::Array(vec!["tag.name".into(), rmpv::Value::Binary(entries)]),
+        )
+        .unwrap();
 
-        assert_event_data_eq!(got[0], expected[0]);
-        assert_event_data_eq!(got[1], expected[1]);
-        assert_event_data_eq!(got[2], expected[2]);
+        // The bad entry is only decoded once some of the frames before it are handed out, and
+        // the message it's from is still the one reported.
+        let source = source(
+            r#"
+            max_pending_frames = 2
+            emit_decode_errors_as_events = true
+            "#,
+        );
+        let got = decode_all_with(&source, message.clone()).unwrap();
+        let dead_letter = got.last().unwrap();
+        assert!(got.len() > 2);
+        assert_eq!(dead_letter["tag"], DEAD_LETTER_TAG.into());
+        assert_eq!(dead_letter["message"], base64::encode(&message).into());
     }
 
     #[test]
-    fn decode_packed_forward_mode() {
+    fn decode_error_sample_bytes() {
+        let data = vec![0xc1; 1024];
+        let sample = |config: &str| source(config).decoder(test_peer_addr()).error_sample(&data);
+
+        assert_eq!(sample(""), base64::encode(&data[..256]));
+        assert_eq!(
+            sample("decode_error_sample_bytes = 16"),
+            base64::encode(&data[..16])
+        );
+        assert_eq!(
+            sample("decode_error_sample_bytes = 4096"),
+            base64::encode(&data)
+        );
+        assert_eq!(sample("decode_error_sample_bytes = 0"), "");
+
+        // sampled from the message that failed, rather than what's buffered after it
+        let invalid = unknown_compression_message();
         //[
-        //    "tag.name",
-        //    <packed messages>
+        //  "tag.name",
+        //  1441588984,
+        //  {"message": "bar"},
         //]
-        //
-        //With packed messages as bin:
-        // [1441588984, {"message": "foo"}]
-        // [1441588985, {"message": "bar"}]
-        // [1441588986, {"message": "baz"}]
-        let message: Vec<u8> = vec![
-            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 196, 57, 146, 206, 85, 236, 230, 248,
-            129, 167, 109, 101, 115, 115, 97, 103, 101, 163, 102, 111, 111, 146, 206, 85, 236, 230,
-            249, 129, 167, 109, 101, 115, 115, 97, 103, 101, 163, 98, 97, 114, 146, 206, 85, 236,
-            230, 250, 129, 167, 109, 101, 115, 115, 97, 103, 101, 163, 98, 97, 122, 129, 167, 109,
-            101, 115, 115, 97, 103, 101, 163, 102, 111, 111,
+        let valid: Vec<u8> = vec![
+            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 206, 85, 236, 230, 248, 129, 167, 109,
+            101, 115, 115, 97, 103, 101, 163, 98, 97, 114,
         ];
+        let mut buf = BytesMut::from(&invalid[..]);
+        buf.extend_from_slice(&valid);
+        let mut decoder = source(
+            r#"
+            decode_error_sample_bytes = 16
+            emit_decode_errors_as_events = true
+            "#,
+        )
+        .decoder(test_peer_addr());
+        assert!(decoder.decode_message(&mut buf).is_err());
+        let failed = decoder.failed_message.clone().unwrap();
+        assert_eq!(&failed[..], &invalid[..]);
+        assert_eq!(
+            decoder.error_sample(&failed),
+            base64::encode(&invalid[..16])
+        );
+        assert_eq!(&buf[..], &valid[..]);
+    }
 
-        let expected = vec![
-            LogEvent::from(btreemap! {
-                "message" => "foo",
-                "tag" => "tag.name",
-                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:04Z").unwrap().into()),
-            }),
-            LogEvent::from(btreemap! {
-                "message" => "bar",
-                "tag" => "tag.name",
-                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:05Z").unwrap().into()),
-            }),
-            LogEvent::from(btreemap! {
-                "message" => "baz",
-                "tag" => "tag.name",
-                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:06Z").unwrap().into()),
-            }),
-        ];
+    #[tokio::test]
+    async fn max_decode_errors_closes_connection() {
+        let (tx, _rx) = Pipeline::new_test();
+        let address = next_addr();
+        let config: FluentConfig = toml::from_str(&format!(
+            r#"
+            address = "{}"
+            max_decode_errors_per_connection = 10
+            "#,
+            address
+        ))
+        .unwrap();
+        tokio::spawn(config.build(SourceContext::new_test(tx)).await.unwrap());
+        wait_for_tcp(address).await;
 
-        let got = decode_all(message).unwrap();
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let invalid = unknown_compression_message();
+        for _ in 0..10 {
+            let _ = stream.write_all(&invalid).await;
+        }
 
-        assert_event_data_eq!(got[0], expected[0]);
-        assert_event_data_eq!(got[1], expected[1]);
-        assert_event_data_eq!(got[2], expected[2]);
+        let mut buf = [0u8; 1];
+        let read = timeout(Duration::from_secs(1), stream.read(&mut buf)).await;
+        assert!(matches!(read, Ok(Ok(0)) | Ok(Err(_))));
     }
 
-    //  TODO
-    #[test]
-    fn decode_compressed_packed_forward_mode() {
-        //[
-        //    "tag.name",
-        //    <packed messages>,
-        //    {"compressed": "gzip"}
-        //]
-        //
-        //With gzip'd packed messages as bin:
-        // [1441588984, {"message": "foo"}]
-        // [1441588985, {"message": "bar"}]
-        // [1441588986, {"message": "baz"}]
-        let message: Vec<u8> = vec![
-            147, 168, 116, 97, 103, 46, 110, 97, 109, 101, 196, 55, 31, 139, 8, 0, 245, 10, 168,
-            96, 0, 3, 155, 116, 46, 244, 205, 179, 31, 141, 203, 115, 83, 139, 139, 19, 211, 83,
-            23, 167, 229, 231, 79, 2, 9, 253, 68, 8, 37, 37, 22, 129, 133, 126, 33, 11, 85, 1, 0,
-            53, 3, 158, 28, 57, 0, 0, 0, 129, 170, 99, 111, 109, 112, 114, 101, 115, 115, 101, 100,
-            164, 103, 122, 105, 112,
-        ];
-
-        let expected = vec![
-            LogEvent::from(btreemap! {
-                "message" => "foo",
-                "tag" => "tag.name",
-                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:04Z").unwrap().into()),
-            }),
-            LogEvent::from(btreemap! {
-                "message" => "bar",
-                "tag" => "tag.name",
-                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:05Z").unwrap().into()),
-            }),
-            LogEvent::from(btreemap! {
-                "message" => "baz",
-                "tag" => "tag.name",
-                "timestamp" => Value::Timestamp(DateTime::parse_from_rfc3339("2015-09-07T01:23:06Z").unwrap().into()),
-            }),
-        ];
+    fn source(config: &str) -> FluentSource {
+        let config: FluentConfig =
+            toml::from_str(&format!("address = \"0.0.0.0:24224\"\n{}", config)).unwrap();
+        FluentSource::new(&config)
+    }
 
-        let got = decode_all(message).unwrap();
+    fn decode_all(message: Vec<u8>) -> Result<Vec<LogEvent>, DecodeError> {
+        decode_all_with(&source(""), message)
+    }
 
-        assert_event_data_eq!(got[0], expected[0]);
-        assert_event_data_eq!(got[1], expected[1]);
-        assert_event_data_eq!(got[2], expected[2]);
+    fn test_peer_addr() -> SocketAddr {
+        "127.0.0.1:51234".parse().unwrap()
     }
 
-    fn decode_all(message: Vec<u8>) -> Result<Vec<LogEvent>, DecodeError> {
+    fn decode_all_with(
+        source: &FluentSource,
+        message: Vec<u8>,
+    ) -> Result<Vec<LogEvent>, DecodeError> {
         let mut buf = BytesMut::from(&message[..]);
 
-        let mut decoder = FluentDecoder::new();
+        let mut decoder = source.decoder(test_peer_addr());
 
         let mut frames = vec![];
         while let Some(frame) = decoder.decode(&mut buf)? {
-            frames.push(LogEvent::from(frame))
+            frames.push(source.log_from_frame(frame))
         }
         Ok(frames)
     }
@@ -781,6 +6908,7 @@ mod integration_tests {
         config::SourceContext,
         docker::docker,
         test_util::{collect_ready, next_addr_for_ip, trace_init, wait_for_tcp},
+        tls::TlsOptions,
         Pipeline,
     };
     use bollard::{
@@ -796,6 +6924,56 @@ mod integration_tests {
 
     #[tokio::test]
     async fn fluentbit() {
+        let (out, address) = source().await;
+        test_fluentbit(out, address, "", &[]).await;
+    }
+
+    #[tokio::test]
+    async fn fluentbit_tls_client_auth() {
+        let (out, address) = source_with(|config| {
+            config.tls = Some(TlsConfig {
+                enabled: Some(true),
+                options: TlsOptions {
+                    crt_file: Some("tests/data/localhost.crt".into()),
+                    key_file: Some("tests/data/localhost.key".into()),
+                    ca_file: Some("tests/data/Client_CA.crt".into()),
+                    verify_certificate: Some(true),
+                    ..Default::default()
+                },
+            });
+            config.tls_client_metadata_key = Some("tls_client".to_owned());
+        })
+        .await;
+
+        let output_options = r#"
+    tls           on
+    tls.verify    on
+    tls.vhost     localhost
+    tls.ca_file   /fluent-bit/etc/Vector_CA.crt
+    tls.crt_file  /fluent-bit/etc/client.crt
+    tls.key_file  /fluent-bit/etc/client.key
+"#;
+        let files = [
+            "tests/data/Vector_CA.crt",
+            "tests/data/client.crt",
+            "tests/data/client.key",
+        ];
+        let events = test_fluentbit(out, address, output_options, &files).await;
+
+        assert_eq!(
+            events[0].as_log()["tls_client.common_name"],
+            "fluent-client".into()
+        );
+    }
+
+    /// Runs fluent-bit forwarding dummy events to `address`, with `output_options` added to its
+    /// forward output and `files` copied to its configuration directory.
+    async fn test_fluentbit(
+        out: mpsc::Receiver<Event>,
+        address: SocketAddr,
+        output_options: &str,
+        files: &[&str],
+    ) -> Vec<Event> {
         trace_init();
 
         let image = "fluent/fluent-bit";
@@ -803,11 +6981,13 @@ mod integration_tests {
 
         let docker = docker(None, None).unwrap();
 
-        let (out, address) = source().await;
-
         pull_image(&docker, image, tag).await;
 
         let dir = tempfile::tempdir().unwrap();
+        for file in files {
+            let path = std::path::Path::new(file);
+            std::fs::copy(path, dir.path().join(path.file_name().unwrap())).unwrap();
+        }
         let mut file = File::create(dir.path().join("fluent-bit.conf")).unwrap();
         write!(
             &mut file,
@@ -825,8 +7005,9 @@ mod integration_tests {
     Match         *
     Host          host.docker.internal
     Port          {}
-"#,
-            address.port()
+{}"#,
+            address.port(),
+            output_options
         )
         .unwrap();
 
@@ -866,6 +7047,7 @@ mod integration_tests {
         assert_eq!(events[0].as_log()["message"], "dummy".into());
         assert!(events[0].as_log().get("timestamp").is_some());
         assert!(events[0].as_log().get("host").is_some());
+        events
     }
 
     #[tokio::test]
@@ -1006,20 +7188,102 @@ mod integration_tests {
     }
 
     async fn source() -> (mpsc::Receiver<Event>, SocketAddr) {
+        source_with(|_| {}).await
+    }
+
+    async fn source_with(
+        configure: impl FnOnce(&mut FluentConfig),
+    ) -> (mpsc::Receiver<Event>, SocketAddr) {
         let (sender, recv) = Pipeline::new_test();
         let address = next_addr_for_ip(std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED));
+        let mut config = FluentConfig {
+            address: FluentAddresses::Single(address.into()),
+            tls: None,
+            keepalive: None,
+            receive_buffer_bytes: None,
+            receive_lowat_bytes: None,
+            send_buffer_bytes: None,
+            nodelay: None,
+            proxy_protocol: false,
+            idle_timeout_secs: None,
+            allowed_peers: None,
+            host_key: None,
+            port_key: None,
+            source_type_key: None,
+            tag_key: None,
+            timestamp_key: None,
+            chunk_key: None,
+            listener_label: None,
+            listener_label_key: None,
+            parse_key_paths: false,
+            key_remapping: None,
+            max_frame_bytes: None,
+            max_decompressed_bytes: None,
+            max_entry_bytes: None,
+            heartbeat_udp: None,
+            tls_client_metadata_key: None,
+            sni_key: None,
+            connection_metadata_key: None,
+            max_bytes_per_second: None,
+            ext_decoders: Vec::new(),
+            reuse_port: false,
+            bind_to_device: None,
+            v6only: None,
+            strict_entry_count: false,
+            frame_read_timeout_secs: None,
+            first_frame_timeout_secs: None,
+            max_connection_lifetime_secs: None,
+            byte_size_key: None,
+            received_timestamp_key: None,
+            max_decode_errors_per_connection: None,
+            pause_accept_on_backpressure: false,
+            non_utf8_key_encoding: FluentKeyEncoding::Hex,
+            max_pending_frames: None,
+            max_record_fields: None,
+            on_oversized: FluentOversizedRecord::Truncate,
+            max_value_depth: None,
+            on_too_deep: FluentTooDeepValue::Truncate,
+            on_key_conflict: FluentKeyConflict::RecordWins,
+            lenient_timestamps: false,
+            global_buffer_bytes: None,
+            batch_mode: FluentBatchMode::PerEntry,
+            force_close_on_shutdown: false,
+            suppress_empty_connections: false,
+            tls_handshake_timeout_secs: None,
+            max_connection_rate: None,
+            max_connections: None,
+            on_connection_limit: TcpOnConnectionLimit::Queue,
+            on_send_error: TcpOnSendError::CloseConnection,
+            max_tag_metric_labels: None,
+            lenient_decompression: false,
+            lenient_message_order: false,
+            lenient_array_records: false,
+            array_record_key: None,
+            fallback_uncompressed: false,
+            resolve_peer_hostname: false,
+            peer_hostname_key: None,
+            framing: FluentFraming::Msgpack,
+            decode_error_sample_bytes: None,
+            stringify_values: false,
+            emit_decode_errors_as_events: false,
+            raw_message_key: None,
+            flatten_records: false,
+            flatten_separator: None,
+            flatten_max_depth: None,
+            sample_rate: None,
+            sample_key: None,
+            timestamp_from_field: None,
+            raw_timestamp_key: None,
+            large_int_as_float: false,
+        };
+        configure(&mut config);
         tokio::spawn(async move {
-            FluentConfig {
-                address: address.into(),
-                tls: None,
-                keepalive: None,
-                receive_buffer_bytes: None,
-            }
-            .build(SourceContext::new_test(sender))
-            .await
-            .unwrap()
-            .await
-            .unwrap()
+            config
+                .build(SourceContext::new_test(sender))
+                .await
+                .unwrap()
+                .await
+                .unwrap()
         });
         wait_for_tcp(address).await;
         (recv, address)
@@ -3,7 +3,7 @@ mod udp;
 #[cfg(unix)]
 mod unix;
 
-use super::util::TcpSource;
+use super::util::{TcpSource, TcpSourceOptions};
 use crate::{
     config::{
         log_schema, DataType, GenerateConfig, Resource, SourceConfig, SourceContext,
@@ -90,6 +90,7 @@ impl SourceConfig for SocketConfig {
                     config.shutdown_timeout_secs(),
                     tls,
                     config.receive_buffer_bytes(),
+                    TcpSourceOptions::default(),
                     cx.shutdown,
                     cx.out,
                 )
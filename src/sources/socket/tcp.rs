@@ -9,6 +9,7 @@ use bytes::Bytes;
 use codec::BytesDelimitedCodec;
 use getset::{CopyGetters, Getters, Setters};
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
 
 #[derive(Deserialize, Serialize, Debug, Clone, Getters, CopyGetters, Setters)]
 pub struct TcpConfig {
@@ -81,11 +82,11 @@ impl TcpSource for RawTcpSource {
     type Error = std::io::Error;
     type Decoder = BytesDelimitedCodec;
 
-    fn decoder(&self) -> Self::Decoder {
+    fn decoder(&self, _peer_addr: SocketAddr) -> Self::Decoder {
         BytesDelimitedCodec::new_with_max_length(b'\n', self.config.max_length)
     }
 
-    fn build_event(&self, frame: Bytes, host: Bytes) -> Option<Event> {
+    fn build_event(&self, frame: Bytes, peer_addr: SocketAddr) -> Option<Event> {
         let byte_size = frame.len();
         let mut event = Event::from(frame);
 
@@ -97,7 +98,9 @@ impl TcpSource for RawTcpSource {
         let host_key = (self.config.host_key.clone())
             .unwrap_or_else(|| crate::config::log_schema().host_key().to_string());
 
-        event.as_mut_log().insert(host_key, host);
+        event
+            .as_mut_log()
+            .insert(host_key, Bytes::from(peer_addr.ip().to_string()));
 
         emit!(SocketEventReceived {
             byte_size,
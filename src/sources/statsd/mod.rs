@@ -4,7 +4,7 @@ use crate::{
     event::Event,
     internal_events::{StatsdEventReceived, StatsdInvalidRecord, StatsdSocketError},
     shutdown::ShutdownSignal,
-    sources::util::{SocketListenAddr, TcpSource},
+    sources::util::{SocketListenAddr, TcpSource, TcpSourceOptions},
     tcp::TcpKeepaliveConfig,
     tls::{MaybeTlsSettings, TlsConfig},
     Pipeline,
@@ -106,6 +106,7 @@ impl SourceConfig for StatsdConfig {
                     config.shutdown_timeout_secs,
                     tls,
                     config.receive_buffer_bytes,
+                    TcpSourceOptions::default(),
                     cx.shutdown,
                     cx.out,
                 )
@@ -200,11 +201,11 @@ impl TcpSource for StatsdTcpSource {
     type Error = std::io::Error;
     type Decoder = BytesDelimitedCodec;
 
-    fn decoder(&self) -> Self::Decoder {
+    fn decoder(&self, _peer_addr: SocketAddr) -> Self::Decoder {
         BytesDelimitedCodec::new(b'\n')
     }
 
-    fn build_event(&self, line: Bytes, _host: Bytes) -> Option<Event> {
+    fn build_event(&self, line: Bytes, _peer_addr: SocketAddr) -> Option<Event> {
         let line = String::from_utf8_lossy(line.as_ref());
         parse_event(&line)
     }
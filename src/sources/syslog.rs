@@ -1,4 +1,4 @@
-use super::util::{SocketListenAddr, TcpSource};
+use super::util::{SocketListenAddr, TcpSource, TcpSourceOptions};
 #[cfg(unix)]
 use crate::sources::util::build_unix_stream_source;
 use crate::udp;
@@ -120,6 +120,7 @@ impl SourceConfig for SyslogConfig {
                     shutdown_secs,
                     tls,
                     receive_buffer_bytes,
+                    TcpSourceOptions::default(),
                     cx.shutdown,
                     cx.out,
                 )
@@ -175,11 +176,12 @@ impl TcpSource for SyslogTcpSource {
     type Error = LinesCodecError;
     type Decoder = SyslogDecoder;
 
-    fn decoder(&self) -> Self::Decoder {
+    fn decoder(&self, _peer_addr: SocketAddr) -> Self::Decoder {
         SyslogDecoder::new(self.max_length)
     }
 
-    fn build_event(&self, frame: String, host: Bytes) -> Option<Event> {
+    fn build_event(&self, frame: String, peer_addr: SocketAddr) -> Option<Event> {
+        let host = Bytes::from(peer_addr.ip().to_string());
         Some(event_from_str(&self.host_key, Some(host), &frame))
     }
 }
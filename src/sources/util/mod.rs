@@ -24,7 +24,11 @@ pub(crate) use self::http::{ErrorMessage, HttpSource, HttpSourceAuthConfig};
 pub use encoding_config::EncodingConfig;
 pub use multiline_config::MultilineConfig;
 #[cfg(all(feature = "sources-utils-tls", feature = "listenfd"))]
-pub use tcp::{IsErrorFatal as TcpIsErrorFatal, SocketListenAddr, TcpSource};
+pub use tcp::{
+    take_systemd_udp_socket, BufferBudget as TcpBufferBudget, IsErrorFatal as TcpIsErrorFatal,
+    OnConnectionLimit as TcpOnConnectionLimit, OnSendError as TcpOnSendError, SocketListenAddr,
+    TcpSource, TcpSourceOptions,
+};
 #[cfg(all(unix, feature = "sources-socket",))]
 pub use unix_datagram::build_unix_datagram_source;
 #[cfg(all(unix, feature = "sources-utils-unix",))]
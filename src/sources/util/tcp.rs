@@ -1,39 +1,77 @@
 use crate::{
     config::Resource,
-    event::Event,
-    internal_events::{ConnectionOpen, OpenGauge, TcpSocketConnectionError},
+    event::{Event, Value},
+    internal_events::{
+        ConnectionOpen, OpenGauge, OpenToken, TcpAcceptPaused, TcpBytesReceived,
+        TcpConnectionDuration, TcpConnectionLifetimeExceeded, TcpConnectionLimitReached,
+        TcpConnectionRateLimited, TcpConnectionRejected, TcpConnectionReset, TcpDrainTimedOut,
+        TcpEventReceived, TcpHandshakeTimedOut, TcpReceiveBufferSize, TcpSendFailed,
+        TcpSocketAcceptError, TcpSocketConnectionError, TcpSocketConnectionEstablished,
+    },
     shutdown::ShutdownSignal,
     tcp::TcpKeepaliveConfig,
     tls::{MaybeTlsIncomingStream, MaybeTlsListener, MaybeTlsSettings},
     Pipeline,
 };
-use bytes::Bytes;
-use futures::{future::BoxFuture, stream, FutureExt, Sink, SinkExt, StreamExt, TryFutureExt};
+use bytes::BytesMut;
+use cidr_utils::cidr::IpCidr;
+use futures::{
+    future::BoxFuture, stream, FutureExt, Sink, SinkExt, Stream, StreamExt, TryFutureExt,
+};
+use lazy_static::lazy_static;
 use listenfd::ListenFd;
 use serde::{de, Deserialize, Deserializer, Serialize};
 use socket2::SockRef;
-use std::{fmt, future::ready, io, mem::drop, net::SocketAddr, task::Poll, time::Duration};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::{ready, Future},
+    io,
+    mem::drop,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
 use tokio::{
-    net::{TcpListener, TcpStream},
-    time::sleep,
+    io::{AsyncRead, ReadBuf},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::{sleep, Instant, Sleep},
 };
 use tokio_util::codec::{Decoder, FramedRead, LinesCodecError};
 use tracing_futures::Instrument;
 
 async fn make_listener(
     addr: SocketListenAddr,
-    mut listenfd: ListenFd,
+    listenfd: &Mutex<ListenFd>,
     tls: &MaybeTlsSettings,
+    reuse_port: bool,
+    bind_to_device: Option<&str>,
+    v6only: Option<bool>,
 ) -> Option<MaybeTlsListener> {
     match addr {
-        SocketListenAddr::SocketAddr(addr) => match tls.bind(&addr).await {
-            Ok(listener) => Some(listener),
-            Err(error) => {
-                error!(message = "Failed to bind to listener socket.", %error);
-                None
+        SocketListenAddr::SocketAddr(addr) => {
+            let listener = if reuse_port || bind_to_device.is_some() || v6only.is_some() {
+                tls.bind_with_options(&addr, reuse_port, bind_to_device, v6only)
+            } else {
+                tls.bind(&addr).await
+            };
+            match listener {
+                Ok(listener) => Some(listener),
+                Err(error) => {
+                    error!(message = "Failed to bind to listener socket.", %error);
+                    None
+                }
             }
-        },
-        SocketListenAddr::SystemdFd(offset) => match listenfd.take_tcp_listener(offset) {
+        }
+        // The socket is set up by systemd, so `reuse_port`, `bind_to_device` and `v6only` don't
+        // apply.
+        SocketListenAddr::SystemdFd(offset) => match take_tcp_listener(listenfd, offset) {
             Ok(Some(listener)) => match TcpListener::from_std(listener) {
                 Ok(listener) => Some(listener.into()),
                 Err(error) => {
@@ -52,20 +90,191 @@ async fn make_listener(
         },
     }
 }
+
+fn take_tcp_listener(
+    listenfd: &Mutex<ListenFd>,
+    offset: usize,
+) -> io::Result<Option<std::net::TcpListener>> {
+    listenfd.lock().unwrap().take_tcp_listener(offset)
+}
+
+/// Takes the UDP socket passed by systemd at `offset`, for sources that listen on UDP next to
+/// TCP, such as for heartbeats.
+pub fn take_systemd_udp_socket(offset: usize) -> crate::Result<UdpSocket> {
+    let socket = take_udp_socket(&SYSTEMD_FDS, offset)?;
+    socket.set_nonblocking(true)?;
+    Ok(UdpSocket::from_std(socket)?)
+}
+
+fn take_udp_socket(
+    listenfd: &Mutex<ListenFd>,
+    offset: usize,
+) -> crate::Result<std::net::UdpSocket> {
+    let addr = SocketListenAddr::SystemdFd(offset);
+    let taken = listenfd.lock().unwrap().take_udp_socket(offset);
+    match taken {
+        Ok(Some(socket)) => Ok(socket),
+        Ok(None) => Err(format!("{} isn't open or is already taken.", addr).into()),
+        Err(error) => Err(format!("{} isn't a UDP socket: {}.", addr, error).into()),
+    }
+}
+
+lazy_static! {
+    /// The sockets passed by systemd. They're only read from the environment once, so all
+    /// listeners take theirs from here.
+    static ref SYSTEMD_FDS: Mutex<ListenFd> = Mutex::new(ListenFd::from_env());
+}
+
+/// How long clients get to complete the TLS handshake, and send the PROXY protocol header if
+/// there is one, when `tls_handshake_timeout_secs` isn't configured.
+const DEFAULT_TLS_HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+/// How many frames a connection hands out in a row, without having to wait for more data, before
+/// it yields to other tasks, so that a connection with a lot of buffered frames doesn't starve
+/// the rest of the runtime.
+const FRAMES_PER_POLL: usize = 128;
+/// Identifies connections in logs, as a peer may have many connections open at once. Unique
+/// across all listeners of the process.
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// What a connection does when sending its events downstream fails.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnSendError {
+    /// Stop reading from the connection and close it.
+    CloseConnection,
+    /// Drop events the sink rejects and carry on reading from the connection. A sink that fails
+    /// altogether, such as a pipeline that's shut down, still closes the connection.
+    Drop,
+}
+
+impl Default for OnSendError {
+    fn default() -> Self {
+        Self::CloseConnection
+    }
+}
+
+/// What happens to new connections once `max_connections` are open.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnConnectionLimit {
+    /// Stop accepting until a connection closes, leaving new ones queued by the listener.
+    Queue,
+    /// Accept and close them right away, so that clients back off rather than wait.
+    Reject,
+}
+
+impl Default for OnConnectionLimit {
+    fn default() -> Self {
+        Self::Queue
+    }
+}
+
+/// Holds the places of the open connections when `max_connections` is set.
+#[derive(Clone)]
+struct ConnectionLimit {
+    semaphore: Arc<Semaphore>,
+    max_connections: u32,
+    on_limit: OnConnectionLimit,
+}
+
+impl ConnectionLimit {
+    fn new(max_connections: u32, on_limit: OnConnectionLimit) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_connections as usize)),
+            max_connections,
+            on_limit,
+        }
+    }
+
+    /// A place for a connection from `peer_addr`, which it holds until it's dropped, or `None` if
+    /// the connection is rejected.
+    async fn acquire(&self, peer_addr: SocketAddr) -> Option<OwnedSemaphorePermit> {
+        match self.on_limit {
+            // The semaphore is never closed.
+            OnConnectionLimit::Queue => self.semaphore.clone().acquire_owned().await.ok(),
+            OnConnectionLimit::Reject => match self.semaphore.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    emit!(TcpConnectionLimitReached {
+                        peer_addr,
+                        max_connections: self.max_connections,
+                    });
+                    None
+                }
+            },
+        }
+    }
+}
+
 pub trait IsErrorFatal {
     fn is_error_fatal(&self) -> bool;
+
+    /// Whether the error is the peer going away abruptly, as clients routinely do when they
+    /// restart, rather than something worth a warning.
+    fn is_disconnect(&self) -> bool {
+        false
+    }
 }
 
 impl IsErrorFatal for LinesCodecError {
     fn is_error_fatal(&self) -> bool {
         false
     }
+
+    fn is_disconnect(&self) -> bool {
+        match self {
+            LinesCodecError::Io(error) => error.is_disconnect(),
+            LinesCodecError::MaxLineLengthExceeded => false,
+        }
+    }
 }
 
 impl IsErrorFatal for std::io::Error {
     fn is_error_fatal(&self) -> bool {
         true
     }
+
+    fn is_disconnect(&self) -> bool {
+        matches!(
+            self.kind(),
+            io::ErrorKind::ConnectionReset
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::ConnectionAborted
+        )
+    }
+}
+
+/// How a `TcpSource` listens for and handles its connections, beyond its address, keepalive,
+/// shutdown timeout, TLS and receive buffer size. The defaults leave sockets as the operating
+/// system sets them up, without any limits or metadata added to events.
+#[derive(Clone, Debug, Default)]
+pub struct TcpSourceOptions {
+    pub receive_lowat_bytes: Option<usize>,
+    pub send_buffer_bytes: Option<usize>,
+    pub nodelay: Option<bool>,
+    pub proxy_protocol: bool,
+    pub idle_timeout_secs: Option<u64>,
+    pub allowed_peers: Option<Vec<IpCidr>>,
+    pub tls_client_metadata_key: Option<String>,
+    pub sni_key: Option<String>,
+    pub connection_metadata_key: Option<String>,
+    pub max_bytes_per_second: Option<u64>,
+    pub reuse_port: bool,
+    pub bind_to_device: Option<String>,
+    pub v6only: Option<bool>,
+    pub frame_read_timeout_secs: Option<u64>,
+    pub first_frame_timeout_secs: Option<u64>,
+    pub max_connection_lifetime_secs: Option<u64>,
+    pub pause_accept_on_backpressure: bool,
+    /// Can be shared by a source's listeners on several addresses to cap what they buffer together.
+    pub buffer_budget: Option<Arc<BufferBudget>>,
+    pub force_close_on_shutdown: bool,
+    pub suppress_empty_connections: bool,
+    pub tls_handshake_timeout_secs: Option<u64>,
+    pub max_connection_rate: Option<u32>,
+    pub max_connections: Option<u32>,
+    pub on_connection_limit: OnConnectionLimit,
+    pub on_send_error: OnSendError,
 }
 
 pub trait TcpSource: Clone + Send + Sync + 'static
@@ -77,9 +286,23 @@ where
     type Error: From<io::Error> + IsErrorFatal + std::fmt::Debug + std::fmt::Display + Send;
     type Decoder: Decoder<Error = Self::Error> + Send + 'static + Send;
 
-    fn decoder(&self) -> Self::Decoder;
+    fn decoder(&self, peer_addr: SocketAddr) -> Self::Decoder;
+
+    fn build_event(
+        &self,
+        frame: <Self::Decoder as Decoder>::Item,
+        peer_addr: SocketAddr,
+    ) -> Option<Event>;
 
-    fn build_event(&self, frame: <Self::Decoder as Decoder>::Item, host: Bytes) -> Option<Event>;
+    /// Awaited once a connection is accepted, before anything is read from it, for sources that
+    /// look something up about the peer to build its events with.
+    fn connection_accepted(&self, _peer_addr: SocketAddr) -> BoxFuture<'static, ()> {
+        ready(()).boxed()
+    }
+
+    /// Called whenever the number of bytes a connection has read but not decoded yet changes,
+    /// including back to zero when the connection is closed.
+    fn buffered_bytes_changed(&self, _previous: usize, _current: usize) {}
 
     fn run(
         self,
@@ -88,17 +311,41 @@ where
         shutdown_timeout_secs: u64,
         tls: MaybeTlsSettings,
         receive_buffer_bytes: Option<usize>,
+        options: TcpSourceOptions,
         shutdown_signal: ShutdownSignal,
         out: Pipeline,
     ) -> crate::Result<crate::sources::Source> {
         let out = out.sink_map_err(|error| error!(message = "Error sending event.", %error));
+        let backpressure = if options.pause_accept_on_backpressure {
+            Some(Arc::new(Backpressure::default()))
+        } else {
+            None
+        };
 
-        let listenfd = ListenFd::from_env();
+        let handshake_timeout = Duration::from_secs(
+            options
+                .tls_handshake_timeout_secs
+                .unwrap_or(DEFAULT_TLS_HANDSHAKE_TIMEOUT_SECS),
+        );
+        let connection_limit = options.max_connections.map(|max_connections| {
+            ConnectionLimit::new(max_connections, options.on_connection_limit)
+        });
+        // Shared by all connections.
+        let options = Arc::new(options);
 
         Ok(Box::pin(async move {
-            let listener = match make_listener(addr, listenfd, &tls).await {
+            let listener = match make_listener(
+                addr,
+                &SYSTEMD_FDS,
+                &tls,
+                options.reuse_port,
+                options.bind_to_device.as_deref(),
+                options.v6only,
+            )
+            .await
+            {
                 None => return Err(()),
-                Some(listener) => listener,
+                Some(listener) => listener.with_proxy_protocol(options.proxy_protocol),
             };
 
             info!(
@@ -119,32 +366,62 @@ where
             let connection_gauge = OpenGauge::new();
             let shutdown_clone = shutdown_signal.clone();
 
-            listener
-                .accept_stream()
-                .take_until(shutdown_clone)
+            let mut connections = Box::pin(
+                PausableAccept::new(
+                    listener.accept_stream(),
+                    backpressure.clone(),
+                    options
+                        .max_connection_rate
+                        .map(|rate| RateLimiter::new(rate.into())),
+                )
+                .take_until(shutdown_clone),
+            );
+            connections
+                .as_mut()
                 .for_each(move |connection| {
                     let shutdown_signal = shutdown_signal.clone();
                     let tripwire = tripwire.clone();
                     let source = self.clone();
-                    let out = out.clone();
+                    let out = SendErrorSink::new(
+                        BackpressureSink::new(out.clone(), backpressure.clone()),
+                        options.on_send_error,
+                    );
                     let connection_gauge = connection_gauge.clone();
+                    let options = Arc::clone(&options);
+                    let connection_limit = connection_limit.clone();
 
                     async move {
-                        let socket = match connection {
-                            Ok(socket) => socket,
-                            Err(error) => {
-                                error!(
-                                    message = "Failed to accept socket.",
-                                    %error
-                                );
-                                return;
+                        let socket = match accepted(connection) {
+                            Some(socket) => socket,
+                            None => return,
+                        };
+                        // Waiting for a place holds up the accept loop, so with `Queue` further
+                        // connections wait in the listen backlog. Shutting down doesn't wait for
+                        // a place to free up.
+                        let permit = match &connection_limit {
+                            Some(limit) => {
+                                let permit = tokio::select! {
+                                    permit = limit.acquire(socket.peer_addr()) => permit,
+                                    _ = shutdown_signal.clone() => None,
+                                };
+                                match permit {
+                                    Some(permit) => Some(permit),
+                                    None => return,
+                                }
                             }
+                            None => None,
                         };
 
                         let peer_addr = socket.peer_addr().ip().to_string();
-                        let span = info_span!("connection", %peer_addr);
-                        let host = Bytes::from(peer_addr);
+                        let connection_id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+                        let span = info_span!("connection", %peer_addr, connection_id);
 
+                        // Connections that are still handing out their frames this long after the
+                        // tripwire reset them are closed, in case the sink has stalled.
+                        let drain_deadline = tripwire
+                            .clone()
+                            .then(move |_| sleep(Duration::from_secs(shutdown_timeout_secs)))
+                            .boxed();
                         let tripwire = tripwire
                             .map(move |_| {
                                 info!(
@@ -155,43 +432,116 @@ where
                             .boxed();
 
                         span.in_scope(|| {
-                            let peer_addr = socket.peer_addr();
-                            debug!(message = "Accepted a new connection.", peer_addr = %peer_addr);
-
-                            let open_token =
-                                connection_gauge.open(|count| emit!(ConnectionOpen { count }));
+                            let mut stats =
+                                ConnectionStats::new(socket.peer_addr(), connection_gauge);
+                            // Otherwise only once the client has sent something, so that health
+                            // checks that connect and close right away aren't counted.
+                            if !options.suppress_empty_connections {
+                                stats.established();
+                            }
 
                             let fut = handle_stream(
                                 shutdown_signal,
                                 socket,
                                 keepalive,
                                 receive_buffer_bytes,
+                                options,
+                                handshake_timeout,
+                                stats,
                                 source,
                                 tripwire,
-                                host,
+                                drain_deadline,
                                 out,
                             );
 
-                            tokio::spawn(
-                                fut.map(move |()| drop(open_token)).instrument(span.clone()),
-                            );
+                            // Connections reset by the tripwire end here too, once their buffered
+                            // frames are drained.
+                            let fut = fut.map(move |()| drop(permit));
+                            tokio::spawn(fut.instrument(span.clone()));
                         });
                     }
                 })
-                .map(Ok)
-                .await
+                .await;
+
+            // The accept stream owns the listener, so dropping it as soon as the shutdown signal
+            // resolves stops accepting, while open connections drain on their own.
+            drop(connections);
+            info!(message = "No longer accepting connections.");
+            Ok(())
         }))
     }
 }
 
+/// Counts connections that failed to be accepted. Accepted ones are counted once they're
+/// established, see `ConnectionStats`.
+fn accepted(
+    connection: crate::tls::Result<MaybeTlsIncomingStream<TcpStream>>,
+) -> Option<MaybeTlsIncomingStream<TcpStream>> {
+    match connection {
+        Ok(socket) => Some(socket),
+        Err(error) => {
+            emit!(TcpSocketAcceptError { error });
+            None
+        }
+    }
+}
+
+/// Counts a connection as established, holds its place in the open connections gauge and records
+/// its duration once it's dropped, if it was ever established.
+struct ConnectionStats {
+    peer_addr: SocketAddr,
+    gauge: OpenGauge,
+    open: Option<(OpenToken<fn(usize)>, Instant)>,
+}
+
+impl ConnectionStats {
+    fn new(peer_addr: SocketAddr, gauge: OpenGauge) -> Self {
+        Self {
+            peer_addr,
+            gauge,
+            open: None,
+        }
+    }
+
+    fn established(&mut self) {
+        if self.open.is_none() {
+            debug!(message = "Accepted a new connection.", peer_addr = %self.peer_addr);
+            emit!(TcpSocketConnectionEstablished {
+                peer_addr: Some(self.peer_addr),
+            });
+            let emitter: fn(usize) = |count| emit!(ConnectionOpen { count });
+            self.open = Some((self.gauge.clone().open(emitter), Instant::now()));
+        }
+    }
+
+    fn is_established(&self) -> bool {
+        self.open.is_some()
+    }
+}
+
+impl Drop for ConnectionStats {
+    fn drop(&mut self) {
+        if let Some((token, opened_at)) = self.open.take() {
+            drop(token);
+            emit!(TcpConnectionDuration {
+                peer_addr: self.peer_addr,
+                duration: opened_at.elapsed(),
+            });
+        }
+    }
+}
+
 async fn handle_stream<T>(
     mut shutdown_signal: ShutdownSignal,
     mut socket: MaybeTlsIncomingStream<TcpStream>,
     keepalive: Option<TcpKeepaliveConfig>,
     receive_buffer_bytes: Option<usize>,
+    options: Arc<TcpSourceOptions>,
+    handshake_timeout: Duration,
+    mut stats: ConnectionStats,
     source: T,
-    tripwire: BoxFuture<'static, ()>,
-    host: Bytes,
+    mut tripwire: BoxFuture<'static, ()>,
+    drain_deadline: BoxFuture<'static, ()>,
     out: impl Sink<Event> + Send + 'static,
 ) where
     <<T as TcpSource>::Decoder as tokio_util::codec::Decoder>::Item: std::marker::Send,
@@ -204,11 +554,33 @@ async fn handle_stream<T>(
                 return;
             }
         },
+        _ = sleep(handshake_timeout) => {
+            emit!(TcpHandshakeTimedOut {
+                peer_addr: socket.peer_addr(),
+                timeout: handshake_timeout,
+            });
+            return;
+        },
         _ = &mut shutdown_signal => {
             return;
         }
     };
 
+    // Only known after the handshake if the client address comes from a PROXY protocol header.
+    let peer_addr = socket.peer_addr();
+
+    if let Some(allowed_peers) = &options.allowed_peers {
+        if !allowed_peers
+            .iter()
+            .any(|cidr| cidr.contains(peer_addr.ip()))
+        {
+            emit!(TcpConnectionRejected { peer_addr });
+            return;
+        }
+    }
+
+    source.connection_accepted(peer_addr).await;
+
     if let Some(keepalive) = keepalive {
         if let Err(error) = socket.set_keepalive(keepalive) {
             warn!(message = "Failed configuring TCP keepalive.", %error);
@@ -216,22 +588,127 @@ async fn handle_stream<T>(
     }
 
     if let Some(receive_buffer_bytes) = receive_buffer_bytes {
-        if let Err(error) = socket.set_receive_buffer_bytes(receive_buffer_bytes) {
-            warn!(message = "Failed configuring receive buffer size on TCP socket.", %error);
+        match socket
+            .set_receive_buffer_bytes(receive_buffer_bytes)
+            .and_then(|()| socket.receive_buffer_bytes())
+        {
+            Ok(effective) => emit!(TcpReceiveBufferSize {
+                requested: receive_buffer_bytes,
+                effective,
+            }),
+            Err(error) => {
+                warn!(message = "Failed configuring receive buffer size on TCP socket.", %error)
+            }
+        }
+    }
+
+    if let Some(receive_lowat_bytes) = options.receive_lowat_bytes {
+        if let Err(error) = socket.set_receive_lowat_bytes(receive_lowat_bytes) {
+            warn!(message = "Failed configuring SO_RCVLOWAT on TCP socket.", %error);
+        }
+    }
+
+    if let Some(send_buffer_bytes) = options.send_buffer_bytes {
+        if let Err(error) = socket.set_send_buffer_bytes(send_buffer_bytes) {
+            warn!(message = "Failed configuring send buffer size on TCP socket.", %error);
+        }
+    }
+
+    if let Some(nodelay) = options.nodelay {
+        if let Err(error) = socket.set_nodelay(nodelay) {
+            warn!(message = "Failed configuring TCP_NODELAY on TCP socket.", %error);
         }
     }
 
+    let tls_client_metadata = options.tls_client_metadata_key.clone().and_then(|key| {
+        socket
+            .peer_certificate()
+            .map(|certificate| (key, Value::from(certificate)))
+    });
+    let server_name = options
+        .sni_key
+        .clone()
+        .and_then(|key| socket.server_name().map(|name| (key, Value::from(name))));
+    let connection_metadata = options
+        .connection_metadata_key
+        .clone()
+        .map(|key| (key, Value::from(socket.connection_metadata())));
+
     let mut shutdown_token = None;
-    let mut reader = FramedRead::new(socket, source.decoder());
+    let mut drain_buffer = None;
+    let mut buffered_bytes = BufferedBytes {
+        source: source.clone(),
+        bytes: 0,
+    };
+    let mut reader = FramedRead::new(
+        BudgetedRead::new(socket, options.buffer_budget.clone()),
+        ByteCountingDecoder(source.decoder(peer_addr)),
+    );
+    let mut idle_timer = options.idle_timeout_secs.map(|secs| {
+        let timeout = Duration::from_secs(secs);
+        (timeout, Box::pin(sleep(timeout)))
+    });
+    let mut rate_limiter = options.max_bytes_per_second.map(RateLimiter::new);
+    // Started once part of a frame is buffered, and stopped when a frame is complete.
+    let mut frame_timer = options.frame_read_timeout_secs.map(|secs| {
+        let timeout = Duration::from_secs(secs);
+        (timeout, Box::pin(sleep(timeout)), false)
+    });
+    // Started once the handshake is done, and stopped by the first frame. Until then it takes the
+    // place of the idle and frame read timeouts.
+    let mut first_frame_timer = options.first_frame_timeout_secs.map(|secs| {
+        let timeout = Duration::from_secs(secs);
+        (timeout, Box::pin(sleep(timeout)))
+    });
+    let mut lifetime_timer = options.max_connection_lifetime_secs.map(|secs| {
+        let lifetime = Duration::from_secs(secs);
+        (lifetime, Box::pin(sleep(lifetime)))
+    });
+    let mut consecutive_frames = 0;
+
+    let forward = stream::poll_fn(|cx| {
+        if let Some((lifetime, timer)) = lifetime_timer.as_mut() {
+            if drain_buffer.is_none() && timer.poll_unpin(cx).is_ready() {
+                emit!(TcpConnectionLifetimeExceeded {
+                    peer_addr,
+                    lifetime: *lifetime,
+                });
+                // Closed like on shutdown, once the frames that were already received are handed
+                // out, so that the client reconnects.
+                drain_buffer = Some(std::mem::take(reader.read_buffer_mut()));
+            }
+        }
+        if drain_buffer.is_none() && tripwire.poll_unpin(cx).is_ready() {
+            if options.force_close_on_shutdown {
+                // Close both directions right away, dropping whatever is still buffered.
+                if let Some(stream) = reader.get_ref().inner.get_ref() {
+                    if let Err(error) = SockRef::from(stream).shutdown(std::net::Shutdown::Both) {
+                        warn!(message = "Failed to close connection.", %error);
+                    }
+                }
+                info!(
+                    message = "Forcibly closed connection still open after shutdown timeout.",
+                    dropped_bytes = reader.read_buffer().len(),
+                );
+                return Poll::Ready(None);
+            }
+            // Stop reading from the connection, but still hand out the frames that were already
+            // received, including any the decoder has decoded but not returned yet.
+            drain_buffer = Some(std::mem::take(reader.read_buffer_mut()));
+        }
+        if let Some(buffer) = drain_buffer.as_mut() {
+            let frame = reader.decoder_mut().decode(buffer).transpose();
+            buffered_bytes.set(buffer.len());
+            return Poll::Ready(frame);
+        }
 
-    stream::poll_fn(|cx| {
         if shutdown_token.is_none() {
             match shutdown_signal.poll_unpin(cx) {
                 Poll::Ready(token) => {
                     debug!("Start graceful shutdown.");
                     // Close our write part of TCP socket to signal the other side
                     // that it should stop writing and close the channel.
-                    let socket = reader.get_ref().get_ref();
+                    let socket = reader.get_ref().inner.get_ref();
                     if let Some(stream) = socket {
                         let socket = SockRef::from(stream);
                         if let Err(error) = socket.shutdown(std::net::Shutdown::Write) {
@@ -249,105 +726,2086 @@ async fn handle_stream<T>(
             }
         }
 
-        reader.poll_next_unpin(cx)
+        if consecutive_frames >= FRAMES_PER_POLL {
+            consecutive_frames = 0;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+
+        if let Some(rate_limiter) = rate_limiter.as_mut() {
+            // Leave the data in the socket, so the client is slowed down by TCP backpressure.
+            if rate_limiter.poll_ready(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+
+        let frame = reader.poll_next_unpin(cx);
+        // Bytes that were decoded no longer count against the buffer budget.
+        let buffered = reader.read_buffer().len();
+        reader.get_mut().release_to(buffered);
+        buffered_bytes.set(buffered);
+        let received = match &frame {
+            Poll::Ready(Some(Ok(_))) => true,
+            Poll::Ready(Some(Err(error))) => !error.is_disconnect(),
+            _ => buffered > 0,
+        };
+        if received {
+            stats.established();
+        }
+        match frame {
+            Poll::Ready(Some(_)) => consecutive_frames += 1,
+            _ => consecutive_frames = 0,
+        }
+
+        if let (Some(rate_limiter), Poll::Ready(Some(Ok((_, byte_size))))) =
+            (rate_limiter.as_mut(), &frame)
+        {
+            rate_limiter.consume(*byte_size);
+        }
+
+        if let Some((timeout, timer)) = first_frame_timer.as_mut() {
+            match frame {
+                Poll::Ready(Some(Ok(_))) => first_frame_timer = None,
+                Poll::Pending => {
+                    if timer.poll_unpin(cx).is_ready() {
+                        warn!(
+                            message = "Closing connection that didn't send a frame in time.",
+                            timeout_secs = ?timeout.as_secs(),
+                        );
+                        return Poll::Ready(None);
+                    }
+                    return Poll::Pending;
+                }
+                _ => {}
+            }
+        }
+
+        if let Some((timeout, timer, started)) = frame_timer.as_mut() {
+            match frame {
+                Poll::Pending if !reader.read_buffer().is_empty() => {
+                    if !*started {
+                        timer.as_mut().reset(Instant::now() + *timeout);
+                        *started = true;
+                    }
+                    if timer.poll_unpin(cx).is_ready() {
+                        warn!(
+                            message = "Closing connection that is sending a frame too slowly, possibly a slowloris attack.",
+                            timeout_secs = ?timeout.as_secs(),
+                        );
+                        return Poll::Ready(None);
+                    }
+                }
+                _ => *started = false,
+            }
+        }
+
+        if let Some((timeout, timer)) = idle_timer.as_mut() {
+            match frame {
+                Poll::Ready(Some(Ok(_))) => timer.as_mut().reset(Instant::now() + *timeout),
+                Poll::Pending => {
+                    if timer.poll_unpin(cx).is_ready() {
+                        warn!(message = "Closing idle connection.", timeout_secs = ?timeout.as_secs());
+                        return Poll::Ready(None);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        frame
     })
-    .take_until(tripwire)
     .take_while(move |frame| ready(
         match frame {
             Ok(_) => true,
-            Err(err) => {
-                !<<T as TcpSource>::Error as IsErrorFatal>::is_error_fatal(err)
+            Err(error) => {
+                let fatal = error.is_error_fatal();
+                if fatal {
+                    if error.is_disconnect() {
+                        emit!(TcpConnectionReset { error, peer_addr });
+                    } else {
+                        warn!(message = "Failed to read data from TCP source.", %error);
+                    }
+                }
+                !fatal
             }
         }
     ))
     .filter_map(move |frame| ready(match frame {
-        Ok(frame) => {
-            let host = host.clone();
-            source.build_event(frame, host).map(Ok)
+        Ok((frame, byte_size)) => {
+            emit!(TcpBytesReceived { byte_size, peer_addr });
+            let mut event = source.build_event(frame, peer_addr);
+            if let (Some(Event::Log(log)), Some((key, metadata))) = (event.as_mut(), &tls_client_metadata) {
+                log.insert(key, metadata.clone());
+            }
+            if let (Some(Event::Log(log)), Some((key, name))) = (event.as_mut(), &server_name) {
+                log.insert(key, name.clone());
+            }
+            if let (Some(Event::Log(log)), Some((key, metadata))) = (event.as_mut(), &connection_metadata) {
+                log.insert(key, metadata.clone());
+            }
+            if event.is_some() {
+                emit!(TcpEventReceived { peer_addr });
+            }
+            event.map(Ok)
         }
         Err(error) => {
             warn!(message = "Failed to read data from TCP source.", %error);
             None
         }
     }))
-    .forward(out)
-    .map_err(|_| warn!(message = "Error received while processing TCP source."))
-    .map(|_| debug!("Connection closed."))
-    .await
+    .forward(out);
+
+    let drain_timed_out = tokio::select! {
+        _ = forward => false,
+        _ = drain_deadline => true,
+    };
+    if drain_timed_out {
+        // Whatever wasn't handed out yet is dropped, including the frames the sink didn't take
+        // before the tripwire, so they are decoded only to count them.
+        let buffer = drain_buffer.get_or_insert_with(|| std::mem::take(reader.read_buffer_mut()));
+        let mut dropped_frames = 0;
+        while let Ok(Some(_)) = reader.decoder_mut().decode(buffer) {
+            dropped_frames += 1;
+        }
+        emit!(TcpDrainTimedOut {
+            peer_addr,
+            dropped_frames,
+        });
+    }
+
+    if stats.is_established() {
+        debug!("Connection closed.");
+    }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
-#[serde(untagged)]
-pub enum SocketListenAddr {
-    SocketAddr(SocketAddr),
-    #[serde(deserialize_with = "parse_systemd_fd")]
-    SystemdFd(usize),
+/// Wraps a source's decoder to also yield how many bytes of the stream each frame consumed.
+struct ByteCountingDecoder<D>(D);
+
+impl<D: Decoder> Decoder for ByteCountingDecoder<D> {
+    type Item = (D::Item, usize);
+    type Error = D::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let len = src.len();
+        let frame = self.0.decode(src)?;
+        Ok(frame.map(|frame| (frame, len - src.len())))
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let len = src.len();
+        let frame = self.0.decode_eof(src)?;
+        Ok(frame.map(|frame| (frame, len - src.len())))
+    }
 }
 
-impl fmt::Display for SocketListenAddr {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Self::SocketAddr(ref addr) => addr.fmt(f),
-            Self::SystemdFd(offset) => write!(f, "systemd socket #{}", offset),
+/// Reports a connection's bytes that are read but not decoded yet to its source, and that there
+/// are none left once the connection is dropped.
+struct BufferedBytes<T: TcpSource>
+where
+    <<T as TcpSource>::Decoder as tokio_util::codec::Decoder>::Item: std::marker::Send,
+{
+    source: T,
+    bytes: usize,
+}
+
+impl<T: TcpSource> BufferedBytes<T>
+where
+    <<T as TcpSource>::Decoder as tokio_util::codec::Decoder>::Item: std::marker::Send,
+{
+    fn set(&mut self, bytes: usize) {
+        if bytes != self.bytes {
+            self.source.buffered_bytes_changed(self.bytes, bytes);
+            self.bytes = bytes;
         }
     }
 }
 
-impl From<SocketAddr> for SocketListenAddr {
-    fn from(addr: SocketAddr) -> Self {
-        Self::SocketAddr(addr)
+impl<T: TcpSource> Drop for BufferedBytes<T>
+where
+    <<T as TcpSource>::Decoder as tokio_util::codec::Decoder>::Item: std::marker::Send,
+{
+    fn drop(&mut self) {
+        self.set(0);
     }
 }
 
-impl From<SocketListenAddr> for Resource {
-    fn from(addr: SocketListenAddr) -> Resource {
-        match addr {
-            SocketListenAddr::SocketAddr(addr) => Resource::tcp(addr),
-            SocketListenAddr::SystemdFd(offset) => Self::SystemFdOffset(offset),
+/// How long sending events has to be blocked before new connections stop being accepted.
+const BACKPRESSURE_PAUSE_AFTER: Duration = Duration::from_secs(1);
+/// How often a paused accept loop checks whether the pipeline has capacity again.
+const BACKPRESSURE_RECHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Tracks how many connections are blocked on sending their events, and since when any were.
+#[derive(Default)]
+struct Backpressure {
+    blocked: Mutex<(usize, Option<Instant>)>,
+}
+
+impl Backpressure {
+    fn block(&self) {
+        let mut blocked = self.blocked.lock().unwrap();
+        if blocked.0 == 0 {
+            blocked.1 = Some(Instant::now());
+        }
+        blocked.0 += 1;
+    }
+
+    fn unblock(&self) {
+        let mut blocked = self.blocked.lock().unwrap();
+        blocked.0 -= 1;
+        if blocked.0 == 0 {
+            blocked.1 = None;
+        }
+    }
+
+    fn is_saturated(&self) -> bool {
+        match self.blocked.lock().unwrap().1 {
+            Some(since) => since.elapsed() >= BACKPRESSURE_PAUSE_AFTER,
+            None => false,
         }
     }
 }
 
-fn parse_systemd_fd<'de, D>(des: D) -> Result<usize, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let s: &'de str = Deserialize::deserialize(des)?;
-    match s {
-        "systemd" => Ok(0),
-        s if s.starts_with("systemd#") => s[8..]
-            .parse::<usize>()
-            .map_err(de::Error::custom)?
-            .checked_sub(1)
-            .ok_or_else(|| de::Error::custom("systemd indices start from 1, found 0")),
-        _ => Err(de::Error::custom("must start with \"systemd\"")),
+/// Reports to `backpressure` while the wrapped sink can't take more events.
+struct BackpressureSink<S> {
+    inner: S,
+    backpressure: Option<Arc<Backpressure>>,
+    blocked: bool,
+}
+
+impl<S> BackpressureSink<S> {
+    fn new(inner: S, backpressure: Option<Arc<Backpressure>>) -> Self {
+        Self {
+            inner,
+            backpressure,
+            blocked: false,
+        }
+    }
+
+    fn track<T>(&mut self, poll: Poll<T>) -> Poll<T> {
+        if let Some(backpressure) = &self.backpressure {
+            if poll.is_pending() != self.blocked {
+                self.blocked = poll.is_pending();
+                if self.blocked {
+                    backpressure.block();
+                } else {
+                    backpressure.unblock();
+                }
+            }
+        }
+        poll
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use serde::Deserialize;
-    use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+impl<S: Sink<Event> + Unpin> Sink<Event> for BackpressureSink<S> {
+    type Error = S::Error;
 
-    #[derive(Debug, Deserialize)]
-    struct Config {
-        addr: SocketListenAddr,
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        let poll = self.inner.poll_ready_unpin(cx);
+        self.track(poll)
     }
 
-    #[test]
-    fn parse_socket_listen_addr() {
-        let test: Config = toml::from_str(r#"addr="127.1.2.3:1234""#).unwrap();
-        assert_eq!(
-            test.addr,
-            SocketListenAddr::SocketAddr(SocketAddr::V4(SocketAddrV4::new(
-                Ipv4Addr::new(127, 1, 2, 3),
-                1234,
-            )))
-        );
-        let test: Config = toml::from_str(r#"addr="systemd""#).unwrap();
-        assert_eq!(test.addr, SocketListenAddr::SystemdFd(0));
-        let test: Config = toml::from_str(r#"addr="systemd#3""#).unwrap();
-        assert_eq!(test.addr, SocketListenAddr::SystemdFd(2));
+    fn start_send(mut self: Pin<&mut Self>, item: Event) -> Result<(), S::Error> {
+        self.inner.start_send_unpin(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        let poll = self.inner.poll_flush_unpin(cx);
+        self.track(poll)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_close_unpin(cx)
+    }
+}
+
+impl<S> Drop for BackpressureSink<S> {
+    fn drop(&mut self) {
+        if let (true, Some(backpressure)) = (self.blocked, &self.backpressure) {
+            backpressure.unblock();
+        }
+    }
+}
+
+/// Handles errors sending events downstream according to `on_send_error`. Only events the sink
+/// rejects when they're handed over can be dropped. The sink failing to get ready or to flush
+/// means it won't take any more events, as a `Pipeline` only fails once it's closed, so that ends
+/// forwarding the connection's events in any case.
+struct SendErrorSink<S> {
+    inner: S,
+    on_send_error: OnSendError,
+}
+
+impl<S> SendErrorSink<S> {
+    fn new(inner: S, on_send_error: OnSendError) -> Self {
+        Self {
+            inner,
+            on_send_error,
+        }
+    }
+}
+
+/// Reports the sink failing, which the connection is closed for.
+fn send_failed<E>(poll: Poll<Result<(), E>>) -> Poll<Result<(), E>> {
+    if let Poll::Ready(Err(_)) = poll {
+        emit!(TcpSendFailed { action: "close" });
+    }
+    poll
+}
+
+impl<S: Sink<Event> + Unpin> Sink<Event> for SendErrorSink<S> {
+    type Error = S::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        send_failed(self.inner.poll_ready_unpin(cx))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Event) -> Result<(), S::Error> {
+        // The event is gone once it's handed over, so it can't be sent again.
+        match (self.inner.start_send_unpin(item), self.on_send_error) {
+            (Err(error), OnSendError::CloseConnection) => {
+                emit!(TcpSendFailed { action: "close" });
+                Err(error)
+            }
+            (Err(_), OnSendError::Drop) => {
+                emit!(TcpSendFailed { action: "drop" });
+                Ok(())
+            }
+            (Ok(()), _) => Ok(()),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        send_failed(self.inner.poll_flush_unpin(cx))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        send_failed(self.inner.poll_close_unpin(cx))
+    }
+}
+
+/// Holds off accepting connections while `backpressure` reports that sending events has been
+/// blocked for a while, or while they come in faster than `rate_limiter` allows. The connections
+/// wait in the listen backlog in the meantime.
+struct PausableAccept<S> {
+    inner: S,
+    backpressure: Option<Arc<Backpressure>>,
+    paused: bool,
+    recheck: Pin<Box<Sleep>>,
+    rate_limiter: Option<RateLimiter>,
+    rate_limited: bool,
+}
+
+impl<S> PausableAccept<S> {
+    fn new(
+        inner: S,
+        backpressure: Option<Arc<Backpressure>>,
+        rate_limiter: Option<RateLimiter>,
+    ) -> Self {
+        Self {
+            inner,
+            backpressure,
+            paused: false,
+            recheck: Box::pin(sleep(Duration::from_secs(0))),
+            rate_limiter,
+            rate_limited: false,
+        }
+    }
+}
+
+impl<S: Stream + Unpin> Stream for PausableAccept<S> {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        let this = &mut *self;
+        if let Some(backpressure) = &this.backpressure {
+            if backpressure.is_saturated() {
+                if !this.paused {
+                    this.paused = true;
+                    emit!(TcpAcceptPaused { paused: true });
+                }
+                this.recheck
+                    .as_mut()
+                    .reset(Instant::now() + BACKPRESSURE_RECHECK_INTERVAL);
+                // only registers the wakeup, as the deadline is in the future
+                let _ = this.recheck.as_mut().poll(cx);
+                return Poll::Pending;
+            }
+            if this.paused {
+                this.paused = false;
+                emit!(TcpAcceptPaused { paused: false });
+            }
+        }
+        if let Some(rate_limiter) = this.rate_limiter.as_mut() {
+            if rate_limiter.poll_ready(cx).is_pending() {
+                // counted once per pause rather than every time the task is polled
+                if !this.rate_limited {
+                    this.rate_limited = true;
+                    emit!(TcpConnectionRateLimited);
+                }
+                return Poll::Pending;
+            }
+            this.rate_limited = false;
+        }
+        let connection = this.inner.poll_next_unpin(cx);
+        if let (Some(rate_limiter), Poll::Ready(Some(_))) =
+            (this.rate_limiter.as_mut(), &connection)
+        {
+            rate_limiter.consume(1);
+        }
+        connection
+    }
+}
+
+/// Budget of bytes buffered for decoding, shared by the connections of a source, so that many
+/// connections buffering partial frames can't add up to more than `max_bytes`. Connections stop
+/// reading once it's used up, until others release bytes they've decoded.
+///
+/// Connections that hold partial frames when it's used up could otherwise wait on each other
+/// forever, so one of them at a time is let past the budget until it has decoded a frame. What's
+/// buffered can then exceed `max_bytes` by up to one frame.
+#[derive(Debug)]
+pub struct BufferBudget {
+    max_bytes: usize,
+    used: AtomicUsize,
+    next_connection: AtomicUsize,
+    state: Mutex<BudgetState>,
+}
+
+#[derive(Debug, Default)]
+struct BudgetState {
+    // connections waiting for bytes to be released, by connection
+    waiting: HashMap<usize, Waker>,
+    // the connection let past the budget
+    overdrawn_by: Option<usize>,
+}
+
+impl BufferBudget {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            used: AtomicUsize::new(0),
+            next_connection: AtomicUsize::new(0),
+            state: Mutex::new(BudgetState::default()),
+        }
+    }
+
+    fn connection_id(&self) -> usize {
+        self.next_connection.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Reserves up to `bytes` for `connection`, which already holds `held` bytes, returning how
+    /// many were reserved. If none are left, the task is woken once some are released.
+    fn reserve(&self, connection: usize, held: usize, bytes: usize, cx: &mut Context<'_>) -> usize {
+        let mut used = self.used.load(Ordering::Acquire);
+        loop {
+            let reserved = bytes.min(self.max_bytes.saturating_sub(used));
+            if reserved == 0 {
+                let mut state = self.state.lock().unwrap();
+                if held > 0
+                    && state
+                        .overdrawn_by
+                        .map_or(true, |overdrawn_by| overdrawn_by == connection)
+                {
+                    state.overdrawn_by = Some(connection);
+                    self.used.fetch_add(bytes, Ordering::AcqRel);
+                    return bytes;
+                }
+                state.waiting.insert(connection, cx.waker().clone());
+                // Bytes released before the waker was added would otherwise go unnoticed.
+                let current = self.used.load(Ordering::Acquire);
+                if current >= self.max_bytes {
+                    return 0;
+                }
+                used = current;
+                continue;
+            }
+            match self.used.compare_exchange_weak(
+                used,
+                used + reserved,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return reserved,
+                Err(current) => used = current,
+            }
+        }
+    }
+
+    fn release(&self, bytes: usize) {
+        if bytes == 0 {
+            return;
+        }
+        self.used.fetch_sub(bytes, Ordering::AcqRel);
+        self.state.lock().unwrap().wake();
+    }
+
+    /// Releases `bytes` that `connection` decoded, which ends it being let past the budget.
+    fn release_decoded(&self, connection: usize, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::AcqRel);
+        let mut state = self.state.lock().unwrap();
+        if state.overdrawn_by == Some(connection) {
+            state.overdrawn_by = None;
+        }
+        state.wake();
+    }
+
+    fn remove(&self, connection: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.waiting.remove(&connection);
+        if state.overdrawn_by == Some(connection) {
+            state.overdrawn_by = None;
+            state.wake();
+        }
+    }
+}
+
+impl BudgetState {
+    fn wake(&mut self) {
+        for (_, waker) in self.waiting.drain() {
+            waker.wake();
+        }
+    }
+}
+
+/// Reads from a connection only as many bytes as the buffer budget allows. Bytes read stay
+/// reserved until `release_to` is told they were decoded, or the connection is dropped.
+struct BudgetedRead<S> {
+    inner: S,
+    budget: Option<(Arc<BufferBudget>, usize)>,
+    reserved: usize,
+}
+
+impl<S> BudgetedRead<S> {
+    fn new(inner: S, budget: Option<Arc<BufferBudget>>) -> Self {
+        Self {
+            inner,
+            budget: budget.map(|budget| {
+                let connection = budget.connection_id();
+                (budget, connection)
+            }),
+            reserved: 0,
+        }
+    }
+
+    /// Releases what was read beyond the `buffered` bytes that are still to be decoded.
+    fn release_to(&mut self, buffered: usize) {
+        if let Some((budget, connection)) = &self.budget {
+            if buffered < self.reserved {
+                budget.release_decoded(*connection, self.reserved - buffered);
+                self.reserved = buffered;
+            }
+        }
+    }
+}
+
+impl<S> Drop for BudgetedRead<S> {
+    fn drop(&mut self) {
+        self.release_to(0);
+        if let Some((budget, connection)) = &self.budget {
+            budget.remove(*connection);
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for BudgetedRead<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let (budget, connection) = match &this.budget {
+            Some((budget, connection)) if buf.remaining() > 0 => (Arc::clone(budget), *connection),
+            _ => return Pin::new(&mut this.inner).poll_read(cx, buf),
+        };
+
+        let reserved = budget.reserve(connection, this.reserved, buf.remaining(), cx);
+        if reserved == 0 {
+            return Poll::Pending;
+        }
+        let mut limited = ReadBuf::new(buf.initialize_unfilled_to(reserved));
+        let result = Pin::new(&mut this.inner).poll_read(cx, &mut limited);
+        let read = limited.filled().len();
+        buf.advance(read);
+
+        budget.release(reserved - read);
+        this.reserved += read;
+        result
+    }
+}
+
+/// Token bucket limiting how many bytes a connection may read, or connections are accepted, per
+/// second, allowing bursts of up to one second's worth.
+struct RateLimiter {
+    per_second: f64,
+    tokens: f64,
+    refilled_at: Instant,
+    delay: Pin<Box<Sleep>>,
+}
+
+impl RateLimiter {
+    fn new(per_second: u64) -> Self {
+        let per_second = per_second.max(1) as f64;
+        Self {
+            per_second,
+            tokens: per_second,
+            refilled_at: Instant::now(),
+            delay: Box::pin(sleep(Duration::from_secs(0))),
+        }
+    }
+
+    /// Ready while there are tokens left, otherwise waits until the debt is paid off.
+    /// A single frame may take the bucket below zero.
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.per_second);
+        self.refilled_at = now;
+
+        if self.tokens >= 0.0 {
+            return Poll::Ready(());
+        }
+        let wait = Duration::from_secs_f64(-self.tokens / self.per_second);
+        self.delay.as_mut().reset(now + wait);
+        self.delay.as_mut().poll(cx)
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.tokens -= amount as f64;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum SocketListenAddr {
+    SocketAddr(SocketAddr),
+    SystemdFd(usize),
+}
+
+// Not derived for an untagged enum, as that would replace errors in systemd addresses, such as an
+// unknown socket name, with a generic one.
+impl<'de> Deserialize<'de> for SocketListenAddr {
+    fn deserialize<D>(des: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(des)?;
+        if s.starts_with("systemd") {
+            let names = std::env::var("LISTEN_FDNAMES").ok();
+            parse_systemd_fd(&s, names.as_deref())
+                .map(Self::SystemdFd)
+                .map_err(de::Error::custom)
+        } else {
+            s.parse().map(Self::SocketAddr).map_err(de::Error::custom)
+        }
+    }
+}
+
+impl fmt::Display for SocketListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::SocketAddr(ref addr) => addr.fmt(f),
+            Self::SystemdFd(offset) => write!(f, "systemd socket #{}", offset),
+        }
+    }
+}
+
+impl From<SocketAddr> for SocketListenAddr {
+    fn from(addr: SocketAddr) -> Self {
+        Self::SocketAddr(addr)
+    }
+}
+
+impl From<SocketListenAddr> for Resource {
+    fn from(addr: SocketListenAddr) -> Resource {
+        match addr {
+            SocketListenAddr::SocketAddr(addr) => Resource::tcp(addr),
+            SocketListenAddr::SystemdFd(offset) => Self::SystemFdOffset(offset),
+        }
+    }
+}
+
+/// Parses a systemd address, with `names` being the names systemd passed the sockets with, if
+/// any, as listed by `LISTEN_FDNAMES`.
+fn parse_systemd_fd(s: &str, names: Option<&str>) -> Result<usize, String> {
+    match s {
+        "systemd" => Ok(0),
+        s if s.starts_with("systemd#") => s[8..]
+            .parse::<usize>()
+            .map_err(|error| error.to_string())?
+            .checked_sub(1)
+            .ok_or_else(|| "systemd indices start from 1, found 0".to_owned()),
+        s if s.starts_with("systemd:") => {
+            let names = names.ok_or_else(|| {
+                format!(
+                    "systemd socket {:?} requested, but systemd didn't pass any named sockets",
+                    &s[8..]
+                )
+            })?;
+            systemd_fd_offset(names, &s[8..]).ok_or_else(|| {
+                format!(
+                    "no socket named {:?} passed by systemd, only {:?}",
+                    &s[8..],
+                    names
+                )
+            })
+        }
+        _ => Err("must start with \"systemd\"".to_owned()),
+    }
+}
+
+/// Looks up a socket named with `FileDescriptorName=` in `LISTEN_FDNAMES`, which lists the names
+/// of the passed sockets in order, separated by colons. The name is resolved when the config is
+/// loaded, so the address refers to the socket by its offset like `systemd#N` does.
+fn systemd_fd_offset(names: &str, name: &str) -> Option<usize> {
+    if name.is_empty() {
+        return None;
+    }
+    names.split(':').position(|candidate| candidate == name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        event::metric::MetricValue,
+        metrics::{capture_metrics, get_controller},
+        test_util::{collect_n, counter, next_addr, send_lines, wait_for_tcp},
+    };
+    use futures::channel::mpsc;
+    use serde::Deserialize;
+    use std::{
+        net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4},
+        str::FromStr,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        time::timeout,
+    };
+    use tokio_util::codec::LinesCodec;
+
+    lazy_static! {
+        static ref NO_SYSTEMD_FDS: Mutex<ListenFd> = Mutex::new(ListenFd::empty());
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Config {
+        addr: SocketListenAddr,
+    }
+
+    #[test]
+    fn parse_socket_listen_addr() {
+        let test: Config = toml::from_str(r#"addr="127.1.2.3:1234""#).unwrap();
+        assert_eq!(
+            test.addr,
+            SocketListenAddr::SocketAddr(SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::new(127, 1, 2, 3),
+                1234,
+            )))
+        );
+        let test: Config = toml::from_str(r#"addr="systemd""#).unwrap();
+        assert_eq!(test.addr, SocketListenAddr::SystemdFd(0));
+        let test: Config = toml::from_str(r#"addr="systemd#3""#).unwrap();
+        assert_eq!(test.addr, SocketListenAddr::SystemdFd(2));
+    }
+
+    #[test]
+    fn systemd_udp_socket() {
+        let error = take_udp_socket(&NO_SYSTEMD_FDS, 1).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "systemd socket #1 isn't open or is already taken."
+        );
+    }
+
+    #[test]
+    fn systemd_fd_names() {
+        assert_eq!(systemd_fd_offset("http:fluent:syslog", "fluent"), Some(1));
+        assert_eq!(systemd_fd_offset("http:fluent:syslog", "http"), Some(0));
+        assert_eq!(systemd_fd_offset("http:fluent:syslog", "statsd"), None);
+        assert_eq!(systemd_fd_offset("http::syslog", ""), None);
+
+        assert_eq!(
+            parse_systemd_fd("systemd:fluent", Some("http:fluent")),
+            Ok(1)
+        );
+        let error = parse_systemd_fd("systemd:statsd", Some("http:fluent")).unwrap_err();
+        assert!(error.contains(r#"no socket named "statsd""#), "{}", error);
+        let error = parse_systemd_fd("systemd:fluent", None).unwrap_err();
+        assert!(error.contains("didn't pass any named sockets"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn set_nodelay() {
+        let addr = next_addr();
+        let mut listener = MaybeTlsSettings::Raw(()).bind(&addr).await.unwrap();
+        let _client = TcpStream::connect(addr).await.unwrap();
+
+        let mut socket = listener.accept().await.unwrap();
+        socket.handshake().await.unwrap();
+
+        socket.set_nodelay(true).unwrap();
+        assert!(SockRef::from(socket.get_ref().unwrap()).nodelay().unwrap());
+        socket.set_nodelay(false).unwrap();
+        assert!(!SockRef::from(socket.get_ref().unwrap()).nodelay().unwrap());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn set_receive_lowat_bytes() {
+        let addr = next_addr();
+        let mut listener = MaybeTlsSettings::Raw(()).bind(&addr).await.unwrap();
+        let _client = TcpStream::connect(addr).await.unwrap();
+
+        let mut socket = listener.accept().await.unwrap();
+        socket.handshake().await.unwrap();
+
+        socket.set_receive_lowat_bytes(4096).unwrap();
+        assert_eq!(
+            crate::tcp::receive_lowat(socket.get_ref().unwrap()).unwrap(),
+            4096
+        );
+    }
+
+    #[cfg(all(unix, not(target_os = "solaris"), not(target_os = "illumos")))]
+    #[tokio::test]
+    async fn reuse_port() {
+        let addr = next_addr();
+        let tls = MaybeTlsSettings::Raw(());
+
+        let _first = make_listener(addr.into(), &NO_SYSTEMD_FDS, &tls, true, None, None)
+            .await
+            .unwrap();
+        let _second = make_listener(addr.into(), &NO_SYSTEMD_FDS, &tls, true, None, None)
+            .await
+            .unwrap();
+        assert!(
+            make_listener(addr.into(), &NO_SYSTEMD_FDS, &tls, false, None, None)
+                .await
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn bind_to_device() {
+        let addr = next_addr();
+        let tls = MaybeTlsSettings::Raw(());
+        let listener =
+            |device| make_listener(addr.into(), &NO_SYSTEMD_FDS, &tls, false, device, None);
+
+        if cfg!(target_os = "linux") {
+            // a device that doesn't exist shows that the option is applied
+            assert!(listener(Some("vector-test0")).await.is_none());
+            assert!(listener(Some("lo")).await.is_some());
+        } else {
+            assert!(listener(Some("lo")).await.is_none());
+        }
+    }
+
+    #[cfg(all(unix, not(target_os = "solaris"), not(target_os = "illumos")))]
+    #[tokio::test]
+    async fn v6only() {
+        let port = next_addr().port();
+        let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, port));
+        let tls = MaybeTlsSettings::Raw(());
+        let listener = |v6only| {
+            make_listener(
+                addr.into(),
+                &NO_SYSTEMD_FDS,
+                &tls,
+                false,
+                None,
+                Some(v6only),
+            )
+        };
+
+        // A dual-stack listener accepts IPv4 connections as IPv4-mapped addresses.
+        let mut dual_stack = listener(false).await.unwrap();
+        let _client = TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+            .await
+            .unwrap();
+        let socket = timeout(Duration::from_secs(1), dual_stack.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            socket.peer_addr().ip(),
+            Ipv4Addr::LOCALHOST.to_ipv6_mapped()
+        );
+        drop(dual_stack);
+
+        let _v6only = listener(true).await.unwrap();
+        assert!(TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+            .await
+            .is_err());
+    }
+
+    #[derive(Clone)]
+    struct LinesSource;
+
+    impl TcpSource for LinesSource {
+        type Error = LinesCodecError;
+        type Decoder = LinesCodec;
+
+        fn decoder(&self, _peer_addr: SocketAddr) -> Self::Decoder {
+            LinesCodec::new()
+        }
+
+        fn build_event(&self, frame: String, _peer_addr: SocketAddr) -> Option<Event> {
+            Some(frame.into())
+        }
+    }
+
+    #[tokio::test]
+    async fn received_totals() {
+        let bytes_before = counter("component_received_bytes_total");
+        let events_before = counter("component_received_events_total");
+
+        let (tx, rx) = Pipeline::new_test();
+        let addr = next_addr();
+        let source = LinesSource
+            .run(
+                addr.into(),
+                None,
+                10,
+                MaybeTlsSettings::Raw(()),
+                None,
+                TcpSourceOptions::default(),
+                ShutdownSignal::noop(),
+                tx,
+            )
+            .unwrap();
+        tokio::spawn(source);
+        wait_for_tcp(addr).await;
+
+        let lines = vec!["foo".to_owned(), "bar".to_owned(), "baz".to_owned()];
+        send_lines(addr, lines).await.unwrap();
+        assert_eq!(collect_n(rx, 3).await.len(), 3);
+
+        // Sources in other tests may be receiving concurrently, so only a lower bound holds.
+        assert!(counter("component_received_bytes_total") - bytes_before >= 12.0);
+        assert!(counter("component_received_events_total") - events_before >= 3.0);
+    }
+
+    #[test]
+    fn accept_error_metric() {
+        let errored = || counter("connection_errored_total");
+
+        let before = errored();
+        let error = crate::tls::TlsError::IncomingListener {
+            source: io::Error::new(io::ErrorKind::Other, "too many open files"),
+        };
+        assert!(accepted(Err(error)).is_none());
+        assert!(errored() - before >= 1.0);
+    }
+
+    #[tokio::test]
+    async fn stop_accepting_on_shutdown() {
+        let (tx, _rx) = Pipeline::new_test();
+        let addr = next_addr();
+        let (trigger, shutdown, _) = ShutdownSignal::new_wired();
+        let source = LinesSource
+            .run(
+                addr.into(),
+                None,
+                10,
+                MaybeTlsSettings::Raw(()),
+                None,
+                TcpSourceOptions::default(),
+                shutdown,
+                tx,
+            )
+            .unwrap();
+        tokio::spawn(source);
+        wait_for_tcp(addr).await;
+
+        // An open connection keeps the source draining for the shutdown timeout.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"foo\n").await.unwrap();
+        sleep(Duration::from_millis(100)).await;
+
+        trigger.cancel();
+        sleep(Duration::from_millis(100)).await;
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn force_close_on_shutdown() {
+        let (tx, _rx) = Pipeline::new_test();
+        let addr = next_addr();
+        let (trigger, shutdown, _) = ShutdownSignal::new_wired();
+        let source = LinesSource
+            .run(
+                addr.into(),
+                None,
+                1,
+                MaybeTlsSettings::Raw(()),
+                None,
+                TcpSourceOptions {
+                    force_close_on_shutdown: true,
+                    ..Default::default()
+                },
+                shutdown,
+                tx,
+            )
+            .unwrap();
+        tokio::spawn(source);
+        wait_for_tcp(addr).await;
+
+        // A partial line that would otherwise be decoded when the connection is drained.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"foo").await.unwrap();
+        sleep(Duration::from_millis(100)).await;
+
+        trigger.cancel();
+        sleep(Duration::from_millis(300)).await;
+        // Only the write half is closed before the timeout.
+        stream.write_all(b"bar").await.unwrap();
+
+        // Once closed in both directions, writes are reset.
+        let closed = timeout(Duration::from_secs(5), async {
+            while stream.write_all(b"baz").await.is_ok() {
+                sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await;
+        assert!(closed.is_ok(), "connection wasn't closed");
+    }
+
+    #[tokio::test]
+    async fn connection_reset() {
+        let resets = || counter("connection_reset_total");
+
+        let (tx, _rx) = Pipeline::new_test();
+        let addr = next_addr();
+        let source = LinesSource
+            .run(
+                addr.into(),
+                None,
+                10,
+                MaybeTlsSettings::Raw(()),
+                None,
+                TcpSourceOptions::default(),
+                ShutdownSignal::noop(),
+                tx,
+            )
+            .unwrap();
+        tokio::spawn(source);
+        wait_for_tcp(addr).await;
+
+        let before = resets();
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"foo").await.unwrap();
+        sleep(Duration::from_millis(100)).await;
+        // Lingering for zero seconds makes closing the socket send a RST.
+        SockRef::from(&stream)
+            .set_linger(Some(Duration::from_secs(0)))
+            .unwrap();
+        drop(stream);
+
+        let reset = timeout(Duration::from_secs(5), async {
+            while resets() - before < 1.0 {
+                sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await;
+        assert!(reset.is_ok(), "reset wasn't counted");
+    }
+
+    #[tokio::test]
+    async fn receive_buffer_size() {
+        let _ = crate::metrics::init();
+        let controller = get_controller().unwrap();
+        let buffer_size = || {
+            capture_metrics(controller)
+                .map(Event::into_metric)
+                .find(|metric| metric.name() == "connection_receive_buffer_bytes")
+                .map(|metric| match metric.value() {
+                    MetricValue::Gauge { value } => *value,
+                    value => panic!("expected a gauge, got {:?}", value),
+                })
+        };
+
+        let (tx, _rx) = Pipeline::new_test();
+        let addr = next_addr();
+        let source = LinesSource
+            .run(
+                addr.into(),
+                None,
+                10,
+                MaybeTlsSettings::Raw(()),
+                Some(1 << 30),
+                TcpSourceOptions::default(),
+                ShutdownSignal::noop(),
+                tx,
+            )
+            .unwrap();
+        tokio::spawn(source);
+        wait_for_tcp(addr).await;
+
+        let _stream = TcpStream::connect(addr).await.unwrap();
+
+        // The operating system won't hand out a gigabyte, but whatever it settled on is reported.
+        let size = timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(size) = buffer_size() {
+                    return size;
+                }
+                sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("receive buffer size wasn't reported");
+        assert!(size > 0.0);
+    }
+
+    /// Records the message of each log, along with the `connection_id` of the span it's logged in.
+    #[derive(Clone, Default)]
+    struct ConnectionLogs(Arc<std::sync::Mutex<Vec<(String, Option<u64>)>>>);
+
+    struct ConnectionId(u64);
+
+    #[derive(Default)]
+    struct LogVisitor {
+        message: Option<String>,
+        connection_id: Option<u64>,
+    }
+
+    impl tracing::field::Visit for LogVisitor {
+        fn record_u64(&mut self, field: &tracing::field::Field, value: u64) {
+            if field.name() == "connection_id" {
+                self.connection_id = Some(value);
+            }
+        }
+
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            if field.name() == "message" {
+                self.message = Some(value.to_owned());
+            }
+        }
+
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                self.message = Some(format!("{:?}", value));
+            }
+        }
+    }
+
+    impl<S> tracing_subscriber::Layer<S> for ConnectionLogs
+    where
+        S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    {
+        fn new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            id: &tracing::span::Id,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut visitor = LogVisitor::default();
+            attrs.record(&mut visitor);
+            if let Some(connection_id) = visitor.connection_id {
+                let span = ctx.span(id).expect("Span not found, this is a bug");
+                span.extensions_mut().insert(ConnectionId(connection_id));
+            }
+        }
+
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut visitor = LogVisitor::default();
+            event.record(&mut visitor);
+            let connection_id = ctx.lookup_current().and_then(|span| {
+                span.extensions()
+                    .get::<ConnectionId>()
+                    .map(|connection_id| connection_id.0)
+            });
+            self.0
+                .lock()
+                .unwrap()
+                .push((visitor.message.unwrap_or_default(), connection_id));
+        }
+    }
+
+    #[tokio::test]
+    async fn connection_id() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let logs = ConnectionLogs::default();
+        // Only for this thread, which the connections are handled on too.
+        let _guard = tracing::subscriber::set_default(
+            tracing_subscriber::registry::Registry::default().with(logs.clone()),
+        );
+        let connection_ids = |message: &str| {
+            logs.0
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(logged, _)| logged == message)
+                .map(|(_, connection_id)| connection_id.expect("logged outside of a connection"))
+                .collect::<Vec<_>>()
+        };
+
+        let (tx, mut rx) = Pipeline::new_test();
+        let addr = next_addr();
+        let source = LinesSource
+            .run(
+                addr.into(),
+                None,
+                10,
+                MaybeTlsSettings::Raw(()),
+                None,
+                TcpSourceOptions {
+                    suppress_empty_connections: true,
+                    ..Default::default()
+                },
+                ShutdownSignal::noop(),
+                tx,
+            )
+            .unwrap();
+        tokio::spawn(source);
+        wait_for_tcp(addr).await;
+
+        for connection in 1..=2 {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(b"foo\n").await.unwrap();
+            assert!(rx.next().await.is_some());
+            drop(stream);
+            let closed = timeout(Duration::from_secs(5), async {
+                while connection_ids("Connection closed.").len() < connection {
+                    sleep(Duration::from_millis(50)).await;
+                }
+            })
+            .await;
+            assert!(closed.is_ok(), "connection wasn't closed");
+        }
+
+        // The connection made by `wait_for_tcp` doesn't send anything, so with
+        // `suppress_empty_connections` it's neither accepted nor closed as far as logs go.
+        let accepted = connection_ids("Accepted a new connection.");
+        let closed = connection_ids("Connection closed.");
+        assert_eq!(accepted.len(), 2);
+        assert_eq!(accepted, closed);
+        assert_ne!(accepted[0], accepted[1]);
+    }
+
+    /// Rejects every other event it's sent, keeping the rest.
+    #[derive(Default)]
+    struct RejectingSink {
+        sent: usize,
+        kept: Arc<Mutex<Vec<Event>>>,
+    }
+
+    impl Sink<Event> for RejectingSink {
+        type Error = ();
+
+        fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), ()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn start_send(mut self: Pin<&mut Self>, item: Event) -> Result<(), ()> {
+            self.sent += 1;
+            if self.sent % 2 == 0 {
+                return Err(());
+            }
+            self.kept.lock().unwrap().push(item);
+            Ok(())
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), ()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), ()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Forwards three events through `SendErrorSink`.
+    async fn send_three<S: Sink<Event> + Unpin>(
+        sink: S,
+        on_send_error: OnSendError,
+    ) -> Result<(), S::Error> {
+        let events = (0..3).map(|_| Ok(Event::from("foo")));
+        stream::iter(events)
+            .forward(SendErrorSink::new(sink, on_send_error))
+            .await
+    }
+
+    #[tokio::test]
+    async fn on_send_error_close_connection() {
+        let sink = RejectingSink::default();
+        let kept = Arc::clone(&sink.kept);
+        assert!(send_three(sink, OnSendError::CloseConnection)
+            .await
+            .is_err());
+        assert_eq!(kept.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn on_send_error_drop() {
+        let sink = RejectingSink::default();
+        let kept = Arc::clone(&sink.kept);
+        assert!(send_three(sink, OnSendError::Drop).await.is_ok());
+        assert_eq!(kept.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn on_send_error_closed_pipeline() {
+        // A closed pipeline doesn't take any more events, whatever `on_send_error` says.
+        for on_send_error in &[OnSendError::CloseConnection, OnSendError::Drop] {
+            let (tx, rx) = Pipeline::new_test();
+            drop(rx);
+            assert!(send_three(tx, *on_send_error).await.is_err());
+        }
+    }
+
+    #[test]
+    fn connection_stats() {
+        let peer_addr = "127.0.0.1:51234".parse().unwrap();
+        let gauge = OpenGauge::new();
+
+        // A connection that's never established, such as a health check with
+        // `suppress_empty_connections`, doesn't count as open.
+        let stats = ConnectionStats::new(peer_addr, gauge.clone());
+        assert!(!stats.is_established());
+        assert!(!gauge.any_open());
+        drop(stats);
+        assert!(!gauge.any_open());
+
+        let mut stats = ConnectionStats::new(peer_addr, gauge.clone());
+        stats.established();
+        stats.established();
+        assert!(stats.is_established());
+        assert!(gauge.any_open());
+        drop(stats);
+        assert!(!gauge.any_open());
+    }
+
+    #[tokio::test]
+    async fn suppress_empty_connections() {
+        let established = || counter("connection_established_total");
+        let counted = |before: f64| async move {
+            timeout(Duration::from_secs(5), async {
+                while established() - before < 1.0 {
+                    sleep(Duration::from_millis(50)).await;
+                }
+            })
+            .await
+            .is_ok()
+        };
+
+        for suppress_empty_connections in &[false, true] {
+            let (tx, rx) = Pipeline::new_test();
+            let addr = next_addr();
+            let source = LinesSource
+                .run(
+                    addr.into(),
+                    None,
+                    10,
+                    MaybeTlsSettings::Raw(()),
+                    None,
+                    TcpSourceOptions {
+                        suppress_empty_connections: *suppress_empty_connections,
+                        ..Default::default()
+                    },
+                    ShutdownSignal::noop(),
+                    tx,
+                )
+                .unwrap();
+            tokio::spawn(source);
+            wait_for_tcp(addr).await;
+
+            // Connections that close without sending anything are only counted if not suppressed,
+            // which other tests connecting concurrently keep from being checked here, see
+            // `connection_stats`.
+            let before = established();
+            drop(TcpStream::connect(addr).await.unwrap());
+            if !*suppress_empty_connections {
+                assert!(counted(before).await, "empty connection wasn't counted");
+            }
+
+            // Either way, those that do send something are.
+            let before = established();
+            send_lines(addr, vec!["foo".to_owned()]).await.unwrap();
+            assert_eq!(collect_n(rx, 1).await.len(), 1);
+            assert!(counted(before).await, "connection wasn't counted");
+        }
+    }
+
+    #[tokio::test]
+    async fn buffer_budget() {
+        const MAX_BYTES: usize = 64 * 1024;
+        const LINE_BYTES: usize = 16 * 1024;
+
+        let (tx, _rx) = Pipeline::new_test();
+        let addr = next_addr();
+        let budget = Arc::new(BufferBudget::new(MAX_BYTES));
+        let source = LinesSource
+            .run(
+                addr.into(),
+                None,
+                10,
+                MaybeTlsSettings::Raw(()),
+                None,
+                TcpSourceOptions {
+                    frame_read_timeout_secs: Some(1),
+                    buffer_budget: Some(Arc::clone(&budget)),
+                    ..Default::default()
+                },
+                ShutdownSignal::noop(),
+                tx,
+            )
+            .unwrap();
+        tokio::spawn(source);
+        wait_for_tcp(addr).await;
+        let used = || budget.used.load(Ordering::Acquire);
+
+        // Lines that never end, so all that's read stays buffered, adding up to more than the
+        // budget. Only the one connection let past the budget goes beyond it.
+        let mut streams = Vec::new();
+        for _ in 0..8 {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(&[b'a'; LINE_BYTES]).await.unwrap();
+            streams.push(stream);
+            assert!(used() <= MAX_BYTES + LINE_BYTES);
+        }
+        sleep(Duration::from_millis(200)).await;
+        assert!(
+            (MAX_BYTES..=MAX_BYTES + LINE_BYTES).contains(&used()),
+            "{}",
+            used()
+        );
+
+        // Connections stuck on partial frames are closed by the frame read timeout, releasing
+        // what they buffered so that the waiting ones get to read.
+        timeout(Duration::from_secs(10), async {
+            while used() > 0 {
+                sleep(Duration::from_millis(50)).await;
+                assert!(used() <= MAX_BYTES + LINE_BYTES);
+            }
+        })
+        .await
+        .unwrap();
+        drop(streams);
+    }
+
+    #[tokio::test]
+    async fn buffer_budget_partial_frames() {
+        let (tx, rx) = Pipeline::new_test();
+        let addr = next_addr();
+        let budget = Arc::new(BufferBudget::new(1024));
+        let source = LinesSource
+            .run(
+                addr.into(),
+                None,
+                10,
+                MaybeTlsSettings::Raw(()),
+                None,
+                TcpSourceOptions {
+                    buffer_budget: Some(Arc::clone(&budget)),
+                    ..Default::default()
+                },
+                ShutdownSignal::noop(),
+                tx,
+            )
+            .unwrap();
+        tokio::spawn(source);
+        wait_for_tcp(addr).await;
+
+        // Both connections buffer part of a line that, together, use up the budget, and neither
+        // gets to finish it unless one is let past the budget.
+        let mut streams = Vec::new();
+        for _ in 0..2 {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(&[b'a'; 700]).await.unwrap();
+            streams.push(stream);
+        }
+        sleep(Duration::from_millis(100)).await;
+        for stream in &mut streams {
+            stream.write_all(&[b'a'; 300]).await.unwrap();
+            stream.write_all(b"\n").await.unwrap();
+        }
+
+        let events = timeout(Duration::from_secs(5), collect_n(rx, 2))
+            .await
+            .expect("partial frames weren't finished");
+        assert_eq!(events.len(), 2);
+        drop(streams);
+        timeout(Duration::from_secs(5), async {
+            while budget.used.load(Ordering::Acquire) > 0 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn buffer_budget_waiting_once_per_connection() {
+        let budget = BufferBudget::new(0);
+        let connection = budget.connection_id();
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        for _ in 0..10 {
+            assert_eq!(budget.reserve(connection, 0, 100, &mut cx), 0);
+        }
+        assert_eq!(budget.state.lock().unwrap().waiting.len(), 1);
+
+        budget.remove(connection);
+        assert!(budget.state.lock().unwrap().waiting.is_empty());
+    }
+
+    #[tokio::test]
+    async fn connection_duration() {
+        let _ = crate::metrics::init();
+        let controller = get_controller().unwrap();
+        let histogram = || {
+            capture_metrics(controller)
+                .map(Event::into_metric)
+                .filter(|metric| metric.name() == "connection_duration_seconds")
+                .map(|metric| match metric.value() {
+                    MetricValue::AggregatedHistogram { count, sum, .. } => (*count, *sum),
+                    value => panic!("expected a histogram, got {:?}", value),
+                })
+                .next()
+                .unwrap_or((0, 0.0))
+        };
+        let (count_before, sum_before) = histogram();
+
+        let (tx, _rx) = Pipeline::new_test();
+        let addr = next_addr();
+        let (trigger, shutdown, _) = ShutdownSignal::new_wired();
+        let source = LinesSource
+            .run(
+                addr.into(),
+                None,
+                1,
+                MaybeTlsSettings::Raw(()),
+                None,
+                TcpSourceOptions::default(),
+                shutdown,
+                tx,
+            )
+            .unwrap();
+        tokio::spawn(source);
+        wait_for_tcp(addr).await;
+
+        // The client keeps the connection open, so it only ends once the tripwire resets it.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"foo\n").await.unwrap();
+        sleep(Duration::from_millis(200)).await;
+        trigger.cancel();
+
+        // Sources in other tests may be recording concurrently, so only lower bounds hold.
+        timeout(Duration::from_secs(5), async {
+            while histogram().1 - sum_before < 1.0 {
+                sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(histogram().0 > count_before);
+    }
+
+    #[tokio::test]
+    async fn drain_deadline_on_stalled_sink() {
+        let dropped_frames = || counter("connection_drain_dropped_frames_total");
+
+        // Nothing ever receives the events, so the sink stalls once its buffer is full.
+        let (tx, _rx) = Pipeline::new_with_buffer(1, vec![]);
+        let addr = next_addr();
+        let (trigger, shutdown, _) = ShutdownSignal::new_wired();
+        let source = LinesSource
+            .run(
+                addr.into(),
+                None,
+                1,
+                MaybeTlsSettings::Raw(()),
+                None,
+                TcpSourceOptions::default(),
+                shutdown,
+                tx,
+            )
+            .unwrap();
+        tokio::spawn(source);
+        wait_for_tcp(addr).await;
+
+        let before = dropped_frames();
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all("foo\n".repeat(100).as_bytes())
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(200)).await;
+        trigger.cancel();
+
+        // Reset a second after shutdown, and closed another second later with the frames it
+        // couldn't hand out dropped, rather than waiting on the sink.
+        timeout(Duration::from_secs(5), async {
+            while dropped_frames() - before < 1.0 {
+                sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("drain didn't time out");
+    }
+
+    #[tokio::test]
+    async fn allowed_peers() {
+        async fn send_line(allowed_peers: &[&str]) -> Option<Event> {
+            let (tx, mut rx) = Pipeline::new_test();
+            let addr = next_addr();
+            let allowed_peers = allowed_peers
+                .iter()
+                .map(|cidr| IpCidr::from_str(cidr).unwrap())
+                .collect();
+            let source = LinesSource
+                .run(
+                    addr.into(),
+                    None,
+                    10,
+                    MaybeTlsSettings::Raw(()),
+                    None,
+                    TcpSourceOptions {
+                        allowed_peers: Some(allowed_peers),
+                        ..Default::default()
+                    },
+                    ShutdownSignal::noop(),
+                    tx,
+                )
+                .unwrap();
+            tokio::spawn(source);
+            wait_for_tcp(addr).await;
+
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let _ = stream.write_all(b"foo\n").await;
+            let mut buf = [0u8; 16];
+            let closed = matches!(
+                timeout(Duration::from_secs(1), stream.read(&mut buf)).await,
+                Ok(Ok(0)) | Ok(Err(_))
+            );
+            let event = timeout(Duration::from_secs(1), rx.next())
+                .await
+                .ok()
+                .flatten();
+            assert_eq!(closed, event.is_none());
+            event
+        }
+
+        assert!(send_line(&["10.0.0.0/8", "2001:db8::/32"]).await.is_none());
+        assert!(send_line(&["10.0.0.0/8", "127.0.0.0/8"]).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn max_bytes_per_second() {
+        let (tx, mut rx) = Pipeline::new_test();
+        let addr = next_addr();
+        let source = LinesSource
+            .run(
+                addr.into(),
+                None,
+                10,
+                MaybeTlsSettings::Raw(()),
+                None,
+                TcpSourceOptions {
+                    max_bytes_per_second: Some(200),
+                    ..Default::default()
+                },
+                ShutdownSignal::noop(),
+                tx,
+            )
+            .unwrap();
+        tokio::spawn(source);
+        wait_for_tcp(addr).await;
+
+        // 400 bytes, of which only the first 200 fit in the initial burst. However long the test
+        // takes, no more than that and what the rate allows for the time that passed, plus the
+        // frame that goes over, may be received.
+        let lines = (0..40)
+            .map(|i| format!("line {:04}", i))
+            .collect::<Vec<_>>();
+        let start = Instant::now();
+        send_lines(addr, lines).await.unwrap();
+        let mut received = 0;
+        for _ in 0..40 {
+            let event = timeout(Duration::from_secs(10), rx.next())
+                .await
+                .unwrap()
+                .unwrap();
+            // with the newline
+            received += event.as_log()[crate::config::log_schema().message_key()]
+                .to_string_lossy()
+                .len()
+                + 1;
+            let allowed = 200.0 + 200.0 * start.elapsed().as_secs_f64() + 10.0;
+            assert!(
+                received as f64 <= allowed,
+                "{} bytes received, {} allowed",
+                received,
+                allowed
+            );
+        }
+        assert_eq!(received, 400);
+    }
+
+    #[tokio::test]
+    async fn max_connection_rate() {
+        tokio::time::pause();
+        let before = counter("connection_rate_limited_total");
+
+        // Connections that are all waiting to be accepted at once.
+        let mut accept = PausableAccept::new(stream::iter(0..5), None, Some(RateLimiter::new(2)));
+        let start = Instant::now();
+        let mut accepted_at = Vec::new();
+        while accept.next().await.is_some() {
+            accepted_at.push(start.elapsed());
+        }
+
+        // The first ones use up the initial burst, and the rest are accepted at two per second.
+        assert_eq!(accepted_at.len(), 5);
+        assert!(accepted_at[..3]
+            .iter()
+            .all(|elapsed| *elapsed < Duration::from_millis(10)));
+        assert!(accepted_at[3] >= Duration::from_millis(500));
+        assert!(accepted_at[4] >= Duration::from_millis(1000));
+        assert!(accepted_at[4] < Duration::from_millis(1100));
+        assert!(counter("connection_rate_limited_total") - before >= 2.0);
+    }
+
+    #[tokio::test]
+    async fn max_connections_queue() {
+        let (mut rx, addr, _) =
+            max_connections_source(OnConnectionLimit::Queue, ShutdownSignal::noop()).await;
+        let first = only_connection(&mut rx, addr).await;
+
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        second.write_all(b"second\n").await.unwrap();
+        assert!(timeout(Duration::from_millis(200), rx.next())
+            .await
+            .is_err());
+
+        // the second connection is served once the first one closes
+        drop(first);
+        let event = timeout(Duration::from_secs(1), rx.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            event.as_log()[crate::config::log_schema().message_key()],
+            "second".into()
+        );
+    }
+
+    #[tokio::test]
+    async fn max_connections_queue_shutdown() {
+        let (trigger, shutdown, _) = ShutdownSignal::new_wired();
+        let (mut rx, addr, source) =
+            max_connections_source(OnConnectionLimit::Queue, shutdown).await;
+        let first = only_connection(&mut rx, addr).await;
+
+        // waits for the first connection to close
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        second.write_all(b"second\n").await.unwrap();
+        sleep(Duration::from_millis(100)).await;
+
+        // The first connection is only reset once the shutdown timeout has passed, and accepting
+        // stops well before that.
+        trigger.cancel();
+        timeout(Duration::from_secs(2), source)
+            .await
+            .expect("source didn't shut down")
+            .unwrap()
+            .unwrap();
+        drop((first, second));
+    }
+
+    #[tokio::test]
+    async fn max_connections_reject() {
+        let rejected = || counter("connection_limit_rejected_total");
+
+        let (mut rx, addr, _) =
+            max_connections_source(OnConnectionLimit::Reject, ShutdownSignal::noop()).await;
+        let _first = only_connection(&mut rx, addr).await;
+
+        let before = rejected();
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        let mut buf = [0u8; 8];
+        let read = timeout(Duration::from_secs(1), second.read(&mut buf))
+            .await
+            .unwrap();
+        // closed without anything read from it
+        assert!(matches!(read, Ok(0) | Err(_)));
+        assert!(rejected() - before >= 1.0);
+        assert!(timeout(Duration::from_millis(100), rx.next())
+            .await
+            .is_err());
+    }
+
+    /// Hands out a thousand frames for every byte received.
+    #[derive(Clone)]
+    struct BurstSource;
+
+    struct BurstDecoder(usize);
+
+    impl Decoder for BurstDecoder {
+        type Item = String;
+        type Error = LinesCodecError;
+
+        fn decode(&mut self, src: &mut BytesMut) -> Result<Option<String>, LinesCodecError> {
+            if self.0 == 0 {
+                if src.is_empty() {
+                    return Ok(None);
+                }
+                let _ = src.split_to(1);
+                self.0 = 1000;
+            }
+            self.0 -= 1;
+            Ok(Some(self.0.to_string()))
+        }
+    }
+
+    impl TcpSource for BurstSource {
+        type Error = LinesCodecError;
+        type Decoder = BurstDecoder;
+
+        fn decoder(&self, _peer_addr: SocketAddr) -> Self::Decoder {
+            BurstDecoder(0)
+        }
+
+        fn build_event(&self, frame: String, _peer_addr: SocketAddr) -> Option<Event> {
+            Some(frame.into())
+        }
+    }
+
+    #[tokio::test]
+    async fn yields_between_frames() {
+        // Large enough that sending events never waits on the receiver.
+        let (tx, mut rx) = Pipeline::new_with_buffer(10_000, vec![]);
+        let addr = next_addr();
+        let source = BurstSource
+            .run(
+                addr.into(),
+                None,
+                10,
+                MaybeTlsSettings::Raw(()),
+                None,
+                TcpSourceOptions::default(),
+                ShutdownSignal::noop(),
+                tx,
+            )
+            .unwrap();
+        tokio::spawn(source);
+        wait_for_tcp(addr).await;
+
+        // Counts how often other tasks get to run.
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let ticker = tokio::spawn({
+            let ticks = Arc::clone(&ticks);
+            async move {
+                loop {
+                    ticks.fetch_add(1, Ordering::Relaxed);
+                    tokio::task::yield_now().await;
+                }
+            }
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"x").await.unwrap();
+
+        // All frames of the burst are decoded from data that is already buffered, so without
+        // yielding in between they would all be sent before anything else gets to run.
+        assert!(rx.next().await.is_some());
+        let first = ticks.load(Ordering::Relaxed);
+        for _ in 1..1000 {
+            assert!(rx.next().await.is_some());
+        }
+        assert!(ticks.load(Ordering::Relaxed) > first);
+
+        ticker.abort();
+    }
+
+    #[tokio::test]
+    async fn shutdown_while_throttled() {
+        let (tx, rx) = Pipeline::new_test();
+        let addr = next_addr();
+        let (trigger, shutdown, _) = ShutdownSignal::new_wired();
+        let source = LinesSource
+            .run(
+                addr.into(),
+                None,
+                1,
+                MaybeTlsSettings::Raw(()),
+                None,
+                TcpSourceOptions {
+                    max_bytes_per_second: Some(10),
+                    ..Default::default()
+                },
+                shutdown,
+                tx,
+            )
+            .unwrap();
+        tokio::spawn(source);
+        wait_for_tcp(addr).await;
+
+        // The first line takes the bucket far below zero, so the second one would only be read
+        // after about 9 seconds.
+        let line = format!("{}\n", "a".repeat(100));
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(line.repeat(2).as_bytes()).await.unwrap();
+        sleep(Duration::from_millis(200)).await;
+        trigger.cancel();
+
+        // The connection is closed once the shutdown timeout passes, which ends the stream.
+        let events = timeout(Duration::from_secs(5), rx.collect::<Vec<_>>())
+            .await
+            .unwrap();
+        assert!(!events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn max_connection_lifetime() {
+        let lifetimes_exceeded = || counter("connection_lifetime_exceeded_total");
+
+        let (tx, mut rx) = Pipeline::new_test();
+        let addr = next_addr();
+        let source = LinesSource
+            .run(
+                addr.into(),
+                None,
+                10,
+                MaybeTlsSettings::Raw(()),
+                None,
+                TcpSourceOptions {
+                    max_connection_lifetime_secs: Some(1),
+                    ..Default::default()
+                },
+                ShutdownSignal::noop(),
+                tx,
+            )
+            .unwrap();
+        tokio::spawn(source);
+        wait_for_tcp(addr).await;
+
+        let before = lifetimes_exceeded();
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"foo\n").await.unwrap();
+        let event = timeout(Duration::from_secs(1), rx.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            event.as_log()[crate::config::log_schema().message_key()],
+            "foo".into()
+        );
+
+        // However active it is, the connection is closed once it has been open for a second.
+        let mut buf = [0u8; 16];
+        let closed = timeout(Duration::from_secs(3), async {
+            loop {
+                if stream.write_all(b"bar\n").await.is_err() {
+                    break;
+                }
+                match timeout(Duration::from_millis(100), stream.read(&mut buf)).await {
+                    Ok(Ok(0)) | Ok(Err(_)) => break,
+                    _ => {}
+                }
+            }
+        })
+        .await;
+        assert!(closed.is_ok(), "connection wasn't closed");
+        assert!(lifetimes_exceeded() - before >= 1.0);
+    }
+
+    #[tokio::test]
+    async fn frame_read_timeout() {
+        // The timeouts and the pauses between writes take no real time.
+        tokio::time::pause();
+        let (tx, mut rx) = Pipeline::new_test();
+        let addr = next_addr();
+        let source = LinesSource
+            .run(
+                addr.into(),
+                None,
+                10,
+                MaybeTlsSettings::Raw(()),
+                None,
+                TcpSourceOptions {
+                    frame_read_timeout_secs: Some(1),
+                    ..Default::default()
+                },
+                ShutdownSignal::noop(),
+                tx,
+            )
+            .unwrap();
+        tokio::spawn(source);
+        wait_for_tcp(addr).await;
+
+        // Unlike the idle timeout, a connection without a partial frame is left open.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        sleep(Duration::from_millis(1500)).await;
+        stream.write_all(b"foo\n").await.unwrap();
+        let event = timeout(Duration::from_secs(1), rx.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            event.as_log()[crate::config::log_schema().message_key()],
+            "foo".into()
+        );
+
+        for byte in b"slowloris" {
+            let _ = stream.write_all(&[*byte]).await;
+            sleep(Duration::from_millis(300)).await;
+        }
+        let mut buf = [0u8; 16];
+        assert!(matches!(
+            timeout(Duration::from_secs(1), stream.read(&mut buf)).await,
+            Ok(Ok(0)) | Ok(Err(_))
+        ));
+        assert!(timeout(Duration::from_millis(100), rx.next())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn pause_accept_on_backpressure() {
+        let _ = crate::metrics::init();
+        let controller = get_controller().unwrap();
+        let accept_paused = || {
+            capture_metrics(controller)
+                .map(Event::into_metric)
+                .filter(|metric| metric.name() == "accept_paused")
+                .map(|metric| match metric.value() {
+                    MetricValue::Gauge { value } => *value,
+                    value => panic!("expected a gauge, got {:?}", value),
+                })
+                .next()
+        };
+
+        tokio::time::pause();
+        let backpressure = Arc::new(Backpressure::default());
+        let (connections, incoming) = mpsc::unbounded();
+        let mut accept = PausableAccept::new(incoming, Some(Arc::clone(&backpressure)), None);
+
+        // Accepting goes on until sending has been blocked for a while.
+        backpressure.block();
+        connections.unbounded_send("first").unwrap();
+        assert_eq!(accept.next().await, Some("first"));
+
+        sleep(BACKPRESSURE_PAUSE_AFTER).await;
+        connections.unbounded_send("second").unwrap();
+        assert!(timeout(Duration::from_secs(5), accept.next())
+            .await
+            .is_err());
+        assert_eq!(accept_paused(), Some(1.0));
+
+        // Once sending is unblocked, the pending connection is accepted at the next check.
+        backpressure.unblock();
+        let second = timeout(BACKPRESSURE_RECHECK_INTERVAL * 2, accept.next())
+            .await
+            .unwrap();
+        assert_eq!(second, Some("second"));
+        assert_eq!(accept_paused(), Some(0.0));
     }
 }
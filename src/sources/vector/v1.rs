@@ -3,16 +3,17 @@ use crate::{
     event::{proto, Event},
     internal_events::{VectorEventReceived, VectorProtoDecodeError},
     sources::{
-        util::{SocketListenAddr, TcpSource},
+        util::{SocketListenAddr, TcpSource, TcpSourceOptions},
         Source,
     },
     tcp::TcpKeepaliveConfig,
     tls::{MaybeTlsSettings, TlsConfig},
 };
-use bytes::{Bytes, BytesMut};
+use bytes::BytesMut;
 use getset::Setters;
 use prost::Message;
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
 use tokio_util::codec::LengthDelimitedCodec;
 
 #[derive(Deserialize, Serialize, Debug, Clone, Setters)]
@@ -62,6 +63,7 @@ impl VectorConfig {
             self.shutdown_timeout_secs,
             tls,
             self.receive_buffer_bytes,
+            TcpSourceOptions::default(),
             cx.shutdown,
             cx.out,
         )
@@ -87,11 +89,11 @@ impl TcpSource for VectorSource {
     type Error = std::io::Error;
     type Decoder = LengthDelimitedCodec;
 
-    fn decoder(&self) -> Self::Decoder {
+    fn decoder(&self, _peer_addr: SocketAddr) -> Self::Decoder {
         LengthDelimitedCodec::new()
     }
 
-    fn build_event(&self, frame: BytesMut, _host: Bytes) -> Option<Event> {
+    fn build_event(&self, frame: BytesMut, _peer_addr: SocketAddr) -> Option<Event> {
         let byte_size = frame.len();
         match proto::EventWrapper::decode(frame).map(Event::from) {
             Ok(event) => {
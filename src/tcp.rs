@@ -24,8 +24,80 @@ pub fn set_receive_buffer_size(socket: &TcpStream, size: usize) -> std::io::Resu
     SockRef::from(socket).set_recv_buffer_size(size)
 }
 
+// This function will be obsolete after tokio/mio internally use `socket2` and expose the methods to
+// apply options to a socket.
+pub fn receive_buffer_size(socket: &TcpStream) -> std::io::Result<usize> {
+    SockRef::from(socket).recv_buffer_size()
+}
+
 // This function will be obsolete after tokio/mio internally use `socket2` and expose the methods to
 // apply options to a socket.
 pub fn set_send_buffer_size(socket: &TcpStream, size: usize) -> std::io::Result<()> {
     SockRef::from(socket).set_send_buffer_size(size)
 }
+
+/// Sets how many bytes have to be buffered on the socket before it's reported readable, so that
+/// small writes are coalesced into fewer reads. `socket2` doesn't support `SO_RCVLOWAT` yet.
+#[cfg(unix)]
+pub fn set_receive_lowat(socket: &TcpStream, bytes: usize) -> std::io::Result<()> {
+    use std::{convert::TryFrom, os::unix::io::AsRawFd};
+
+    let value = libc::c_int::try_from(bytes).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "SO_RCVLOWAT value is too large.",
+        )
+    })?;
+    // Safety: `value` is a valid `c_int` for the duration of the call, and its size is passed
+    // along with it.
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVLOWAT,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+pub fn set_receive_lowat(_socket: &TcpStream, _bytes: usize) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "SO_RCVLOWAT is only supported on Unix.",
+    ))
+}
+
+#[cfg(unix)]
+pub fn receive_lowat(socket: &TcpStream) -> std::io::Result<usize> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // Safety: `value` and `len` are valid for writes for the duration of the call, and `len`
+    // holds the size of `value`.
+    let result = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVLOWAT,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result == 0 {
+        Ok(value as usize)
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+pub fn set_nodelay(socket: &TcpStream, nodelay: bool) -> std::io::Result<()> {
+    SockRef::from(socket).set_nodelay(nodelay)
+}
@@ -0,0 +1,70 @@
+#![cfg(test)]
+
+use std::net::SocketAddr;
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+
+/// A minimal fluent forward client, for sending each mode of message to a running source
+/// without a fluentd or fluent-bit container.
+pub struct FluentClient {
+    stream: TcpStream,
+}
+
+impl FluentClient {
+    pub async fn connect(address: SocketAddr) -> Self {
+        Self {
+            stream: TcpStream::connect(address).await.unwrap(),
+        }
+    }
+
+    pub async fn send_message(&mut self, tag: &str, timestamp: i64, record: rmpv::Value) {
+        self.send(vec![tag.into(), timestamp.into(), record]).await
+    }
+
+    pub async fn send_forward(&mut self, tag: &str, entries: Vec<(i64, rmpv::Value)>) {
+        let entries = entries
+            .into_iter()
+            .map(|(timestamp, record)| rmpv::Value::Array(vec![timestamp.into(), record]))
+            .collect();
+        self.send(vec![tag.into(), rmpv::Value::Array(entries)])
+            .await
+    }
+
+    pub async fn send_packed_forward(
+        &mut self,
+        tag: &str,
+        entries: Vec<(i64, rmpv::Value)>,
+        compressed: bool,
+    ) {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let size = entries.len() as i64;
+        let mut packed = Vec::new();
+        for (timestamp, record) in entries {
+            rmpv::encode::write_value(
+                &mut packed,
+                &rmpv::Value::Array(vec![timestamp.into(), record]),
+            )
+            .unwrap();
+        }
+        let mut options = vec![("size".into(), size.into())];
+        if compressed {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&packed).unwrap();
+            packed = encoder.finish().unwrap();
+            options.push(("compressed".into(), "gzip".into()));
+        }
+        self.send(vec![
+            tag.into(),
+            rmpv::Value::Binary(packed),
+            rmpv::Value::Map(options),
+        ])
+        .await
+    }
+
+    async fn send(&mut self, elements: Vec<rmpv::Value>) {
+        let mut message = Vec::new();
+        rmpv::encode::write_value(&mut message, &rmpv::Value::Array(elements)).unwrap();
+        self.stream.write_all(&message).await.unwrap();
+    }
+}
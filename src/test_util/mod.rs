@@ -39,12 +39,17 @@ use tokio_stream::wrappers::TcpListenerStream;
 #[cfg(unix)]
 use tokio_stream::wrappers::UnixListenerStream;
 use tokio_util::codec::{Encoder, FramedRead, FramedWrite, LinesCodec};
-use vector_core::event::{BatchNotifier, Event, LogEvent};
+use vector_core::event::{
+    metric::{Metric, MetricValue},
+    BatchNotifier, Event, LogEvent,
+};
 
 const WAIT_FOR_SECS: u64 = 5; // The default time to wait in `wait_for`
 const WAIT_FOR_MIN_MILLIS: u64 = 5; // The minimum time to pause before retrying
 const WAIT_FOR_MAX_MILLIS: u64 = 500; // The maximum time to pause before retrying
 
+#[cfg(feature = "sources-fluent")]
+pub mod fluent;
 pub mod stats;
 
 #[macro_export]
@@ -102,6 +107,35 @@ pub fn next_addr_v6() -> SocketAddr {
     next_addr_for_ip(IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1)))
 }
 
+/// The sum of the internal counters named `name`, whatever their tags, as recorded so far.
+pub fn counter(name: &str) -> f64 {
+    sum_counters(name, |_| true)
+}
+
+/// The sum of the internal counters named `name` with the tag `key` set to `value`.
+pub fn tagged_counter(name: &str, key: &str, value: &str) -> f64 {
+    sum_counters(name, |metric| {
+        metric
+            .tags()
+            .and_then(|tags| tags.get(key))
+            .map(String::as_str)
+            == Some(value)
+    })
+}
+
+fn sum_counters(name: &str, filter: impl Fn(&Metric) -> bool) -> f64 {
+    let _ = crate::metrics::init();
+    let controller = crate::metrics::get_controller().unwrap();
+    crate::metrics::capture_metrics(controller)
+        .map(Event::into_metric)
+        .filter(|metric| metric.name() == name && filter(metric))
+        .map(|metric| match metric.value() {
+            MetricValue::Counter { value } => *value,
+            value => panic!("expected a counter, got {:?}", value),
+        })
+        .sum()
+}
+
 pub fn trace_init() {
     #[cfg(unix)]
     let color = atty::is(atty::Stream::Stdout);
@@ -1,14 +1,25 @@
 use super::{
-    CreateAcceptor, Handshake, IncomingListener, MaybeTlsSettings, MaybeTlsStream, SslBuildError,
-    TcpBind, TlsError, TlsSettings,
+    CreateAcceptor, Handshake, IncomingListener, MaybeTlsSettings, MaybeTlsStream,
+    ProxyProtocolHeader, SslBuildError, TcpBind, TlsError, TlsSettings,
 };
+#[cfg(feature = "listenfd")]
+use crate::event::Value;
+use crate::proxy_protocol::read_proxy_header;
 #[cfg(feature = "sources-utils-tcp-socket")]
 use crate::tcp;
 #[cfg(feature = "sources-utils-tcp-keepalive")]
 use crate::tcp::TcpKeepaliveConfig;
 use futures::{future::BoxFuture, stream, FutureExt, Stream};
-use openssl::ssl::{Ssl, SslAcceptor, SslMethod};
+use openssl::ssl::{select_next_proto, AlpnError, Ssl, SslAcceptor, SslMethod};
+#[cfg(feature = "listenfd")]
+use openssl::{
+    nid::Nid,
+    ssl::NameType,
+    x509::{X509Ref, X509VerifyResult},
+};
 use snafu::ResultExt;
+#[cfg(feature = "listenfd")]
+use std::collections::BTreeMap;
 use std::{
     future::Future,
     net::SocketAddr,
@@ -29,6 +40,17 @@ impl TlsSettings {
                 let mut acceptor =
                     SslAcceptor::mozilla_intermediate(SslMethod::tls()).context(CreateAcceptor)?;
                 self.apply_context(&mut acceptor)?;
+                if let Some(protocols) = &self.alpn_protocols {
+                    let protocols = protocols.clone();
+                    let strict = self.alpn_strict;
+                    acceptor.set_alpn_select_callback(move |_, client| {
+                        select_next_proto(&protocols, client).ok_or(if strict {
+                            AlpnError::ALERT_FATAL
+                        } else {
+                            AlpnError::NOACK
+                        })
+                    });
+                }
                 Ok(acceptor.build())
             }
         }
@@ -38,19 +60,116 @@ impl TlsSettings {
 impl MaybeTlsSettings {
     pub(crate) async fn bind(&self, addr: &SocketAddr) -> crate::tls::Result<MaybeTlsListener> {
         let listener = TcpListener::bind(addr).await.context(TcpBind)?;
+        self.listener(listener)
+    }
 
-        let acceptor = match self {
-            Self::Tls(tls) => Some(tls.acceptor()?),
-            Self::Raw(()) => None,
+    /// Like `bind`, but sets `SO_REUSEPORT` on the socket first if `reuse_port` is set, so that
+    /// several listeners can share the address, and binds it to the network device named
+    /// `device` if there is one. Fails on platforms without `SO_REUSEPORT`, or without
+    /// `SO_BINDTODEVICE` when binding to a device.
+    #[cfg(feature = "listenfd")]
+    pub(crate) fn bind_with_options(
+        &self,
+        addr: &SocketAddr,
+        reuse_port: bool,
+        device: Option<&str>,
+        v6only: Option<bool>,
+    ) -> crate::tls::Result<MaybeTlsListener> {
+        let listener = configured_listener(addr, reuse_port, device, v6only).context(TcpBind)?;
+        self.listener(listener)
+    }
+
+    fn listener(&self, listener: TcpListener) -> crate::tls::Result<MaybeTlsListener> {
+        let (acceptor, alpn_strict) = match self {
+            Self::Tls(tls) => (Some(tls.acceptor()?), tls.alpn_strict),
+            Self::Raw(()) => (None, false),
         };
 
-        Ok(MaybeTlsListener { listener, acceptor })
+        Ok(MaybeTlsListener {
+            listener,
+            acceptor,
+            alpn_strict,
+            proxy_protocol: false,
+        })
+    }
+}
+
+#[cfg(all(
+    feature = "listenfd",
+    unix,
+    not(target_os = "solaris"),
+    not(target_os = "illumos")
+))]
+fn configured_listener(
+    addr: &SocketAddr,
+    reuse_port: bool,
+    device: Option<&str>,
+    v6only: Option<bool>,
+) -> io::Result<TcpListener> {
+    use tokio::net::TcpSocket;
+
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    // Same as `TcpListener::bind`.
+    socket.set_reuseaddr(true)?;
+    if reuse_port {
+        socket.set_reuseport(true)?;
+    }
+    if let Some(device) = device {
+        bind_device(&socket, device)?;
+    }
+    // Only IPv6 sockets can accept IPv4 connections as well.
+    if let (SocketAddr::V6(_), Some(v6only)) = (addr, v6only) {
+        socket2::SockRef::from(&socket).set_only_v6(v6only)?;
     }
+    socket.bind(*addr)?;
+    socket.listen(1024)
+}
+
+#[cfg(all(
+    feature = "listenfd",
+    any(target_os = "android", target_os = "fuchsia", target_os = "linux")
+))]
+fn bind_device(socket: &tokio::net::TcpSocket, device: &str) -> io::Result<()> {
+    socket2::SockRef::from(socket).bind_device(Some(device.as_bytes()))
+}
+
+#[cfg(all(
+    feature = "listenfd",
+    unix,
+    not(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))
+))]
+fn bind_device(_socket: &tokio::net::TcpSocket, _device: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "SO_BINDTODEVICE is not supported on this platform.",
+    ))
+}
+
+#[cfg(all(
+    feature = "listenfd",
+    not(all(unix, not(target_os = "solaris"), not(target_os = "illumos")))
+))]
+fn configured_listener(
+    _addr: &SocketAddr,
+    _reuse_port: bool,
+    _device: Option<&str>,
+    _v6only: Option<bool>,
+) -> io::Result<TcpListener> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "SO_REUSEPORT, SO_BINDTODEVICE and IPV6_V6ONLY are not supported on this platform.",
+    ))
 }
 
 pub(crate) struct MaybeTlsListener {
     listener: TcpListener,
     acceptor: Option<SslAcceptor>,
+    // reject TLS connections that don't negotiate an ALPN protocol
+    alpn_strict: bool,
+    proxy_protocol: bool,
 }
 
 impl MaybeTlsListener {
@@ -59,7 +178,13 @@ impl MaybeTlsListener {
             .accept()
             .await
             .map(|(stream, peer_addr)| {
-                MaybeTlsIncomingStream::new(stream, peer_addr, self.acceptor.clone())
+                MaybeTlsIncomingStream::new(
+                    stream,
+                    peer_addr,
+                    self.acceptor.clone(),
+                    self.alpn_strict,
+                    self.proxy_protocol,
+                )
             })
             .context(IncomingListener)
     }
@@ -87,6 +212,14 @@ impl MaybeTlsListener {
     pub(crate) fn local_addr(&self) -> Result<SocketAddr, std::io::Error> {
         self.listener.local_addr()
     }
+
+    /// Expect every accepted connection to start with a PROXY protocol header, which is read
+    /// ahead of the TLS handshake and replaces the peer address of the connection.
+    #[cfg(feature = "listenfd")]
+    pub(crate) fn with_proxy_protocol(mut self, proxy_protocol: bool) -> Self {
+        self.proxy_protocol = proxy_protocol;
+        self
+    }
 }
 
 impl From<TcpListener> for MaybeTlsListener {
@@ -94,6 +227,8 @@ impl From<TcpListener> for MaybeTlsListener {
         Self {
             listener,
             acceptor: None,
+            alpn_strict: false,
+            proxy_protocol: false,
         }
     }
 }
@@ -106,9 +241,12 @@ pub struct MaybeTlsIncomingStream<S> {
     peer_addr: SocketAddr,
 }
 
+/// Accepted stream, along with the client address from the PROXY protocol header if there was one.
+type AcceptResult<S> = Result<(MaybeTlsStream<S>, Option<SocketAddr>), TlsError>;
+
 enum StreamState<S> {
     Accepted(MaybeTlsStream<S>),
-    Accepting(BoxFuture<'static, Result<SslStream<S>, TlsError>>),
+    Accepting(BoxFuture<'static, AcceptResult<S>>),
     AcceptError(String),
     Closed,
 }
@@ -158,35 +296,204 @@ impl<S> MaybeTlsIncomingStream<S> {
             StreamState::Closed => None,
         }
     }
+
+    /// The identity from the certificate the client presented during the TLS handshake.
+    /// None if the connection isn't TLS, hasn't been established yet, or the client
+    /// certificate is missing or wasn't verified.
+    #[cfg(feature = "listenfd")]
+    pub(crate) fn peer_certificate(&self) -> Option<CertificateMetadata> {
+        use super::MaybeTls;
+
+        let ssl = match &self.state {
+            StreamState::Accepted(MaybeTls::Tls(stream)) => stream.ssl(),
+            _ => return None,
+        };
+        if ssl.verify_result() != X509VerifyResult::OK {
+            return None;
+        }
+        ssl.peer_certificate()
+            .map(|certificate| CertificateMetadata::from(&*certificate))
+    }
+
+    /// The server name the client requested through SNI during the TLS handshake.
+    /// None if the connection isn't TLS, hasn't been established yet, or the client
+    /// didn't send one.
+    #[cfg(feature = "listenfd")]
+    pub(crate) fn server_name(&self) -> Option<String> {
+        use super::MaybeTls;
+
+        match &self.state {
+            StreamState::Accepted(MaybeTls::Tls(stream)) => stream
+                .ssl()
+                .servername(NameType::HOST_NAME)
+                .map(String::from),
+            _ => None,
+        }
+    }
+}
+
+/// The subject of a client certificate.
+#[cfg(feature = "listenfd")]
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct CertificateMetadata {
+    pub(crate) common_name: Option<String>,
+    pub(crate) subject_alt_names: Vec<String>,
+}
+
+#[cfg(feature = "listenfd")]
+impl From<&X509Ref> for CertificateMetadata {
+    fn from(certificate: &X509Ref) -> Self {
+        let common_name = certificate
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .and_then(|entry| entry.data().as_utf8().ok())
+            .map(|name| name.to_string());
+        let subject_alt_names = certificate
+            .subject_alt_names()
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| {
+                        name.dnsname()
+                            .or_else(|| name.email())
+                            .or_else(|| name.uri())
+                    })
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            common_name,
+            subject_alt_names,
+        }
+    }
+}
+
+#[cfg(feature = "listenfd")]
+impl From<CertificateMetadata> for Value {
+    fn from(certificate: CertificateMetadata) -> Self {
+        let mut map = BTreeMap::new();
+        map.insert("common_name".to_string(), certificate.common_name.into());
+        map.insert(
+            "subject_alt_names".to_string(),
+            certificate.subject_alt_names.into(),
+        );
+        map.into()
+    }
+}
+
+/// Where a connection was accepted, and what was negotiated for it if it's TLS.
+#[cfg(feature = "listenfd")]
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ConnectionMetadata {
+    pub(crate) local_addr: Option<SocketAddr>,
+    pub(crate) tls_version: Option<String>,
+    pub(crate) cipher: Option<String>,
+}
+
+#[cfg(feature = "listenfd")]
+impl From<ConnectionMetadata> for Value {
+    fn from(connection: ConnectionMetadata) -> Self {
+        let mut map = BTreeMap::new();
+        map.insert(
+            "local_addr".to_string(),
+            connection
+                .local_addr
+                .map(|local_addr| local_addr.to_string())
+                .into(),
+        );
+        if let Some(tls_version) = connection.tls_version {
+            map.insert("tls_version".to_string(), tls_version.into());
+        }
+        if let Some(cipher) = connection.cipher {
+            map.insert("cipher".to_string(), cipher.into());
+        }
+        map.into()
+    }
 }
 
 impl MaybeTlsIncomingStream<TcpStream> {
+    /// The local address of the connection, and for TLS connections the protocol version and
+    /// cipher suite negotiated during the handshake. Empty if the connection hasn't been
+    /// established yet.
+    #[cfg(feature = "listenfd")]
+    pub(crate) fn connection_metadata(&self) -> ConnectionMetadata {
+        use super::MaybeTls;
+
+        let local_addr = self.get_ref().and_then(|stream| stream.local_addr().ok());
+        let (tls_version, cipher) = match &self.state {
+            StreamState::Accepted(MaybeTls::Tls(stream)) => {
+                let ssl = stream.ssl();
+                (
+                    Some(ssl.version_str().to_owned()),
+                    ssl.current_cipher().map(|cipher| cipher.name().to_owned()),
+                )
+            }
+            _ => (None, None),
+        };
+        ConnectionMetadata {
+            local_addr,
+            tls_version,
+            cipher,
+        }
+    }
+
     pub(super) fn new(
         stream: TcpStream,
         peer_addr: SocketAddr,
         acceptor: Option<SslAcceptor>,
+        alpn_strict: bool,
+        proxy_protocol: bool,
     ) -> Self {
-        let state = match acceptor {
-            Some(acceptor) => StreamState::Accepting(
+        let state = match (acceptor, proxy_protocol) {
+            (None, false) => StreamState::Accepted(MaybeTlsStream::Raw(stream)),
+            (acceptor, proxy_protocol) => StreamState::Accepting(
                 async move {
-                    let ssl = Ssl::new(acceptor.context()).context(SslBuildError)?;
-                    let mut stream = SslStream::new(ssl, stream).context(SslBuildError)?;
-                    Pin::new(&mut stream).accept().await.context(Handshake)?;
-                    Ok(stream)
+                    let mut stream = stream;
+                    let proxy_peer_addr = if proxy_protocol {
+                        read_proxy_header(&mut stream)
+                            .await
+                            .context(ProxyProtocolHeader)?
+                    } else {
+                        None
+                    };
+
+                    let stream = match acceptor {
+                        Some(acceptor) => {
+                            let ssl = Ssl::new(acceptor.context()).context(SslBuildError)?;
+                            let mut stream = SslStream::new(ssl, stream).context(SslBuildError)?;
+                            Pin::new(&mut stream).accept().await.context(Handshake)?;
+                            // Clients that send no ALPN protocols at all get this far.
+                            if alpn_strict && stream.ssl().selected_alpn_protocol().is_none() {
+                                return Err(TlsError::AlpnNotNegotiated);
+                            }
+                            MaybeTlsStream::Tls(stream)
+                        }
+                        None => MaybeTlsStream::Raw(stream),
+                    };
+
+                    Ok((stream, proxy_peer_addr))
                 }
                 .boxed(),
             ),
-            None => StreamState::Accepted(MaybeTlsStream::Raw(stream)),
         };
         Self { state, peer_addr }
     }
 
+    fn accepted(&mut self, stream: MaybeTlsStream<TcpStream>, peer_addr: Option<SocketAddr>) {
+        if let Some(peer_addr) = peer_addr {
+            self.peer_addr = peer_addr;
+        }
+        self.state = StreamState::Accepted(stream);
+    }
+
     // Explicit handshake method
     #[cfg(feature = "listenfd")]
     pub(crate) async fn handshake(&mut self) -> crate::tls::Result<()> {
         if let StreamState::Accepting(fut) = &mut self.state {
-            let stream = fut.await?;
-            self.state = StreamState::Accepted(MaybeTlsStream::Tls(stream));
+            let (stream, peer_addr) = fut.await?;
+            self.accepted(stream, peer_addr);
         }
 
         Ok(())
@@ -223,6 +530,54 @@ impl MaybeTlsIncomingStream<TcpStream> {
         tcp::set_receive_buffer_size(stream, bytes)
     }
 
+    #[cfg(feature = "sources-utils-tcp-socket")]
+    pub(crate) fn receive_buffer_bytes(&self) -> std::io::Result<usize> {
+        let stream = self.get_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Can't get receive buffer size on connection that has not been accepted yet.",
+            )
+        })?;
+
+        tcp::receive_buffer_size(stream)
+    }
+
+    #[cfg(feature = "sources-utils-tcp-socket")]
+    pub(crate) fn set_send_buffer_bytes(&mut self, bytes: usize) -> std::io::Result<()> {
+        let stream = self.get_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Can't set send buffer size on connection that has not been accepted yet.",
+            )
+        })?;
+
+        tcp::set_send_buffer_size(stream, bytes)
+    }
+
+    #[cfg(feature = "sources-utils-tcp-socket")]
+    pub(crate) fn set_receive_lowat_bytes(&mut self, bytes: usize) -> std::io::Result<()> {
+        let stream = self.get_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Can't set SO_RCVLOWAT on connection that has not been accepted yet.",
+            )
+        })?;
+
+        tcp::set_receive_lowat(stream, bytes)
+    }
+
+    #[cfg(feature = "sources-utils-tcp-socket")]
+    pub(crate) fn set_nodelay(&mut self, nodelay: bool) -> std::io::Result<()> {
+        let stream = self.get_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Can't set TCP_NODELAY on connection that has not been accepted yet.",
+            )
+        })?;
+
+        tcp::set_nodelay(stream, nodelay)
+    }
+
     fn poll_io<T, F>(self: Pin<&mut Self>, cx: &mut Context, poll_fn: F) -> Poll<io::Result<T>>
     where
         F: FnOnce(Pin<&mut MaybeTlsStream<TcpStream>>, &mut Context) -> Poll<io::Result<T>>,
@@ -232,8 +587,8 @@ impl MaybeTlsIncomingStream<TcpStream> {
             return match &mut this.state {
                 StreamState::Accepted(stream) => poll_fn(Pin::new(stream), cx),
                 StreamState::Accepting(fut) => match futures::ready!(fut.as_mut().poll(cx)) {
-                    Ok(stream) => {
-                        this.state = StreamState::Accepted(MaybeTlsStream::Tls(stream));
+                    Ok((stream, peer_addr)) => {
+                        this.accepted(stream, peer_addr);
                         continue;
                     }
                     Err(error) => {
@@ -281,8 +636,8 @@ impl AsyncWrite for MaybeTlsIncomingStream<TcpStream> {
                 poll_result => poll_result,
             },
             StreamState::Accepting(fut) => match futures::ready!(fut.as_mut().poll(cx)) {
-                Ok(stream) => {
-                    this.state = StreamState::Accepted(MaybeTlsStream::Tls(stream));
+                Ok((stream, peer_addr)) => {
+                    this.accepted(stream, peer_addr);
                     Poll::Pending
                 }
                 Err(error) => {
@@ -14,6 +14,8 @@ mod maybe_tls;
 mod outgoing;
 mod settings;
 
+#[cfg(feature = "sources-utils-tls")]
+pub(crate) use incoming::CertificateMetadata;
 #[cfg(all(feature = "sources-utils-tls", feature = "listenfd"))]
 pub(crate) use incoming::{MaybeTlsIncomingStream, MaybeTlsListener};
 pub(crate) use maybe_tls::MaybeTls;
@@ -77,6 +79,14 @@ pub enum TlsError {
     MissingRequiredIdentity,
     #[snafu(display("TLS handshake failed: {}", source))]
     Handshake { source: openssl::ssl::Error },
+    #[snafu(display("ALPN protocol {:?} must be between 1 and 255 bytes long", protocol))]
+    InvalidAlpnProtocol { protocol: String },
+    #[snafu(display("Error setting up the ALPN protocols: {}", source))]
+    SetAlpnProtocols { source: ErrorStack },
+    #[snafu(display("Client did not negotiate any of the ALPN protocols"))]
+    AlpnNotNegotiated,
+    #[snafu(display("Invalid PROXY protocol header: {}", source))]
+    ProxyProtocolHeader { source: std::io::Error },
     #[snafu(display("Incoming listener failed: {}", source))]
     IncomingListener { source: tokio::io::Error },
     #[snafu(display("Creating the TLS acceptor failed: {}", source))]
@@ -166,6 +176,11 @@ pub(crate) fn tls_connector_builder(settings: &MaybeTlsSettings) -> Result<SslCo
     let mut builder = SslConnector::builder(SslMethod::tls()).context(TlsBuildConnector)?;
     if let Some(settings) = settings.tls() {
         settings.apply_context(&mut builder)?;
+        if let Some(protocols) = &settings.alpn_protocols {
+            builder
+                .set_alpn_protos(protocols)
+                .context(SetAlpnProtocols)?;
+        }
     }
     Ok(builder)
 }
@@ -60,6 +60,8 @@ pub struct TlsOptions {
     #[serde(alias = "key_path")]
     pub key_file: Option<PathBuf>,
     pub key_pass: Option<String>,
+    pub alpn_protocols: Option<Vec<String>>,
+    pub alpn_strict: Option<bool>,
 }
 
 impl TlsOptions {
@@ -81,6 +83,9 @@ pub struct TlsSettings {
     pub(super) verify_hostname: bool,
     authorities: Vec<X509>,
     pub(super) identity: Option<IdentityStore>, // openssl::pkcs12::ParsedPkcs12 doesn't impl Clone yet
+    // the ALPN protocols in wire format, each prefixed with its length
+    pub(super) alpn_protocols: Option<Vec<u8>>,
+    pub(super) alpn_strict: bool,
 }
 
 #[derive(Clone)]
@@ -117,6 +122,8 @@ impl TlsSettings {
             verify_hostname: options.verify_hostname.unwrap_or(!for_server),
             authorities: options.load_authorities()?,
             identity: options.load_identity()?,
+            alpn_protocols: options.alpn_wire_format()?,
+            alpn_strict: options.alpn_strict.unwrap_or(false),
         })
     }
 
@@ -181,6 +188,29 @@ impl TlsSettings {
 }
 
 impl TlsOptions {
+    /// The ALPN protocols in the wire format OpenSSL expects, where each protocol is prefixed
+    /// with its length in a single byte.
+    fn alpn_wire_format(&self) -> Result<Option<Vec<u8>>> {
+        self.alpn_protocols
+            .as_ref()
+            .map(|protocols| {
+                let mut wire = Vec::new();
+                for protocol in protocols {
+                    match protocol.len() {
+                        1..=255 => wire.push(protocol.len() as u8),
+                        _ => {
+                            return Err(TlsError::InvalidAlpnProtocol {
+                                protocol: protocol.clone(),
+                            })
+                        }
+                    }
+                    wire.extend_from_slice(protocol.as_bytes());
+                }
+                Ok(wire)
+            })
+            .transpose()
+    }
+
     fn load_authorities(&self) -> Result<Vec<X509>> {
         match &self.ca_file {
             None => Ok(vec![]),
@@ -347,6 +377,7 @@ impl fmt::Debug for TlsSettings {
         f.debug_struct("TlsSettings")
             .field("verify_certificate", &self.verify_certificate)
             .field("verify_hostname", &self.verify_hostname)
+            .field("alpn_strict", &self.alpn_strict)
             .finish()
     }
 }
@@ -600,6 +631,93 @@ mod test {
         assert!(config.is_tls());
     }
 
+    #[test]
+    fn from_options_invalid_alpn_protocol() {
+        let options = TlsOptions {
+            alpn_protocols: Some(vec!["fluent".into(), "".into()]),
+            ..Default::default()
+        };
+        let error = TlsSettings::from_options(&Some(options))
+            .expect_err("from_options failed to check the ALPN protocols");
+        assert!(matches!(error, TlsError::InvalidAlpnProtocol { .. }));
+    }
+
+    #[cfg(feature = "listenfd")]
+    #[tokio::test]
+    async fn alpn_negotiation() {
+        use crate::test_util::next_addr;
+        use tokio::io::AsyncWriteExt;
+
+        fn server(alpn_strict: bool) -> MaybeTlsSettings {
+            let config = TlsConfig {
+                enabled: Some(true),
+                options: TlsOptions {
+                    crt_file: Some(TEST_PEM_CRT_PATH.into()),
+                    key_file: Some(TEST_PEM_KEY_PATH.into()),
+                    alpn_protocols: Some(vec!["h2".into(), "fluent".into()]),
+                    alpn_strict: Some(alpn_strict),
+                    ..Default::default()
+                },
+            };
+            MaybeTlsSettings::from_config(&Some(config), true).unwrap()
+        }
+
+        fn client(alpn_protocols: Option<Vec<String>>) -> MaybeTlsSettings {
+            let config = TlsConfig {
+                enabled: Some(true),
+                options: TlsOptions {
+                    ca_file: Some(TEST_PEM_CA_PATH.into()),
+                    alpn_protocols,
+                    ..Default::default()
+                },
+            };
+            MaybeTlsSettings::from_config(&Some(config), false).unwrap()
+        }
+
+        /// The protocol the client negotiated, and whether the server accepted the connection.
+        async fn connect(
+            server: MaybeTlsSettings,
+            client: MaybeTlsSettings,
+        ) -> (Option<Vec<u8>>, bool) {
+            let addr = next_addr();
+            let mut listener = server.bind(&addr).await.unwrap();
+            let accepted = tokio::spawn(async move {
+                let mut stream = listener.accept().await.unwrap();
+                stream.handshake().await.is_ok()
+            });
+
+            let protocol = match client.connect("localhost", &addr).await {
+                Ok(MaybeTls::Tls(mut stream)) => {
+                    let protocol = stream.ssl().selected_alpn_protocol().map(Vec::from);
+                    let _ = stream.shutdown().await;
+                    protocol
+                }
+                _ => None,
+            };
+            (protocol, accepted.await.unwrap())
+        }
+
+        // the server's order of preference wins
+        let negotiated = connect(
+            server(true),
+            client(Some(vec!["fluent".into(), "h2".into()])),
+        )
+        .await;
+        assert_eq!(negotiated, (Some(b"h2".to_vec()), true));
+
+        let negotiated = connect(server(true), client(Some(vec!["fluent".into()]))).await;
+        assert_eq!(negotiated, (Some(b"fluent".to_vec()), true));
+
+        // without a protocol in common, only strict mode rejects the connection
+        let mismatch = || client(Some(vec!["http/1.1".into()]));
+        assert_eq!(connect(server(false), mismatch()).await, (None, true));
+        assert_eq!(connect(server(true), mismatch()).await, (None, false));
+
+        // the same goes for clients that don't use ALPN at all
+        assert_eq!(connect(server(false), client(None)).await, (None, true));
+        assert!(!connect(server(true), client(None)).await.1);
+    }
+
     fn settings_from_config(
         enabled: Option<bool>,
         set_crt: bool,